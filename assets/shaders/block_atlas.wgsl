@@ -0,0 +1,43 @@
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
+    forward_io::{VertexOutput, FragmentOutput},
+}
+
+struct BlockAtlas {
+    // xy: size of a tile in UV units, z: half a texel in tile units
+    tile: vec4<f32>,
+}
+
+@group(2) @binding(100)
+var<uniform> block_atlas: BlockAtlas;
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var atlas_in = in;
+
+#ifdef VERTEX_UVS_B
+    // uv repeats once per block along the quad, uv_b is the corner of the tile in the atlas.
+    // Sampling stays half a texel inside the tile so that it never bleeds into its neighbours.
+    let inset = block_atlas.tile.z;
+    atlas_in.uv = in.uv_b + clamp(fract(in.uv), vec2(inset), vec2(1.0 - inset)) * block_atlas.tile.xy;
+#endif
+
+    var pbr_input = pbr_input_from_standard_material(atlas_in, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    // Emissive blocks ignore the lights of the scene
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+
+    return out;
+}
@@ -1,12 +1,10 @@
-struct Uniforms {
-    view_proj: mat4x4<f32>, // View-projection matrix
-    model: mat4x4<f32>,     // Model matrix
+#import bevy_pbr::{
+    mesh_functions::{get_world_from_local, mesh_position_local_to_world},
+    view_transformations::position_world_to_clip,
 }
 
-@group(0) @binding(0)
-var<uniform> uniforms: Uniforms;
-
 struct VertexInput {
+    @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>
 }
 
@@ -17,8 +15,9 @@ struct VertexOutput {
 @vertex
 fn vertex(input: VertexInput) -> VertexOutput {
     var output: VertexOutput;
-    let world_position = uniforms.model * vec4<f32>(input.position, 1.0);
-    output.position = uniforms.view_proj * world_position;
+    let world_from_local = get_world_from_local(input.instance_index);
+    let world_position = mesh_position_local_to_world(world_from_local, vec4<f32>(input.position, 1.0));
+    output.position = position_world_to_clip(world_position.xyz);
 
     return output;
 }
@@ -26,4 +25,4 @@ fn vertex(input: VertexInput) -> VertexOutput {
 @fragment
 fn fragment() -> @location(0) vec4<f32> {
     return vec4<f32>(0.14509803, 0.5882353, 0.74509803, 1.0); // Blue color
-}
\ No newline at end of file
+}
@@ -1,59 +1,127 @@
+use crate::chunk::registry::{AtlasLayout, RenderClass};
+use crate::chunk::state::BlockState;
 use bevy::asset::Handle;
-use bevy::pbr::StandardMaterial;
-use bevy::platform::collections::HashMap;
+use bevy::pbr::{ExtendedMaterial, MaterialExtension, StandardMaterial};
 use bevy::prelude::*;
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, Debug)]
-pub enum VoxelBlockType {
-    #[default]
-    Empty,
-    Rock,
-    Grass,
-    Gem,
-    Dirt,
+/// Numeric id of a block type, resolved through the `BlockRegistry`
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, Debug, Deserialize)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    pub const EMPTY: BlockId = BlockId(0);
 }
 
-#[derive(Default, Copy, Clone, Debug)]
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub struct VoxelBlock {
-    pub block_type: VoxelBlockType,
+    pub id: BlockId,
+    pub state: BlockState,
 }
 
-pub type BlockMaterialHashMap = HashMap<VoxelBlockType, Handle<StandardMaterial>>;
+impl VoxelBlock {
+    pub fn is_empty(&self) -> bool {
+        self.id == BlockId::EMPTY
+    }
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct BlockMaterialMap(BlockMaterialHashMap);
+    /// Id and state packed together, the id in the low 16 bits
+    pub fn to_raw(self) -> u32 {
+        self.id.0 as u32 | (self.state.0 as u32) << 16
+    }
 
-#[derive(Resource, Deref, DerefMut, Clone)]
-pub struct BlockMaterial(pub Handle<StandardMaterial>);
+    pub fn from_raw(raw: u32) -> Self {
+        Self {
+            id: BlockId(raw as u16),
+            state: BlockState((raw >> 16) as u16),
+        }
+    }
+}
 
-impl FromWorld for BlockMaterial {
-    fn from_world(world: &mut World) -> Self {
-        let asset_server = world.resource_mut::<AssetServer>();
-        let handle_image = asset_server.load("atlas.png");
+impl Serialize for VoxelBlock {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.to_raw())
+    }
+}
 
-        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+impl<'de> Deserialize<'de> for VoxelBlock {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Self::from_raw(u32::deserialize(deserializer)?))
+    }
+}
 
-        let handle_material = materials.add(handle_image);
+/// Material of the chunk meshes. The extension turns the per-block UVs of the
+/// mesh into atlas UVs so that merged quads repeat their tile.
+pub type VoxelMaterial = ExtendedMaterial<StandardMaterial, BlockAtlasExtension>;
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct BlockAtlasExtension {
+    /// xy: size of a tile in UV units, z: half texel inset in tile units
+    #[uniform(100)]
+    pub tile: Vec4,
+}
 
-        Self(handle_material)
+impl From<&AtlasLayout> for BlockAtlasExtension {
+    fn from(atlas: &AtlasLayout) -> Self {
+        let [width, height] = atlas.tile_uv_size();
+
+        Self {
+            tile: Vec4::new(width, height, atlas.half_texel_inset(), 0.0),
+        }
     }
 }
 
-impl FromWorld for BlockMaterialMap {
-    fn from_world(world: &mut World) -> Self {
-        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+impl MaterialExtension for BlockAtlasExtension {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/block_atlas.wgsl".into()
+    }
+}
 
-        let mut material_map: BlockMaterialHashMap = HashMap::new();
+/// Material of each `RenderClass`. They share the atlas and only differ by
+/// their alpha mode, and by the scene lighting for the emissive one.
+#[derive(Resource, Clone)]
+pub struct BlockMaterial {
+    materials: [Handle<VoxelMaterial>; RenderClass::ALL.len()],
+}
+
+impl BlockMaterial {
+    pub fn get(&self, class: RenderClass) -> &Handle<VoxelMaterial> {
+        &self.materials[class as usize]
+    }
+}
+
+fn alpha_mode(class: RenderClass) -> AlphaMode {
+    match class {
+        RenderClass::Opaque | RenderClass::Emissive => AlphaMode::Opaque,
+        RenderClass::Cutout => AlphaMode::Mask(0.5),
+        RenderClass::Transparent | RenderClass::Fluid => AlphaMode::Blend,
+    }
+}
+
+impl FromWorld for BlockMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource_mut::<AssetServer>();
+        let handle_image: Handle<Image> = asset_server.load("atlas.png");
 
-        material_map.insert(
-            VoxelBlockType::Rock,
-            materials.add(Color::srgba(79.0 / 255.0, 87.0 / 255.0, 99.0 / 255.0, 1.0)),
-        );
-        material_map.insert(
-            VoxelBlockType::Grass,
-            materials.add(Color::srgba(76.0 / 255.0, 153.0 / 255.0, 0.0 / 255.0, 1.0)),
-        );
+        let mut materials = world.resource_mut::<Assets<VoxelMaterial>>();
 
-        Self(material_map)
+        Self {
+            materials: RenderClass::ALL.map(|class| {
+                materials.add(VoxelMaterial {
+                    base: StandardMaterial {
+                        alpha_mode: alpha_mode(class),
+                        unlit: class == RenderClass::Emissive,
+                        ..StandardMaterial::from(handle_image.clone())
+                    },
+                    extension: BlockAtlasExtension::from(&AtlasLayout::default()),
+                })
+            }),
+        }
     }
 }
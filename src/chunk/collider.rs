@@ -0,0 +1,256 @@
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::voxel_chunk::VoxelChunk;
+use crate::game_world::coord::{ChunkCoord, LocalVoxelBlockOffset};
+use crate::settings::{
+    CoordSystemIntegerSize, GameSettingResource, CHUNK_HEIGHT, CHUNK_SIZE, MAX_OFFSET,
+    SECTION_COUNT, SECTION_VOLUME,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::{Collider, RigidBody};
+
+/// Marks the chunks whose collider is built. Chunks without any solid block
+/// have the marker but no `Collider`.
+#[derive(Component, Debug, Default)]
+pub struct ChunkCollider;
+
+/// Box made of solid blocks, in block coordinates of the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolidBox {
+    pub min: [CoordSystemIntegerSize; 3],
+    /// Number of blocks along each axis
+    pub size: [CoordSystemIntegerSize; 3],
+}
+
+/// Covers the solid blocks of a chunk with as few boxes as possible. Each
+/// box grows along x, then z, then y over the blocks no other box covers.
+/// Terrain ends up as a handful of large slabs instead of the thousands of
+/// triangles of its render mesh.
+pub fn solid_boxes(chunk: &VoxelChunk, registry: &BlockRegistry) -> Vec<SolidBox> {
+    let index = |position: [CoordSystemIntegerSize; 3]| {
+        (position[0] + position[2] * CHUNK_SIZE + position[1] * CHUNK_SIZE * CHUNK_SIZE) as usize
+    };
+
+    let mut remaining = vec![false; MAX_OFFSET as usize];
+
+    for section in 0..SECTION_COUNT {
+        let storage = chunk.section(section);
+
+        // Skips the sections made of air or water
+        if !storage
+            .palette()
+            .iter()
+            .any(|block| registry.is_solid(block.id))
+        {
+            continue;
+        }
+
+        let first = section * SECTION_VOLUME as usize;
+
+        for (offset, solid) in remaining
+            .iter_mut()
+            .enumerate()
+            .skip(first)
+            .take(SECTION_VOLUME as usize)
+        {
+            *solid = chunk
+                .get_block(&LocalVoxelBlockOffset(offset))
+                .is_some_and(|block| registry.is_solid(block.id));
+        }
+    }
+
+    let mut boxes = vec![];
+
+    for y in 0..CHUNK_HEIGHT {
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                if !remaining[index([x, y, z])] {
+                    continue;
+                }
+
+                let mut size = [1, 1, 1];
+
+                while x + size[0] < CHUNK_SIZE && remaining[index([x + size[0], y, z])] {
+                    size[0] += 1;
+                }
+
+                while z + size[2] < CHUNK_SIZE
+                    && (x..x + size[0]).all(|i| remaining[index([i, y, z + size[2]])])
+                {
+                    size[2] += 1;
+                }
+
+                while y + size[1] < CHUNK_HEIGHT
+                    && (x..x + size[0])
+                        .all(|i| (z..z + size[2]).all(|k| remaining[index([i, y + size[1], k])]))
+                {
+                    size[1] += 1;
+                }
+
+                for j in y..y + size[1] {
+                    for k in z..z + size[2] {
+                        for i in x..x + size[0] {
+                            remaining[index([i, j, k])] = false;
+                        }
+                    }
+                }
+
+                boxes.push(SolidBox {
+                    min: [x, y, z],
+                    size,
+                });
+            }
+        }
+    }
+
+    boxes
+}
+
+/// Compound of one cuboid per box, in chunk space. Blocks are centered on
+/// their coordinates, like in the chunk meshes.
+pub fn chunk_collider(boxes: &[SolidBox]) -> Option<Collider> {
+    if boxes.is_empty() {
+        return None;
+    }
+
+    Some(Collider::compound(
+        boxes
+            .iter()
+            .map(|solid_box| {
+                let half_size = Vec3::from_array(solid_box.size.map(|s| s as f32 / 2.0));
+                let min = Vec3::from_array(solid_box.min.map(|m| m as f32 - 0.5));
+
+                (
+                    min + half_size,
+                    Quat::IDENTITY,
+                    Collider::cuboid(half_size.x, half_size.y, half_size.z),
+                )
+            })
+            .collect(),
+    ))
+}
+
+/// Chunks only get a collider while a body that is not fixed, like the
+/// player, is at most `collider_distance` chunks away. The collider is built
+/// again when a block of the chunk changes, and removed once every body is
+/// gone.
+pub fn update_chunk_colliders(
+    mut commands: Commands,
+    bodies: Query<(&GlobalTransform, &RigidBody)>,
+    mut chunks: Query<(Entity, &ChunkCoord, &mut VoxelChunk, Has<ChunkCollider>)>,
+    block_registry: Res<BlockRegistry>,
+    game_setting_resource: Res<GameSettingResource>,
+) {
+    let distance = game_setting_resource.settings.physics.collider_distance;
+
+    let body_coords: Vec<ChunkCoord> = bodies
+        .iter()
+        .filter(|(_, body)| **body != RigidBody::Fixed)
+        .map(|(transform, _)| {
+            ChunkCoord::from(Transform::from_translation(transform.translation()))
+        })
+        .collect();
+
+    let is_near = |coord: &ChunkCoord| {
+        body_coords
+            .iter()
+            .any(|body| (0..3).all(|axis| (coord[axis] - body[axis]).abs() <= distance))
+    };
+
+    for (entity, coord, mut chunk, has_collider) in chunks.iter_mut() {
+        let rebuild = match (is_near(coord), has_collider) {
+            (true, false) => true,
+            (true, true) => chunk.take_collider_dirty(),
+            (false, true) => {
+                commands
+                    .entity(entity)
+                    .remove::<(ChunkCollider, RigidBody, Collider)>();
+
+                false
+            }
+            (false, false) => false,
+        };
+
+        if !rebuild {
+            continue;
+        }
+
+        chunk.take_collider_dirty();
+
+        match chunk_collider(&solid_boxes(&chunk, &block_registry)) {
+            Some(collider) => {
+                commands
+                    .entity(entity)
+                    .insert((ChunkCollider, RigidBody::Fixed, collider));
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(ChunkCollider)
+                    .remove::<(RigidBody, Collider)>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::{chunk_with, game_block_registry};
+
+    fn volume(boxes: &[SolidBox]) -> CoordSystemIntegerSize {
+        boxes.iter().map(|b| b.size.iter().product::<i32>()).sum()
+    }
+
+    #[test]
+    fn ground_is_a_single_box() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|_, y, _| (y < CHUNK_HEIGHT / 2).then_some("rock"));
+
+        assert_eq!(
+            solid_boxes(&chunk, &registry),
+            vec![SolidBox {
+                min: [0, 0, 0],
+                size: [CHUNK_SIZE, CHUNK_HEIGHT / 2, CHUNK_SIZE],
+            }]
+        );
+    }
+
+    #[test]
+    fn boxes_cover_every_solid_block_once() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|x, y, z| {
+            let height = (x * 3 + z * 5) % 7 + 2;
+
+            match y {
+                _ if y > height + 1 => None,
+                _ if y == height + 1 => Some("water"),
+                _ if y == height => Some("grass"),
+                _ => Some("rock"),
+            }
+        });
+
+        let boxes = solid_boxes(&chunk, &registry);
+        let solid_count = (0..MAX_OFFSET as usize)
+            .filter(|offset| {
+                chunk
+                    .get_block(&LocalVoxelBlockOffset(*offset))
+                    .is_some_and(|block| registry.is_solid(block.id))
+            })
+            .count();
+
+        assert_eq!(volume(&boxes) as usize, solid_count);
+        assert!(boxes.len() < solid_count / 4);
+
+        let dimensions = [CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE];
+        for solid_box in &boxes {
+            assert!(
+                (0..3).all(|axis| solid_box.min[axis] + solid_box.size[axis] <= dimensions[axis])
+            );
+        }
+
+        // Water is not solid
+        let pond = chunk_with(|_, y, _| (y == 0).then_some("water"));
+        assert!(solid_boxes(&pond, &registry).is_empty());
+        assert!(chunk_collider(&[]).is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::chunk::block::{BlockMaterial, VoxelBlock};
+use crate::chunk::mesher::block_mesh;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::voxel_chunk::BlockChangedEvent;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::game_world::voxel_world::SetBlockError;
+use crate::game_world::VoxelWorld;
+use crate::settings::CoordSystemIntegerSize;
+use crate::utils::render_mesh;
+use bevy::prelude::*;
+
+/// Speed a falling block gains every second, in blocks per second
+const FALL_ACCELERATION: f32 = 20.0;
+const MAX_FALL_SPEED: f32 = 40.0;
+
+/// Position of a block in world space
+type Position = [CoordSystemIntegerSize; 3];
+
+/// A block that left its chunk to fall, until it lands on another block and
+/// takes its place in the chunks again
+#[derive(Component, Debug)]
+pub struct FallingBlock {
+    block: VoxelBlock,
+    /// Blocks per second, downwards
+    speed: f32,
+}
+
+/// Falling blocks go through empty blocks and fluids. Chunks that are not
+/// loaded stop them.
+fn falls_through(registry: &BlockRegistry, block: Option<VoxelBlock>) -> bool {
+    block.is_some_and(|block| block.is_empty() || registry.fluid_flow(block.id).is_some())
+}
+
+/// Height of the block where a block moving down from `from` to `to` (the
+/// heights of its center) lands: the first one on the way whose block below
+/// stops it. `None` while it keeps falling.
+pub fn landing_height<F>(
+    registry: &BlockRegistry,
+    column: [CoordSystemIntegerSize; 2],
+    from: f32,
+    to: f32,
+    block_at: F,
+) -> Option<CoordSystemIntegerSize>
+where
+    F: Fn(Position) -> Option<VoxelBlock>,
+{
+    let top = from.ceil() as CoordSystemIntegerSize;
+    let bottom = to.ceil() as CoordSystemIntegerSize;
+
+    (bottom..=top)
+        .rev()
+        .find(|y| !falls_through(registry, block_at([column[0], y - 1, column[1]])))
+}
+
+/// Falling blocks land in empty blocks, or in fluids flowing from a source
+/// nearby. Anything else in their way was put there while they fell.
+fn can_land_in(registry: &BlockRegistry, block: VoxelBlock) -> bool {
+    block.is_empty() || (registry.fluid_flow(block.id).is_some() && !block.state.is_fluid_source())
+}
+
+/// Height of the first block from `y` up that a falling block landing at `y`
+/// can take the place of. `None` when a chunk that is not loaded is on the
+/// way.
+pub fn free_height<F>(
+    registry: &BlockRegistry,
+    column: [CoordSystemIntegerSize; 2],
+    y: CoordSystemIntegerSize,
+    block_at: F,
+) -> Option<CoordSystemIntegerSize>
+where
+    F: Fn(Position) -> Option<VoxelBlock>,
+{
+    for y in y.. {
+        if can_land_in(registry, block_at([column[0], y, column[1]])?) {
+            return Some(y);
+        }
+    }
+
+    None
+}
+
+/// Turns the blocks that lost their support into falling blocks. A replaced
+/// block may start to fall itself, or let the block above it fall. The blocks
+/// they leave behind are replaced in turn, so a whole pile falls one block
+/// after the other, whatever chunks it spans.
+pub fn start_falling_blocks(
+    mut commands: Commands,
+    mut ev_block_changed: EventReader<BlockChangedEvent>,
+    mut voxel_world: VoxelWorld,
+    mut meshes: ResMut<Assets<Mesh>>,
+    block_registry: Res<BlockRegistry>,
+    block_material: Res<BlockMaterial>,
+) {
+    let changed: Vec<Position> = ev_block_changed
+        .read()
+        .map(|event| [event.coord.x, event.coord.y, event.coord.z])
+        .collect();
+
+    for [x, y, z] in changed {
+        for position in [[x, y, z], [x, y + 1, z]] {
+            let block_at = |position: Position| {
+                voxel_world
+                    .get_block(GlobalVoxelBlockCoord::from(position))
+                    .ok()
+            };
+
+            let Some(block) = block_at(position) else {
+                continue;
+            };
+
+            let below = [position[0], position[1] - 1, position[2]];
+
+            if !block_registry.falls(block.id) || !falls_through(&block_registry, block_at(below)) {
+                continue;
+            }
+
+            // The block was just read, so its chunk is loaded
+            if voxel_world
+                .set_block(GlobalVoxelBlockCoord::from(position), VoxelBlock::default())
+                .is_err()
+            {
+                continue;
+            }
+
+            let (indices, vertices) = block_mesh(&block_registry, block);
+
+            commands.spawn((
+                FallingBlock { block, speed: 0.0 },
+                Mesh3d(meshes.add(render_mesh(&indices, &vertices))),
+                MeshMaterial3d(
+                    block_material
+                        .get(block_registry.render_class(block.id))
+                        .clone(),
+                ),
+                Transform::from_xyz(position[0] as f32, position[1] as f32, position[2] as f32),
+            ));
+        }
+    }
+}
+
+/// Moves the falling blocks down, and puts them back in the chunks once
+/// they land
+pub fn update_falling_blocks(
+    mut commands: Commands,
+    mut falling_blocks: Query<(Entity, &mut FallingBlock, &mut Transform)>,
+    mut voxel_world: VoxelWorld,
+    block_registry: Res<BlockRegistry>,
+    time: Res<Time>,
+) {
+    for (entity, mut falling_block, mut transform) in falling_blocks.iter_mut() {
+        falling_block.speed =
+            (falling_block.speed + FALL_ACCELERATION * time.delta_secs()).min(MAX_FALL_SPEED);
+
+        let from = transform.translation.y;
+        let to = from - falling_block.speed * time.delta_secs();
+        let column = [transform.translation.x, transform.translation.z]
+            .map(|v| v.round() as CoordSystemIntegerSize);
+
+        let block_at = |position: Position| {
+            voxel_world
+                .get_block(GlobalVoxelBlockCoord::from(position))
+                .ok()
+        };
+
+        let Some(landing) = landing_height(&block_registry, column, from, to, block_at) else {
+            transform.translation.y = to;
+            continue;
+        };
+
+        // A block whose chunk was unloaded waits for it to come back
+        let Some(y) = free_height(&block_registry, column, landing, block_at) else {
+            continue;
+        };
+
+        match voxel_world.set_block(
+            GlobalVoxelBlockCoord::new(column[0], y, column[1]),
+            falling_block.block,
+        ) {
+            Err(SetBlockError::NotLoaded(_)) => {}
+            // Pushed up out of the world, the block is lost
+            Ok(_) | Err(SetBlockError::OutOfWorld(_)) => {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::game_block_registry;
+
+    #[test]
+    fn falling_blocks_land_on_the_first_block_below() {
+        let registry = game_block_registry();
+        let rock = registry.block(registry.id("rock").unwrap());
+        let water = registry.block(registry.id("water").unwrap());
+
+        // Rock up to 2, then water up to 4, then air
+        let block_at = |position: Position| match position[1] {
+            ..=2 => Some(rock),
+            3..=4 => Some(water),
+            _ => Some(VoxelBlock::default()),
+        };
+
+        // Still above the water
+        assert_eq!(landing_height(&registry, [0, 0], 9.0, 8.2, block_at), None);
+        // Through the water in a single step
+        assert_eq!(
+            landing_height(&registry, [0, 0], 6.0, 1.5, block_at),
+            Some(3)
+        );
+        assert_eq!(
+            landing_height(&registry, [0, 0], 3.2, 2.9, block_at),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn falling_blocks_are_pushed_up_by_what_took_their_place() {
+        let registry = game_block_registry();
+        let rock = registry.block(registry.id("rock").unwrap());
+        let water = registry.block(registry.id("water").unwrap());
+        let flowing = VoxelBlock {
+            state: water.state.with_fluid_level(3),
+            ..water
+        };
+
+        // A rock put at 3 while the block fell, a water source at 4, flowing
+        // water at 5
+        let block_at = |position: Position| match position[1] {
+            ..=3 => Some(rock),
+            4 => Some(water),
+            5 => Some(flowing),
+            _ => Some(VoxelBlock::default()),
+        };
+
+        assert_eq!(free_height(&registry, [0, 0], 3, block_at), Some(5));
+        assert_eq!(free_height(&registry, [0, 0], 6, block_at), Some(6));
+
+        let unloaded = |position: Position| (position[1] <= 3).then_some(rock);
+        assert_eq!(free_height(&registry, [0, 0], 3, unloaded), None);
+    }
+
+    #[test]
+    fn chunks_that_are_not_loaded_stop_falling_blocks() {
+        let registry = game_block_registry();
+        let block_at = |position: Position| (position[1] >= 0).then(VoxelBlock::default);
+
+        assert_eq!(
+            landing_height(&registry, [5, -3], 4.0, -2.0, block_at),
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::voxel_chunk::BlockChangedEvent;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::game_world::VoxelWorld;
+use crate::settings::CoordSystemIntegerSize;
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+
+/// World ticks between two steps of the fluids
+pub const FLOW_TICKS: u64 = 5;
+
+/// Blocks looked at in a single step, the others wait for the next ones
+const MAX_UPDATES_PER_STEP: usize = 4096;
+
+/// Position of a block in world space
+type Position = [CoordSystemIntegerSize; 3];
+
+const NEIGHBORS: [Position; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+const HORIZONTAL: [Position; 4] = [[1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1]];
+
+fn offset(position: Position, direction: Position) -> Position {
+    [0, 1, 2].map(|axis| position[axis] + direction[axis])
+}
+
+/// Blocks that may change at the next step of the fluids: the ones next to a
+/// block that was replaced
+#[derive(Resource, Debug, Default)]
+pub struct FluidUpdates {
+    pending: HashSet<Position>,
+}
+
+/// State a block takes at the next step of the fluids, `None` when it stays
+/// as it is. Fluids fall into the empty blocks below them, and spread
+/// sideways one level further from their source at each block, but only from
+/// where they cannot fall. A flowing block that is no longer fed drains away.
+/// Sources never change, and blocks of chunks that are not loaded count as
+/// solid.
+pub fn next_fluid_state<F>(
+    registry: &BlockRegistry,
+    position: Position,
+    block_at: F,
+) -> Option<VoxelBlock>
+where
+    F: Fn(Position) -> Option<VoxelBlock>,
+{
+    let current = block_at(position)?;
+
+    let fluid = match current.is_empty() {
+        true => None,
+        false if current.state.is_fluid_source() => return None,
+        false => {
+            registry.fluid_flow(current.id)?;
+            Some(current.id)
+        }
+    };
+
+    // A block only fills with its own fluid, an empty one with any
+    let feeds = |block: &VoxelBlock| {
+        registry.fluid_flow(block.id).is_some() && fluid.is_none_or(|id| id == block.id)
+    };
+    let can_fall = |from: Position, block: VoxelBlock| {
+        block_at(offset(from, [0, -1, 0])).is_some_and(|below| {
+            below.is_empty() || (below.id == block.id && !below.state.is_fluid_source())
+        })
+    };
+
+    let next = if let Some(above) = block_at(offset(position, [0, 1, 0])).filter(feeds) {
+        VoxelBlock {
+            id: above.id,
+            state: registry.block(above.id).state.with_falling(true),
+        }
+    } else {
+        let mut fed_by: Option<VoxelBlock> = None;
+
+        for direction in HORIZONTAL {
+            let from = offset(position, direction);
+            let Some(neighbor) = block_at(from).filter(feeds) else {
+                continue;
+            };
+
+            let level = match neighbor.state {
+                state if state.is_fluid_source() => 1,
+                _ if can_fall(from, neighbor) => continue,
+                state if state.is_falling() => 1,
+                state => state.fluid_level() + 1,
+            };
+
+            let flow = registry.fluid_flow(neighbor.id).unwrap_or_default();
+            let is_closer = fed_by.is_none_or(|block| level < block.state.fluid_level());
+
+            if level <= flow && is_closer {
+                fed_by = Some(VoxelBlock {
+                    id: neighbor.id,
+                    state: registry.block(neighbor.id).state.with_fluid_level(level),
+                });
+            }
+        }
+
+        fed_by.unwrap_or_default()
+    };
+
+    (next != current).then_some(next)
+}
+
+/// Wakes the fluids around the blocks that were replaced
+pub fn schedule_fluid_updates(
+    mut fluid_updates: ResMut<FluidUpdates>,
+    mut ev_block_changed: EventReader<BlockChangedEvent>,
+) {
+    for event in ev_block_changed.read() {
+        let position = [event.coord.x, event.coord.y, event.coord.z];
+
+        fluid_updates.pending.insert(position);
+        fluid_updates.pending.extend(
+            NEIGHBORS
+                .iter()
+                .map(|direction| offset(position, *direction)),
+        );
+    }
+}
+
+/// One step of the fluids. Every pending block is looked at before any is
+/// replaced, so that fluids move by one block per step. The replaced blocks
+/// wake their neighbours for the next step through `schedule_fluid_updates`.
+pub fn flow_fluids(
+    mut fluid_updates: ResMut<FluidUpdates>,
+    mut voxel_world: VoxelWorld,
+    block_registry: Res<BlockRegistry>,
+) {
+    if fluid_updates.pending.is_empty() {
+        return;
+    }
+
+    let pending: Vec<Position> = fluid_updates
+        .pending
+        .iter()
+        .take(MAX_UPDATES_PER_STEP)
+        .copied()
+        .collect();
+
+    for position in pending.iter() {
+        fluid_updates.pending.remove(position);
+    }
+
+    // Unloaded chunks stop the fluids, so the blocks that change are loaded
+    let changes: Vec<(GlobalVoxelBlockCoord, VoxelBlock)> = pending
+        .into_iter()
+        .filter_map(|position| {
+            next_fluid_state(&block_registry, position, |at| {
+                voxel_world.get_block(GlobalVoxelBlockCoord::from(at)).ok()
+            })
+            .map(|block| (GlobalVoxelBlockCoord::from(position), block))
+        })
+        .collect();
+
+    if let Err(error) = voxel_world.set_blocks(changes) {
+        warn!("Fluids could not flow: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::game_block_registry;
+    use bevy::platform::collections::HashMap;
+
+    const RADIUS: CoordSystemIntegerSize = 10;
+    const HEIGHT: CoordSystemIntegerSize = 6;
+
+    /// Blocks of a small world on a rock floor, the others are empty
+    struct Basin {
+        registry: BlockRegistry,
+        blocks: HashMap<Position, VoxelBlock>,
+    }
+
+    impl Basin {
+        fn new() -> Self {
+            let registry = game_block_registry();
+            let rock = registry.block(registry.id("rock").unwrap());
+            let mut blocks = HashMap::new();
+
+            for x in -RADIUS..=RADIUS {
+                for z in -RADIUS..=RADIUS {
+                    blocks.insert([x, 0, z], rock);
+                }
+            }
+
+            Self { registry, blocks }
+        }
+
+        fn set(&mut self, position: Position, name: &str) {
+            let block = self.registry.block(self.registry.id(name).unwrap());
+
+            self.blocks.insert(position, block);
+        }
+
+        fn get(&self, position: Position) -> VoxelBlock {
+            self.blocks.get(&position).copied().unwrap_or_default()
+        }
+
+        fn step(&mut self, steps: usize) {
+            for _ in 0..steps {
+                let mut changes = vec![];
+
+                for x in -RADIUS..=RADIUS {
+                    for y in 0..HEIGHT {
+                        for z in -RADIUS..=RADIUS {
+                            let next = next_fluid_state(&self.registry, [x, y, z], |at| {
+                                let inside = at[0].abs() <= RADIUS && at[2].abs() <= RADIUS;
+
+                                inside.then(|| self.get(at))
+                            });
+
+                            if let Some(block) = next {
+                                changes.push(([x, y, z], block));
+                            }
+                        }
+                    }
+                }
+
+                self.blocks.extend(changes);
+            }
+        }
+    }
+
+    #[test]
+    fn water_spreads_away_from_its_source() {
+        let mut basin = Basin::new();
+        basin.set([0, 1, 0], "water");
+        basin.step(10);
+
+        assert_eq!(basin.get([3, 1, 0]).state.fluid_level(), 3);
+        assert_eq!(basin.get([0, 1, -7]).state.fluid_level(), 7);
+        assert_eq!(basin.get([2, 1, 2]).state.fluid_level(), 4);
+        assert!(basin.get([8, 1, 0]).is_empty());
+        assert!(basin.get([0, 2, 0]).is_empty());
+    }
+
+    #[test]
+    fn water_falls_then_spreads_on_the_floor() {
+        let mut basin = Basin::new();
+        basin.set([0, 4, 0], "water");
+        basin.step(8);
+
+        for y in 1..4 {
+            assert!(basin.get([0, y, 0]).state.is_falling());
+        }
+
+        // Sideways from the source, then down the edge of the step
+        assert_eq!(basin.get([1, 4, 0]).state.fluid_level(), 1);
+        assert!(basin.get([2, 4, 0]).is_empty());
+        assert_eq!(basin.get([2, 1, 0]).state.fluid_level(), 1);
+    }
+
+    #[test]
+    fn water_drains_without_its_source() {
+        let mut basin = Basin::new();
+        basin.set([0, 1, 0], "water");
+        basin.step(10);
+
+        basin.blocks.remove(&[0, 1, 0]);
+        basin.step(10);
+
+        assert!(basin
+            .blocks
+            .values()
+            .all(|block| basin.registry.fluid_flow(block.id).is_none()));
+    }
+}
@@ -0,0 +1,656 @@
+use crate::chunk::neighborhood::neighbor_offsets;
+use crate::chunk::palette::PaletteValue;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::voxel_chunk::{sections_around_block, BlockChangedEvent, NewChunks, VoxelChunk};
+use crate::game_world::coord::{
+    ChunkCoord, GlobalVoxelBlockCoord, LocalVoxelBlockCoord, LocalVoxelBlockOffset,
+};
+use crate::game_world::GameWorld;
+use crate::settings::{
+    CoordSystemIntegerSize, CHUNK_HEIGHT, CHUNK_SIZE, CUBIC_CHUNKS, SECTION_COUNT, SECTION_HEIGHT,
+    SECTION_VOLUME,
+};
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::ops::DerefMut;
+
+/// Brightest light level, the one of the open sky
+pub const MAX_LIGHT: u8 = 15;
+
+/// Position of a block in world space
+type Position = [CoordSystemIntegerSize; 3];
+
+const DIRECTIONS: [Position; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+const DOWN: Position = [0, -1, 0];
+
+/// Light levels of a block, from 0 to `MAX_LIGHT`: the sky light in the high
+/// bits and the block light in the low bits
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Light(u8);
+
+impl Light {
+    pub fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.0 >> 4,
+            LightChannel::Block => self.0 & 0x0f,
+        }
+    }
+
+    pub fn with(self, channel: LightChannel, level: u8) -> Self {
+        match channel {
+            LightChannel::Sky => Self((self.0 & 0x0f) | (level << 4)),
+            LightChannel::Block => Self((self.0 & 0xf0) | level),
+        }
+    }
+
+    /// Brightest of the two channels
+    pub fn level(self) -> u8 {
+        self.get(LightChannel::Sky)
+            .max(self.get(LightChannel::Block))
+    }
+}
+
+/// Blocks in the dark are the empty values of the light storage
+impl PaletteValue for Light {
+    fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightChannel {
+    /// Comes down from the sky without fading while nothing is in the way
+    Sky,
+    /// Emitted by blocks
+    Block,
+}
+
+impl LightChannel {
+    pub const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+
+    /// Level reached by the block next to a block lit at `level`
+    fn spread(self, level: u8, direction: Position) -> u8 {
+        match self {
+            LightChannel::Sky if level == MAX_LIGHT && direction == DOWN => MAX_LIGHT,
+            _ => level.saturating_sub(1),
+        }
+    }
+}
+
+/// Loaded chunks the light floods through. Light stops at opaque blocks and
+/// at chunks that are not loaded, and crosses the borders of the others.
+pub struct LightVolume<'a, C> {
+    chunks: HashMap<ChunkCoord, C>,
+    registry: &'a BlockRegistry,
+    /// Sections whose mesh shows a light that changed
+    changed_sections: HashSet<(ChunkCoord, usize)>,
+    track_changes: bool,
+}
+
+impl<'a, C> LightVolume<'a, C>
+where
+    C: DerefMut<Target = VoxelChunk>,
+{
+    pub fn new(
+        registry: &'a BlockRegistry,
+        chunks: impl IntoIterator<Item = (ChunkCoord, C)>,
+    ) -> Self {
+        Self {
+            chunks: chunks.into_iter().collect(),
+            registry,
+            changed_sections: HashSet::new(),
+            track_changes: true,
+        }
+    }
+
+    fn locate(position: Position) -> (ChunkCoord, LocalVoxelBlockOffset) {
+        let (chunk_coord, block_coord): (ChunkCoord, LocalVoxelBlockCoord) =
+            GlobalVoxelBlockCoord::from(position).into();
+
+        (chunk_coord, block_coord.into())
+    }
+
+    /// `None` when the chunk is not loaded
+    fn light(&self, position: Position) -> Option<Light> {
+        let (chunk_coord, offset) = Self::locate(position);
+
+        self.chunks.get(&chunk_coord)?.light(&offset)
+    }
+
+    fn lets_light_through(&self, position: Position) -> bool {
+        let (chunk_coord, offset) = Self::locate(position);
+
+        self.chunks
+            .get(&chunk_coord)
+            .and_then(|chunk| chunk.get_block(&offset))
+            .is_some_and(|block| !self.registry.is_opaque(block.id))
+    }
+
+    fn emission(&self, position: Position) -> u8 {
+        let (chunk_coord, offset) = Self::locate(position);
+
+        self.chunks
+            .get(&chunk_coord)
+            .and_then(|chunk| chunk.get_block(&offset))
+            .map_or(0, |block| self.registry.emission(block.id))
+    }
+
+    /// Lights the blocks that give off light at their emission level, and
+    /// returns the ones that do
+    fn light_emitters(&mut self, positions: impl IntoIterator<Item = Position>) -> Vec<Position> {
+        let mut emitters = vec![];
+
+        for position in positions {
+            let emission = self.emission(position);
+
+            if emission > 0 {
+                self.set_light(position, LightChannel::Block, emission);
+                emitters.push(position);
+            }
+        }
+
+        emitters
+    }
+
+    fn set_light(&mut self, position: Position, channel: LightChannel, level: u8) {
+        let (chunk_coord, offset) = Self::locate(position);
+
+        let Some(chunk) = self.chunks.get_mut(&chunk_coord) else {
+            return;
+        };
+
+        let light = chunk.light(&offset).unwrap_or_default();
+        chunk.set_light(&offset, light.with(channel, level));
+
+        if !self.track_changes {
+            return;
+        }
+
+        let block_coord = LocalVoxelBlockCoord::from(offset);
+        let in_section = [block_coord.x, block_coord.y % SECTION_HEIGHT, block_coord.z];
+        let section_dimensions = [CHUNK_SIZE, SECTION_HEIGHT, CHUNK_SIZE];
+
+        // Only the blocks on the faces of a section show in the meshes of the
+        // sections around
+        if (0..3)
+            .all(|axis| in_section[axis] > 0 && in_section[axis] < section_dimensions[axis] - 1)
+        {
+            self.changed_sections
+                .insert((chunk_coord, (block_coord.y / SECTION_HEIGHT) as usize));
+        } else {
+            self.changed_sections
+                .extend(sections_around_block(chunk_coord, &block_coord));
+        }
+    }
+
+    /// Floods the light of the given blocks into the blocks around them that
+    /// are darker
+    pub fn spread(&mut self, channel: LightChannel, sources: impl IntoIterator<Item = Position>) {
+        let mut queue: VecDeque<Position> = sources.into_iter().collect();
+
+        while let Some(position) = queue.pop_front() {
+            let level = self
+                .light(position)
+                .map(|light| light.get(channel))
+                .unwrap_or_default();
+
+            if level <= 1 {
+                continue;
+            }
+
+            for direction in DIRECTIONS {
+                let neighbor = [0, 1, 2].map(|axis| position[axis] + direction[axis]);
+                let spread = channel.spread(level, direction);
+
+                if !self.lets_light_through(neighbor)
+                    || self
+                        .light(neighbor)
+                        .is_none_or(|light| light.get(channel) >= spread)
+                {
+                    continue;
+                }
+
+                self.set_light(neighbor, channel, spread);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    /// Darkens the blocks lit by the given blocks, then lets the light of the
+    /// remaining sources around flow back in
+    fn remove(&mut self, channel: LightChannel, positions: &[Position]) {
+        let mut queue = VecDeque::new();
+        let mut sources = vec![];
+
+        for position in positions {
+            let level = self
+                .light(*position)
+                .map(|light| light.get(channel))
+                .unwrap_or_default();
+
+            self.set_light(*position, channel, 0);
+            queue.push_back((*position, level));
+        }
+
+        while let Some((position, level)) = queue.pop_front() {
+            for direction in DIRECTIONS {
+                let neighbor = [0, 1, 2].map(|axis| position[axis] + direction[axis]);
+                let Some(neighbor_level) = self.light(neighbor).map(|light| light.get(channel))
+                else {
+                    continue;
+                };
+
+                if neighbor_level == 0 {
+                    continue;
+                }
+
+                // The neighbour got its light from this block
+                if neighbor_level < level
+                    || (channel == LightChannel::Sky && direction == DOWN && level == MAX_LIGHT)
+                {
+                    self.set_light(neighbor, channel, 0);
+                    queue.push_back((neighbor, neighbor_level));
+
+                    // Unless it gives off light itself
+                    if channel == LightChannel::Block {
+                        sources.extend(self.light_emitters([neighbor]));
+                    }
+                } else {
+                    sources.push(neighbor);
+                }
+            }
+        }
+
+        self.spread(channel, sources);
+    }
+
+    /// Updates the light around blocks that were replaced
+    pub fn update_blocks(&mut self, positions: &[Position]) {
+        for channel in LightChannel::ALL {
+            self.remove(channel, positions);
+        }
+
+        // The top of the world is open to the sky
+        let sky_sources: Vec<Position> = positions
+            .iter()
+            .filter(|position| !CUBIC_CHUNKS && position[1] == CHUNK_HEIGHT - 1)
+            .filter(|position| self.lets_light_through(**position))
+            .copied()
+            .collect();
+
+        for position in &sky_sources {
+            self.set_light(*position, LightChannel::Sky, MAX_LIGHT);
+        }
+
+        self.spread(LightChannel::Sky, sky_sources);
+
+        let emitters = self.light_emitters(positions.iter().copied());
+        self.spread(LightChannel::Block, emitters);
+    }
+
+    /// Lets the light flow between a chunk and the loaded chunks next to it
+    pub fn spread_across_borders(&mut self, chunk_coord: ChunkCoord) {
+        let dimensions = [CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE];
+        let origin = [0, 1, 2].map(|axis| chunk_coord[axis] * dimensions[axis]);
+        let mut sources = vec![];
+
+        for direction in DIRECTIONS {
+            if !self.chunks.contains_key(&chunk_coord.offset(direction)) {
+                continue;
+            }
+
+            let axis = direction.iter().position(|d| *d != 0).unwrap();
+            let [first, second] = match axis {
+                0 => [1, 2],
+                1 => [0, 2],
+                _ => [0, 1],
+            };
+
+            // The layer of this chunk on the border and the one of the neighbour
+            let layer = match direction[axis] {
+                1 => dimensions[axis] - 1,
+                _ => 0,
+            };
+
+            for i in 0..dimensions[first] {
+                for j in 0..dimensions[second] {
+                    let mut position = origin;
+                    position[axis] += layer;
+                    position[first] += i;
+                    position[second] += j;
+
+                    sources.push(position);
+                    sources.push([0, 1, 2].map(|a| position[a] + direction[a]));
+                }
+            }
+        }
+
+        for channel in LightChannel::ALL {
+            self.spread(channel, sources.iter().copied());
+        }
+    }
+
+    /// Marks the sections showing a light that changed to be meshed again
+    pub fn mark_changed_sections_dirty(&mut self) {
+        for (chunk_coord, section) in self.changed_sections.drain() {
+            if let Some(chunk) = self.chunks.get_mut(&chunk_coord) {
+                chunk.mark_dirty(section);
+            }
+        }
+    }
+}
+
+/// Lights a chunk on its own, as if the chunks around were not loaded. The
+/// sky light comes down from the top of the chunk when `open_sky` is set.
+/// The light of the neighbours flows in once the chunk is spawned (see
+/// `spread_light`).
+pub fn light_chunk(chunk: &mut VoxelChunk, registry: &BlockRegistry, open_sky: bool) {
+    // Only the sections with blocks giving off light are walked
+    let emitting_sections: Vec<usize> = (0..SECTION_COUNT)
+        .filter(|section| {
+            chunk
+                .section(*section)
+                .palette()
+                .iter()
+                .any(|block| registry.emission(block.id) > 0)
+        })
+        .collect();
+
+    let mut volume = LightVolume::new(registry, [(ChunkCoord::default(), chunk)]);
+    volume.track_changes = false;
+
+    let candidates = emitting_sections.into_iter().flat_map(|section| {
+        let first = section * SECTION_VOLUME as usize;
+
+        (first..first + SECTION_VOLUME as usize).map(|offset| {
+            let coord = LocalVoxelBlockCoord::from(LocalVoxelBlockOffset(offset));
+
+            [coord.x, coord.y, coord.z]
+        })
+    });
+    let emitters = volume.light_emitters(candidates);
+    volume.spread(LightChannel::Block, emitters);
+
+    if !open_sky {
+        return;
+    }
+
+    let mut sources = vec![];
+
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            for y in (0..CHUNK_HEIGHT).rev() {
+                if !volume.lets_light_through([x, y, z]) {
+                    break;
+                }
+
+                volume.set_light([x, y, z], LightChannel::Sky, MAX_LIGHT);
+                sources.push([x, y, z]);
+            }
+        }
+    }
+
+    volume.spread(LightChannel::Sky, sources);
+}
+
+/// Chunks whose light can change with the light of the given chunks. The
+/// light fades out before crossing a whole chunk, so these are the chunks
+/// around them, except in cubic chunks where the sky light goes down without
+/// fading through all the chunks below.
+fn chunks_in_reach(
+    game_world: &GameWorld,
+    around: impl IntoIterator<Item = ChunkCoord>,
+) -> HashSet<ChunkCoord> {
+    let mut in_reach: HashSet<ChunkCoord> = around
+        .into_iter()
+        .flat_map(|chunk_coord| {
+            neighbor_offsets()
+                .chain([[0, 0, 0]])
+                .map(move |offset| chunk_coord.offset(offset))
+        })
+        .collect();
+
+    if CUBIC_CHUNKS {
+        let columns: HashSet<[CoordSystemIntegerSize; 2]> = in_reach
+            .iter()
+            .map(|chunk_coord| [chunk_coord.x, chunk_coord.z])
+            .collect();
+
+        in_reach.extend(
+            game_world
+                .keys()
+                .filter(|chunk_coord| columns.contains(&[chunk_coord.x, chunk_coord.z])),
+        );
+    }
+
+    in_reach
+}
+
+/// Lets the light in and out of the chunks that were just spawned, and
+/// updates it around the blocks that were replaced. Only the chunks the
+/// light can reach from them are looked at.
+pub fn spread_light(
+    game_world: Res<GameWorld>,
+    mut chunks: ParamSet<(NewChunks, Query<(&ChunkCoord, &mut VoxelChunk)>)>,
+    mut ev_block_changed: EventReader<BlockChangedEvent>,
+    block_registry: Res<BlockRegistry>,
+) {
+    let new_chunks: Vec<ChunkCoord> = chunks.p0().iter().copied().collect();
+    let edited: Vec<Position> = ev_block_changed
+        .read()
+        .map(|event| [event.coord.x, event.coord.y, event.coord.z])
+        .collect();
+
+    if new_chunks.is_empty() && edited.is_empty() {
+        return;
+    }
+
+    let edited_chunks = edited.iter().map(|position| {
+        let (chunk_coord, _): (ChunkCoord, LocalVoxelBlockCoord) =
+            GlobalVoxelBlockCoord::from(*position).into();
+
+        chunk_coord
+    });
+    let in_reach = chunks_in_reach(&game_world, new_chunks.iter().copied().chain(edited_chunks));
+
+    let mut chunks = chunks.p1();
+    let mut volume = LightVolume::new(
+        &block_registry,
+        chunks
+            .iter_mut()
+            .filter(|(coord, _)| in_reach.contains(*coord))
+            .map(|(coord, chunk)| (*coord, chunk)),
+    );
+
+    for chunk_coord in new_chunks {
+        volume.spread_across_borders(chunk_coord);
+    }
+
+    volume.update_blocks(&edited);
+    volume.mark_changed_sections_dirty();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::block::VoxelBlock;
+    use crate::chunk::registry::tests::game_block_registry;
+    use bevy_rapier3d::na::Point3;
+
+    const GROUND: CoordSystemIntegerSize = 10;
+    const TUNNEL: CoordSystemIntegerSize = 4;
+
+    /// Rock up to `GROUND` with air above
+    fn ground(registry: &BlockRegistry) -> VoxelChunk {
+        let rock = registry.block(registry.id("rock").unwrap());
+        let mut chunk = VoxelChunk::default();
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..=GROUND {
+                for z in 0..CHUNK_SIZE {
+                    chunk.update_block(&LocalVoxelBlockCoord(Point3::new(x, y, z)), |block| {
+                        *block = rock
+                    });
+                }
+            }
+        }
+
+        chunk
+    }
+
+    fn dig(chunk: &mut VoxelChunk, positions: impl IntoIterator<Item = Position>) {
+        for position in positions {
+            chunk.update_block(&LocalVoxelBlockCoord(Point3::from(position)), |block| {
+                *block = VoxelBlock::default()
+            });
+        }
+    }
+
+    fn sky_at(chunk: &VoxelChunk, position: Position) -> u8 {
+        chunk
+            .light(&LocalVoxelBlockCoord(Point3::from(position)))
+            .unwrap()
+            .get(LightChannel::Sky)
+    }
+
+    fn volume<'r, 'c>(
+        registry: &'r BlockRegistry,
+        chunks: &'c mut HashMap<ChunkCoord, VoxelChunk>,
+    ) -> LightVolume<'r, &'c mut VoxelChunk> {
+        LightVolume::new(
+            registry,
+            chunks.iter_mut().map(|(coord, chunk)| (*coord, chunk)),
+        )
+    }
+
+    #[test]
+    fn sky_light_fades_into_caves() {
+        let registry = game_block_registry();
+        let mut chunk = ground(&registry);
+
+        // A shaft down to the tunnel, which goes toward x = 0
+        dig(&mut chunk, (TUNNEL..=GROUND).map(|y| [8, y, 8]));
+        dig(&mut chunk, (0..8).map(|x| [x, TUNNEL, 8]));
+
+        light_chunk(&mut chunk, &registry, true);
+
+        assert_eq!(sky_at(&chunk, [3, GROUND + 1, 3]), MAX_LIGHT);
+        assert_eq!(sky_at(&chunk, [8, TUNNEL, 8]), MAX_LIGHT);
+        assert_eq!(sky_at(&chunk, [3, TUNNEL, 8]), MAX_LIGHT - 5);
+        assert_eq!(sky_at(&chunk, [3, TUNNEL, 3]), 0);
+    }
+
+    #[test]
+    fn gems_light_up_closed_caves() {
+        let registry = game_block_registry();
+        let gem = registry.id("gem").unwrap();
+        let emission = registry.emission(gem);
+        let block_at = |chunk: &VoxelChunk, position: Position| {
+            chunk
+                .light(&LocalVoxelBlockCoord(Point3::from(position)))
+                .unwrap()
+                .get(LightChannel::Block)
+        };
+
+        // A tunnel without any opening, with a gem in its wall
+        let mut chunks = HashMap::from([(ChunkCoord::default(), ground(&registry))]);
+        let chunk = chunks.get_mut(&ChunkCoord::default()).unwrap();
+        dig(chunk, (2..12).map(|x| [x, TUNNEL, 8]));
+        chunk.update_block(&LocalVoxelBlockCoord(Point3::new(1, TUNNEL, 8)), |block| {
+            *block = registry.block(gem)
+        });
+        light_chunk(chunk, &registry, false);
+
+        assert_eq!(block_at(chunk, [1, TUNNEL, 8]), emission);
+        assert_eq!(block_at(chunk, [6, TUNNEL, 8]), emission - 5);
+        assert_eq!(sky_at(chunk, [6, TUNNEL, 8]), 0);
+
+        // Mining the gem puts out the light
+        chunk.update_block(&LocalVoxelBlockCoord(Point3::new(1, TUNNEL, 8)), |block| {
+            *block = VoxelBlock::default()
+        });
+        volume(&registry, &mut chunks).update_blocks(&[[1, TUNNEL, 8]]);
+
+        let chunk = &chunks[&ChunkCoord::default()];
+        assert_eq!(block_at(chunk, [1, TUNNEL, 8]), 0);
+        assert_eq!(block_at(chunk, [6, TUNNEL, 8]), 0);
+    }
+
+    #[test]
+    fn light_crosses_borders_and_follows_edits() {
+        let registry = game_block_registry();
+        let rock = registry.block(registry.id("rock").unwrap());
+        let left_coord = ChunkCoord(Point3::new(0, 0, 0));
+        let right_coord = ChunkCoord(Point3::new(1, 0, 0));
+
+        // A shaft near the border of the left chunk, and a tunnel from it
+        // into the right chunk
+        let mut left = ground(&registry);
+        dig(&mut left, (TUNNEL..=GROUND).map(|y| [14, y, 8]));
+        dig(&mut left, [[15, TUNNEL, 8]]);
+        let mut right = ground(&registry);
+        dig(&mut right, (0..6).map(|x| [x, TUNNEL, 8]));
+
+        light_chunk(&mut left, &registry, true);
+        light_chunk(&mut right, &registry, true);
+        assert_eq!(sky_at(&right, [0, TUNNEL, 8]), 0);
+
+        let mut chunks = HashMap::from([(left_coord, left), (right_coord, right)]);
+
+        {
+            let mut border = volume(&registry, &mut chunks);
+            border.spread_across_borders(right_coord);
+            assert!(border.changed_sections.contains(&(right_coord, 0)));
+            border.mark_changed_sections_dirty();
+        }
+
+        assert_eq!(sky_at(&chunks[&right_coord], [0, TUNNEL, 8]), MAX_LIGHT - 2);
+        assert_eq!(sky_at(&chunks[&right_coord], [3, TUNNEL, 8]), MAX_LIGHT - 5);
+        assert!(chunks
+            .get_mut(&right_coord)
+            .unwrap()
+            .take_dirty_sections()
+            .contains(&0));
+
+        // Blocking the tunnel on the border darkens the other side
+        let wall = LocalVoxelBlockCoord(Point3::new(15, TUNNEL, 8));
+        chunks
+            .get_mut(&left_coord)
+            .unwrap()
+            .update_block(&wall, |block| *block = rock);
+        volume(&registry, &mut chunks).update_blocks(&[[15, TUNNEL, 8]]);
+
+        assert_eq!(sky_at(&chunks[&left_coord], [15, TUNNEL, 8]), 0);
+        assert_eq!(sky_at(&chunks[&right_coord], [3, TUNNEL, 8]), 0);
+        assert_eq!(sky_at(&chunks[&left_coord], [14, TUNNEL, 8]), MAX_LIGHT);
+
+        // And opening it again lets the light back in
+        chunks
+            .get_mut(&left_coord)
+            .unwrap()
+            .update_block(&wall, |block| *block = VoxelBlock::default());
+        volume(&registry, &mut chunks).update_blocks(&[[15, TUNNEL, 8]]);
+
+        assert_eq!(sky_at(&chunks[&right_coord], [3, TUNNEL, 8]), MAX_LIGHT - 5);
+    }
+
+    #[test]
+    fn light_changes_only_reach_the_chunks_around() {
+        let far = ChunkCoord(Point3::new(5, 0, 0));
+        let game_world = GameWorld(HashMap::from([(far, Entity::PLACEHOLDER)]));
+
+        let in_reach = chunks_in_reach(&game_world, [ChunkCoord::default()]);
+
+        assert_eq!(in_reach.len(), 27);
+        assert!(in_reach.contains(&ChunkCoord(Point3::new(-1, 1, 1))));
+        assert!(!in_reach.contains(&far));
+    }
+}
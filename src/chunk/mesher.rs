@@ -0,0 +1,1331 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::light::{Light, MAX_LIGHT};
+use crate::chunk::neighborhood::ChunkNeighborhood;
+use crate::chunk::registry::{BlockFace, BlockRegistry, RenderClass};
+use crate::chunk::state::{OrientationKind, MAX_FLUID_LEVEL};
+use crate::chunk::voxel_chunk::VoxelChunk;
+use crate::game_world::coord::LocalVoxelBlockCoord;
+use crate::settings::{CoordSystemIntegerSize, CHUNK_SIZE, SECTION_HEIGHT};
+use crate::utils::{Color, VertexBuffer, UV};
+use bevy::render::mesh::Indices;
+use bevy_rapier3d::na::Point3;
+use serde_derive::Deserialize;
+
+/// Algorithm used to turn the blocks of a chunk into a mesh
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mesher {
+    /// One quad for every visible block face
+    Naive,
+    /// Coplanar visible faces of the same block type are merged into larger quads
+    #[default]
+    Greedy,
+}
+
+const SECTION_DIMENSIONS: [CoordSystemIntegerSize; 3] = [CHUNK_SIZE, SECTION_HEIGHT, CHUNK_SIZE];
+
+const QUAD_INDICES: [u32; 6] = [0, 1, 2, 2, 3, 0];
+/// Same quad split along the other diagonal
+const FLIPPED_QUAD_INDICES: [u32; 6] = [1, 2, 3, 3, 0, 1];
+
+/// Brightness of a face corner for each ambient occlusion level, from fully
+/// occluded (0) to not occluded at all (3)
+const AMBIENT_OCCLUSION_BRIGHTNESS: [f32; 4] = [0.35, 0.55, 0.75, 1.0];
+
+/// Ambient occlusion level of the 4 corners of a face, in the order of `Face::corners`
+type AmbientOcclusion = [u8; 4];
+
+/// Brightness lost by each light level below `MAX_LIGHT`
+const LIGHT_FALLOFF: f32 = 0.8;
+
+/// Light level of the 4 corners of a face, in the order of `Face::corners`
+type CornerLight = [u8; 4];
+
+struct Face {
+    normal: [CoordSystemIntegerSize; 3],
+    /// Corner of the unit cube (-1 or 1 on each axis) and its UV inside the
+    /// tile. V grows downwards in the atlas, so side faces have v = 0 at the top.
+    corners: [([f32; 3], UV); 4],
+    /// Axis along which each UV component grows when the quad is larger than a block
+    uv_axes: [usize; 2],
+}
+
+// suppose Y-up right hand, and camera look from +z to -z
+const FACES: [Face; 6] = [
+    Face {
+        normal: [0, 0, 1],
+        corners: [
+            ([-1., -1., 1.], [0., 1.]),
+            ([1., -1., 1.], [1., 1.]),
+            ([1., 1., 1.], [1., 0.]),
+            ([-1., 1., 1.], [0., 0.]),
+        ],
+        uv_axes: [0, 1],
+    },
+    Face {
+        normal: [0, 0, -1],
+        corners: [
+            ([-1., 1., -1.], [1., 0.]),
+            ([1., 1., -1.], [0., 0.]),
+            ([1., -1., -1.], [0., 1.]),
+            ([-1., -1., -1.], [1., 1.]),
+        ],
+        uv_axes: [0, 1],
+    },
+    Face {
+        normal: [1, 0, 0],
+        corners: [
+            ([1., -1., -1.], [1., 1.]),
+            ([1., 1., -1.], [1., 0.]),
+            ([1., 1., 1.], [0., 0.]),
+            ([1., -1., 1.], [0., 1.]),
+        ],
+        uv_axes: [2, 1],
+    },
+    Face {
+        normal: [-1, 0, 0],
+        corners: [
+            ([-1., -1., 1.], [1., 1.]),
+            ([-1., 1., 1.], [1., 0.]),
+            ([-1., 1., -1.], [0., 0.]),
+            ([-1., -1., -1.], [0., 1.]),
+        ],
+        uv_axes: [2, 1],
+    },
+    Face {
+        normal: [0, 1, 0],
+        corners: [
+            ([1., 1., -1.], [1., 0.]),
+            ([-1., 1., -1.], [0., 0.]),
+            ([-1., 1., 1.], [0., 1.]),
+            ([1., 1., 1.], [1., 1.]),
+        ],
+        uv_axes: [0, 2],
+    },
+    Face {
+        normal: [0, -1, 0],
+        corners: [
+            ([1., -1., 1.], [0., 0.]),
+            ([-1., -1., 1.], [1., 0.]),
+            ([-1., -1., -1.], [1., 1.]),
+            ([1., -1., -1.], [0., 1.]),
+        ],
+        uv_axes: [0, 2],
+    },
+];
+
+/// One quad for every face of the blocks of a render class in a section that
+/// is not hidden by a neighbouring block
+pub fn naive_mesh(
+    chunk: &VoxelChunk,
+    section: usize,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    class: RenderClass,
+) -> (Indices, VertexBuffer) {
+    let mut indices: Vec<u32> = vec![];
+    let mut vertices: VertexBuffer = vec![];
+
+    let Some(bounds) = SectionBounds::of(chunk, section, registry, class) else {
+        return (Indices::U32(indices), vertices);
+    };
+
+    for x in 0..CHUNK_SIZE {
+        for y in bounds.min[1]..bounds.min[1] + SECTION_HEIGHT {
+            for z in 0..CHUNK_SIZE {
+                let position = [x, y, z];
+
+                if bounds.is_hidden(position) {
+                    continue;
+                }
+
+                let Some(block) = block_at(chunk, position) else {
+                    continue;
+                };
+
+                if block.is_empty() || registry.render_class(block.id) != class {
+                    continue;
+                }
+
+                let center = position.map(|v| v as f32);
+
+                for face in FACES.iter() {
+                    if is_face_visible(chunk, neighborhood, registry, block, position, face) {
+                        let (ambient_occlusion, light) =
+                            face_shading(chunk, neighborhood, registry, position, face, class);
+
+                        push_quad(
+                            &mut indices,
+                            &mut vertices,
+                            face,
+                            center.map(|v| v - 0.5),
+                            center.map(|v| v + 0.5),
+                            face_texture(registry, block, face),
+                            ambient_occlusion,
+                            light,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    (Indices::U32(indices), vertices)
+}
+
+/// Merges the visible faces of the blocks of a render class in each slice of
+/// a section into the largest rectangles of a single block (id and state),
+/// ambient occlusion and light. The UVs grow with the quad so that the tile repeats
+/// once per block (see `shaders/block_atlas.wgsl`).
+pub fn greedy_mesh(
+    chunk: &VoxelChunk,
+    section: usize,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    class: RenderClass,
+) -> (Indices, VertexBuffer) {
+    let mut indices: Vec<u32> = vec![];
+    let mut vertices: VertexBuffer = vec![];
+
+    let Some(bounds) = SectionBounds::of(chunk, section, registry, class) else {
+        return (Indices::U32(indices), vertices);
+    };
+
+    for face in FACES.iter() {
+        let axis = face.normal.iter().position(|n| *n != 0).unwrap();
+        let (u_axis, v_axis) = match axis {
+            0 => (1, 2),
+            1 => (0, 2),
+            _ => (0, 1),
+        };
+        let u_len = SECTION_DIMENSIONS[u_axis] as usize;
+        let v_len = SECTION_DIMENSIONS[v_axis] as usize;
+
+        let mut mask: Vec<Option<(VoxelBlock, AmbientOcclusion, CornerLight)>> =
+            vec![None; u_len * v_len];
+
+        for slice in bounds.min[axis]..bounds.min[axis] + SECTION_DIMENSIONS[axis] {
+            for v in 0..v_len {
+                for u in 0..u_len {
+                    let mut position = [0; 3];
+                    position[axis] = slice;
+                    position[u_axis] = bounds.min[u_axis] + u as CoordSystemIntegerSize;
+                    position[v_axis] = bounds.min[v_axis] + v as CoordSystemIntegerSize;
+
+                    if bounds.is_hidden(position) {
+                        mask[u + v * u_len] = None;
+                        continue;
+                    }
+
+                    mask[u + v * u_len] = block_at(chunk, position)
+                        .filter(|block| {
+                            !block.is_empty() && registry.render_class(block.id) == class
+                        })
+                        .filter(|block| {
+                            is_face_visible(chunk, neighborhood, registry, *block, position, face)
+                        })
+                        .map(|block| {
+                            let (ambient_occlusion, light) =
+                                face_shading(chunk, neighborhood, registry, position, face, class);
+
+                            (block, ambient_occlusion, light)
+                        });
+                }
+            }
+
+            for v in 0..v_len {
+                let mut u = 0;
+
+                while u < u_len {
+                    let Some(key) = mask[u + v * u_len] else {
+                        u += 1;
+                        continue;
+                    };
+
+                    let mut width = 1;
+                    while u + width < u_len && mask[u + width + v * u_len] == Some(key) {
+                        width += 1;
+                    }
+
+                    let mut height = 1;
+                    while v + height < v_len
+                        && (u..u + width).all(|i| mask[i + (v + height) * u_len] == Some(key))
+                    {
+                        height += 1;
+                    }
+
+                    for row in v..v + height {
+                        for i in u..u + width {
+                            mask[i + row * u_len] = None;
+                        }
+                    }
+
+                    let plane = slice as f32 + face.normal[axis] as f32 * 0.5;
+                    let mut min = [plane; 3];
+                    let mut max = [plane; 3];
+                    let u_start = (bounds.min[u_axis] as usize + u) as f32;
+                    let v_start = (bounds.min[v_axis] as usize + v) as f32;
+                    min[u_axis] = u_start - 0.5;
+                    max[u_axis] = u_start + width as f32 - 0.5;
+                    min[v_axis] = v_start - 0.5;
+                    max[v_axis] = v_start + height as f32 - 0.5;
+
+                    let (block, ambient_occlusion, light) = key;
+
+                    push_quad(
+                        &mut indices,
+                        &mut vertices,
+                        face,
+                        min,
+                        max,
+                        face_texture(registry, block, face),
+                        ambient_occlusion,
+                        light,
+                    );
+
+                    u += width;
+                }
+            }
+        }
+    }
+
+    (Indices::U32(indices), vertices)
+}
+
+/// One quad for every visible face of the fluid blocks in a section. The
+/// surface of a fluid block sinks with its level, and each of its top corners
+/// is shared with the fluid blocks around it so that the surface slopes away
+/// from the sources without steps. The sides are cut at the surface instead
+/// of squeezed.
+pub fn fluid_mesh(
+    chunk: &VoxelChunk,
+    section: usize,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+) -> (Indices, VertexBuffer) {
+    let mut indices: Vec<u32> = vec![];
+    let mut vertices: VertexBuffer = vec![];
+
+    let Some(bounds) = SectionBounds::of(chunk, section, registry, RenderClass::Fluid) else {
+        return (Indices::U32(indices), vertices);
+    };
+
+    for x in 0..CHUNK_SIZE {
+        for y in bounds.min[1]..bounds.min[1] + SECTION_HEIGHT {
+            for z in 0..CHUNK_SIZE {
+                let position = [x, y, z];
+
+                let Some(block) = block_at(chunk, position) else {
+                    continue;
+                };
+
+                if block.is_empty() || registry.render_class(block.id) != RenderClass::Fluid {
+                    continue;
+                }
+
+                let center = position.map(|v| v as f32);
+
+                for face in FACES.iter() {
+                    if !is_face_visible(chunk, neighborhood, registry, block, position, face) {
+                        continue;
+                    }
+
+                    let corners = face.corners.map(|(corner, uv)| {
+                        let mut vertex = [0, 1, 2].map(|axis| center[axis] + corner[axis] * 0.5);
+                        let mut uv = uv;
+
+                        if corner[1] > 0.0 {
+                            let height =
+                                fluid_corner_height(chunk, neighborhood, block, position, corner);
+                            vertex[1] = center[1] - 0.5 + height;
+
+                            if face.normal[1] == 0 {
+                                uv[1] = 1.0 - height;
+                            }
+                        }
+
+                        (vertex, uv)
+                    });
+                    let light = corner_light(chunk, neighborhood, registry, position, face);
+
+                    push_face(
+                        &mut indices,
+                        &mut vertices,
+                        face,
+                        corners,
+                        face_texture(registry, block, face).tile,
+                        brightness([3; 4], light),
+                    );
+                }
+            }
+        }
+    }
+
+    (Indices::U32(indices), vertices)
+}
+
+/// Height of the surface of a fluid block, from its level. A falling fluid
+/// block fills the whole block.
+fn fluid_height(block: VoxelBlock) -> f32 {
+    match block.state.is_falling() {
+        true => 1.0,
+        false => {
+            (MAX_FLUID_LEVEL + 1 - block.state.fluid_level()) as f32 / (MAX_FLUID_LEVEL + 2) as f32
+        }
+    }
+}
+
+/// Height of a top corner of a fluid block: the average surface of the same
+/// fluid in the 4 columns around the corner, or the top of the block when
+/// one of them has the same fluid above
+fn fluid_corner_height(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    block: VoxelBlock,
+    position: [CoordSystemIntegerSize; 3],
+    corner: [f32; 3],
+) -> f32 {
+    let [dx, dz] = [corner[0], corner[2]].map(|c| c as CoordSystemIntegerSize);
+    let mut heights: Vec<f32> = vec![];
+
+    for [x, z] in [[0, 0], [dx, 0], [0, dz], [dx, dz]] {
+        let column = [position[0] + x, position[1], position[2] + z];
+        let same_fluid =
+            |position| block_around(chunk, neighborhood, position).filter(|o| o.id == block.id);
+
+        let Some(other) = same_fluid(column) else {
+            continue;
+        };
+
+        if same_fluid([column[0], column[1] + 1, column[2]]).is_some() {
+            return 1.0;
+        }
+
+        heights.push(fluid_height(other));
+    }
+
+    heights.iter().sum::<f32>() / heights.len() as f32
+}
+
+/// Every face of a single block centered on the origin, at full brightness,
+/// for the blocks drawn outside of the chunks
+pub fn block_mesh(registry: &BlockRegistry, block: VoxelBlock) -> (Indices, VertexBuffer) {
+    let mut indices: Vec<u32> = vec![];
+    let mut vertices: VertexBuffer = vec![];
+
+    for face in FACES.iter() {
+        push_quad(
+            &mut indices,
+            &mut vertices,
+            face,
+            [-0.5; 3],
+            [0.5; 3],
+            face_texture(registry, block, face),
+            [3; 4],
+            [MAX_LIGHT; 4],
+        );
+    }
+
+    (Indices::U32(indices), vertices)
+}
+
+/// Part of the chunk covered by a section
+struct SectionBounds {
+    min: [CoordSystemIntegerSize; 3],
+    /// A section made only of opaque blocks can only show the faces of its
+    /// outer layer, its inner blocks are skipped
+    is_opaque_full: bool,
+}
+
+impl SectionBounds {
+    /// `None` when the section has no block of the render class to mesh
+    fn of(
+        chunk: &VoxelChunk,
+        section: usize,
+        registry: &BlockRegistry,
+        class: RenderClass,
+    ) -> Option<Self> {
+        let storage = chunk.section(section);
+
+        if storage.is_empty()
+            || !storage
+                .palette()
+                .iter()
+                .any(|block| !block.is_empty() && registry.render_class(block.id) == class)
+        {
+            return None;
+        }
+
+        Some(Self {
+            min: [0, section as CoordSystemIntegerSize * SECTION_HEIGHT, 0],
+            is_opaque_full: storage.is_full()
+                && storage
+                    .palette()
+                    .iter()
+                    .all(|block| registry.is_opaque(block.id)),
+        })
+    }
+
+    fn is_hidden(&self, position: [CoordSystemIntegerSize; 3]) -> bool {
+        self.is_opaque_full
+            && (0..3).all(|axis| {
+                let local = position[axis] - self.min[axis];
+
+                local > 0 && local < SECTION_DIMENSIONS[axis] - 1
+            })
+    }
+}
+
+/// Texture of a block face: the tile in the atlas and how it is turned
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct FaceTexture {
+    /// UV of the top left corner of the tile
+    tile: UV,
+    /// Number of clockwise quarter turns of the texture on the face
+    quarter_turns: u8,
+}
+
+/// Picks the tile of a block face from the orientation of the block and its
+/// growth stage. The texture of the sides points toward the top of the
+/// block, the one of the top and bottom toward its front.
+fn face_texture(registry: &BlockRegistry, block: VoxelBlock, face: &Face) -> FaceTexture {
+    let Some(definition) = registry.get(block.id) else {
+        return FaceTexture::default();
+    };
+
+    let orientation = block.state.orientation().normal();
+    let (up, front) = match definition.states.orientation {
+        Some(OrientationKind::Horizontal) => ([0, 1, 0], Some(orientation)),
+        Some(_) => (orientation, None),
+        None => ([0, 1, 0], None),
+    };
+
+    let block_face = match face.normal {
+        normal if normal == up => BlockFace::Top,
+        normal if normal == up.map(|n| -n) => BlockFace::Bottom,
+        normal if Some(normal) == front => BlockFace::Front,
+        _ => BlockFace::Side,
+    };
+
+    let texture_up = match block_face {
+        BlockFace::Side | BlockFace::Front => Some(up),
+        BlockFace::Top | BlockFace::Bottom => front,
+    };
+
+    let tile = definition.textures.tile(block_face) + block.state.growth() as u16;
+
+    FaceTexture {
+        tile: registry.atlas().tile_origin(tile),
+        quarter_turns: texture_up
+            .map(|direction| quarter_turns_toward(face, direction))
+            .unwrap_or_default(),
+    }
+}
+
+/// Quarter turns that make the top of the texture of a face point toward
+/// a direction lying on the face
+fn quarter_turns_toward(face: &Face, direction: [CoordSystemIntegerSize; 3]) -> u8 {
+    let corner_at = |uv: UV| {
+        face.corners
+            .iter()
+            .find(|(_, corner_uv)| *corner_uv == uv)
+            .map(|(corner, _)| *corner)
+            .unwrap()
+    };
+    let unit = |from: [f32; 3], to: [f32; 3]| {
+        [0, 1, 2].map(|axis| ((to[axis] - from[axis]) / 2.0) as CoordSystemIntegerSize)
+    };
+
+    // V grows downwards in the atlas
+    let texture_up = unit(corner_at([0., 1.]), corner_at([0., 0.]));
+    let texture_right = unit(corner_at([0., 0.]), corner_at([1., 0.]));
+
+    match direction {
+        d if d == texture_right => 1,
+        d if d == texture_up.map(|n| -n) => 2,
+        d if d == texture_right.map(|n| -n) => 3,
+        _ => 0,
+    }
+}
+
+fn block_at(chunk: &VoxelChunk, position: [CoordSystemIntegerSize; 3]) -> Option<VoxelBlock> {
+    let coord = LocalVoxelBlockCoord(Point3::from(position));
+
+    if !coord.is_valid_chunk_voxel_coord() {
+        return None;
+    }
+
+    chunk.get_block(&coord).copied()
+}
+
+/// Light of a block in this chunk or in a loaded neighbour
+fn light_around(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    position: [CoordSystemIntegerSize; 3],
+) -> Option<Light> {
+    match LocalVoxelBlockCoord(Point3::from(position)).is_valid_chunk_voxel_coord() {
+        true => chunk.light(&LocalVoxelBlockCoord(Point3::from(position))),
+        false => neighborhood.get_light(position),
+    }
+}
+
+/// Block at a position in this chunk or in a loaded neighbour. `None` in
+/// chunks that are not loaded.
+fn block_around(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    position: [CoordSystemIntegerSize; 3],
+) -> Option<VoxelBlock> {
+    block_at(chunk, position).or_else(|| neighborhood.get_block(position).copied())
+}
+
+/// Whether the block at a position, in this chunk or in a loaded neighbour,
+/// is opaque. Blocks of chunks that are not loaded count as not opaque.
+fn is_opaque_at(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    position: [CoordSystemIntegerSize; 3],
+) -> bool {
+    block_around(chunk, neighborhood, position).is_some_and(|block| registry.is_opaque(block.id))
+}
+
+/// A face is hidden when the neighbouring block is opaque, whether it is in
+/// this chunk or in an adjacent loaded one, or when both blocks are the same
+/// transparent block or fluid. Faces toward chunks that are not loaded are drawn until
+/// that chunk arrives and the border is re-meshed.
+fn is_face_visible(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    block: VoxelBlock,
+    position: [CoordSystemIntegerSize; 3],
+    face: &Face,
+) -> bool {
+    let neighbor = [0, 1, 2].map(|axis| position[axis] + face.normal[axis]);
+
+    match block_around(chunk, neighborhood, neighbor) {
+        Some(other) if registry.is_opaque(other.id) => false,
+        Some(other) => {
+            other.id != block.id
+                || !matches!(
+                    registry.render_class(block.id),
+                    RenderClass::Transparent | RenderClass::Fluid
+                )
+        }
+        None => true,
+    }
+}
+
+/// Ambient occlusion and light of the corners of a face. Emissive faces are
+/// drawn at full brightness.
+fn face_shading(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    position: [CoordSystemIntegerSize; 3],
+    face: &Face,
+    class: RenderClass,
+) -> (AmbientOcclusion, CornerLight) {
+    match class {
+        RenderClass::Emissive => ([3; 4], [MAX_LIGHT; 4]),
+        _ => (
+            ambient_occlusion(chunk, neighborhood, registry, position, face),
+            corner_light(chunk, neighborhood, registry, position, face),
+        ),
+    }
+}
+
+/// Classic voxel ambient occlusion: each corner of a face is darkened by the
+/// two blocks along its edges and the block on its diagonal, in the layer the
+/// face looks into. A corner between two opaque sides is fully occluded
+/// whatever the diagonal block is.
+fn ambient_occlusion(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    position: [CoordSystemIntegerSize; 3],
+    face: &Face,
+) -> AmbientOcclusion {
+    let [first, second] = tangent_axes(face);
+
+    face.corners.map(|(corner, _)| {
+        let occluded_by = |tangents: &[usize]| {
+            let neighbor = corner_neighbor(position, face, corner, tangents);
+
+            is_opaque_at(chunk, neighborhood, registry, neighbor) as u8
+        };
+
+        let side_a = occluded_by(&[first]);
+        let side_b = occluded_by(&[second]);
+
+        if side_a == 1 && side_b == 1 {
+            return 0;
+        }
+
+        3 - (side_a + side_b + occluded_by(&[first, second]))
+    })
+}
+
+/// Smooth lighting: each corner of a face averages the light of the blocks
+/// that are not opaque around it, in the layer the face looks into. As with
+/// ambient occlusion, the diagonal block does not count when both sides are
+/// opaque.
+fn corner_light(
+    chunk: &VoxelChunk,
+    neighborhood: &ChunkNeighborhood,
+    registry: &BlockRegistry,
+    position: [CoordSystemIntegerSize; 3],
+    face: &Face,
+) -> CornerLight {
+    let [first, second] = tangent_axes(face);
+
+    face.corners.map(|(corner, _)| {
+        let neighbor = |tangents: &[usize]| corner_neighbor(position, face, corner, tangents);
+        let is_open =
+            |tangents: &[usize]| !is_opaque_at(chunk, neighborhood, registry, neighbor(tangents));
+
+        let mut samples = vec![neighbor(&[])];
+        if is_open(&[first]) {
+            samples.push(neighbor(&[first]));
+        }
+        if is_open(&[second]) {
+            samples.push(neighbor(&[second]));
+        }
+        if samples.len() > 1 && is_open(&[first, second]) {
+            samples.push(neighbor(&[first, second]));
+        }
+
+        // Chunks that are not loaded yet count as lit, like the faces toward them
+        let levels: Vec<u32> = samples
+            .into_iter()
+            .map(|sample| {
+                light_around(chunk, neighborhood, sample).map_or(MAX_LIGHT, Light::level) as u32
+            })
+            .collect();
+
+        ((levels.iter().sum::<u32>() as f32 / levels.len() as f32).round()) as u8
+    })
+}
+
+/// The two axes a face lies on
+fn tangent_axes(face: &Face) -> [usize; 2] {
+    match face.normal.iter().position(|n| *n != 0).unwrap() {
+        0 => [1, 2],
+        1 => [0, 2],
+        _ => [0, 1],
+    }
+}
+
+/// Block in front of a face, moved toward one of its corners along the
+/// given tangent axes
+fn corner_neighbor(
+    position: [CoordSystemIntegerSize; 3],
+    face: &Face,
+    corner: [f32; 3],
+    tangents: &[usize],
+) -> [CoordSystemIntegerSize; 3] {
+    let mut neighbor = [0, 1, 2].map(|i| position[i] + face.normal[i]);
+    for tangent in tangents {
+        neighbor[*tangent] += corner[*tangent] as CoordSystemIntegerSize;
+    }
+
+    neighbor
+}
+
+fn light_brightness(level: u8) -> f32 {
+    LIGHT_FALLOFF.powi((MAX_LIGHT - level.min(MAX_LIGHT)) as i32)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_quad(
+    indices: &mut Vec<u32>,
+    vertices: &mut VertexBuffer,
+    face: &Face,
+    min: [f32; 3],
+    max: [f32; 3],
+    texture: FaceTexture,
+    ambient_occlusion: AmbientOcclusion,
+    light: CornerLight,
+) {
+    let size = [max[0] - min[0], max[1] - min[1], max[2] - min[2]];
+    // Once turned by an odd number of quarters, the texture U runs along the face V
+    let uv_axes = match texture.quarter_turns % 2 {
+        0 => face.uv_axes,
+        _ => [face.uv_axes[1], face.uv_axes[0]],
+    };
+
+    let corners = face.corners.map(|(corner, uv)| {
+        let position = [0, 1, 2].map(|axis| match corner[axis] < 0.0 {
+            true => min[axis],
+            false => max[axis],
+        });
+        let uv = (0..texture.quarter_turns).fold(uv, |uv, _| [uv[1], 1.0 - uv[0]]);
+
+        (
+            position,
+            [uv[0] * size[uv_axes[0]], uv[1] * size[uv_axes[1]]],
+        )
+    });
+
+    push_face(
+        indices,
+        vertices,
+        face,
+        corners,
+        texture.tile,
+        brightness(ambient_occlusion, light),
+    );
+}
+
+fn brightness(ambient_occlusion: AmbientOcclusion, light: CornerLight) -> [f32; 4] {
+    std::array::from_fn(|i| {
+        AMBIENT_OCCLUSION_BRIGHTNESS[ambient_occlusion[i] as usize] * light_brightness(light[i])
+    })
+}
+
+/// Pushes the 4 corners of a face, each with its position, UV and brightness
+fn push_face(
+    indices: &mut Vec<u32>,
+    vertices: &mut VertexBuffer,
+    face: &Face,
+    corners: [([f32; 3], UV); 4],
+    tile: UV,
+    brightness: [f32; 4],
+) {
+    let first_index = vertices.len() as u32;
+    let normal = face.normal.map(|n| n as f32);
+
+    for ((position, uv), brightness) in corners.into_iter().zip(brightness) {
+        let color: Color = [brightness, brightness, brightness, 1.0];
+
+        vertices.push((position, normal, uv, tile, color));
+    }
+
+    // Split the quad along the brightest diagonal so that the shade of a
+    // single corner does not bleed into the whole quad
+    let [a, b, c, d] = brightness;
+    let quad_indices = match a + c < b + d {
+        true => FLIPPED_QUAD_INDICES,
+        false => QUAD_INDICES,
+    };
+
+    quad_indices
+        .iter()
+        .for_each(|i| indices.push(first_index + i));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::{chunk_with, game_block_registry};
+    use crate::chunk::state::{BlockState, Orientation};
+    use crate::settings::{CHUNK_HEIGHT, SECTION_COUNT};
+
+    fn face_count(indices: &Indices) -> usize {
+        indices.len() / QUAD_INDICES.len()
+    }
+
+    /// Sum of the quad areas for each normal
+    fn area_per_normal(vertices: &VertexBuffer) -> Vec<([f32; 3], f32)> {
+        let mut areas: Vec<([f32; 3], f32)> = vec![];
+
+        for quad in vertices.chunks(4) {
+            let edge = |a: [f32; 3], b: [f32; 3]| {
+                ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+            };
+            let area = edge(quad[0].0, quad[1].0) * edge(quad[1].0, quad[2].0);
+
+            match areas.iter_mut().find(|(normal, _)| *normal == quad[0].1) {
+                Some((_, total)) => *total += area,
+                None => areas.push((quad[0].1, area)),
+            }
+        }
+
+        areas.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        areas
+    }
+
+    #[test]
+    fn single_block_is_the_same_for_both_meshers() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|x, y, z| (x == 3 && y == 4 && z == 5).then_some("rock"));
+
+        let (naive_indices, naive_vertices) = naive_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+        let (greedy_indices, greedy_vertices) = greedy_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+
+        assert_eq!(face_count(&naive_indices), 6);
+        assert_eq!(face_count(&greedy_indices), 6);
+        assert_eq!(naive_vertices.len(), greedy_vertices.len());
+        assert_eq!(
+            area_per_normal(&naive_vertices),
+            area_per_normal(&greedy_vertices)
+        );
+    }
+
+    #[test]
+    fn flat_layer_is_merged_into_one_quad_per_side() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|_, y, _| (y == 0).then_some("grass"));
+
+        let (naive_indices, naive_vertices) = naive_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+        let (greedy_indices, greedy_vertices) = greedy_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+
+        let layer = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        assert_eq!(
+            face_count(&naive_indices),
+            2 * layer + 4 * CHUNK_SIZE as usize
+        );
+        assert_eq!(face_count(&greedy_indices), 6);
+        assert_eq!(greedy_vertices.len(), 24);
+        assert_eq!(
+            area_per_normal(&naive_vertices),
+            area_per_normal(&greedy_vertices)
+        );
+
+        // The tile repeats once per block across the merged quad
+        let max_uv = greedy_vertices
+            .iter()
+            .map(|(_, _, uv, _, _)| uv[0].max(uv[1]))
+            .fold(0.0, f32::max);
+        assert_eq!(max_uv, CHUNK_SIZE as f32);
+    }
+
+    #[test]
+    fn different_blocks_are_not_merged() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|x, y, _| match (y, x % 2) {
+            (0, 0) => Some("rock"),
+            (0, _) => Some("dirt"),
+            _ => None,
+        });
+
+        let (naive_indices, naive_vertices) = naive_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+        let (greedy_indices, greedy_vertices) = greedy_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+
+        assert!(face_count(&greedy_indices) < face_count(&naive_indices));
+        assert_eq!(
+            area_per_normal(&naive_vertices),
+            area_per_normal(&greedy_vertices)
+        );
+
+        for quad in greedy_vertices.chunks(4) {
+            assert!(quad.iter().all(|(_, _, _, tile, _)| *tile == quad[0].3));
+        }
+    }
+
+    #[test]
+    fn terrain_covers_the_same_surface() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|x, y, z| {
+            let height = (x * 3 + z * 5) % 7 + 2;
+
+            match y {
+                _ if y > height => None,
+                _ if y == height => Some("grass"),
+                _ if (x + y + z) % 11 == 0 => Some("gem"),
+                _ => Some("rock"),
+            }
+        });
+
+        let (naive_indices, naive_vertices) = naive_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+        let (greedy_indices, greedy_vertices) = greedy_mesh(
+            &chunk,
+            0,
+            &ChunkNeighborhood::default(),
+            &registry,
+            RenderClass::Opaque,
+        );
+
+        assert!(face_count(&greedy_indices) < face_count(&naive_indices));
+        assert_eq!(
+            area_per_normal(&naive_vertices),
+            area_per_normal(&greedy_vertices)
+        );
+    }
+
+    #[test]
+    fn loaded_neighbors_hide_border_faces() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|_, y, _| (y == 0).then_some("rock"));
+        let right = chunk_with(|_, y, _| (y == 0).then_some("rock"));
+
+        let neighborhood = ChunkNeighborhood::from_neighbors(|offset| match offset {
+            [1, 0, 0] => Some(&right),
+            _ => None,
+        });
+
+        for (indices, vertices) in [
+            naive_mesh(&chunk, 0, &neighborhood, &registry, RenderClass::Opaque),
+            greedy_mesh(&chunk, 0, &neighborhood, &registry, RenderClass::Opaque),
+        ] {
+            assert!(vertices
+                .iter()
+                .all(|(_, normal, _, _, _)| *normal != [1.0, 0.0, 0.0]));
+            assert!(vertices
+                .iter()
+                .any(|(_, normal, _, _, _)| *normal == [-1.0, 0.0, 0.0]));
+            assert!(face_count(&indices) > 0);
+        }
+    }
+
+    #[test]
+    fn corners_next_to_blocks_are_occluded() {
+        let registry = game_block_registry();
+        // A floor with a single block standing on it
+        let chunk = chunk_with(|x, y, z| match (x, y, z) {
+            (_, 0, _) => Some("rock"),
+            (5, 1, 5) => Some("rock"),
+            _ => None,
+        });
+
+        for (_, vertices) in [
+            naive_mesh(
+                &chunk,
+                0,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            ),
+            greedy_mesh(
+                &chunk,
+                0,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            ),
+        ] {
+            let brightness_at = |position: [f32; 3]| {
+                vertices
+                    .iter()
+                    .filter(|(p, normal, _, _, _)| *p == position && *normal == [0.0, 1.0, 0.0])
+                    .map(|(_, _, _, _, color)| color[0])
+                    .fold(f32::MAX, f32::min)
+            };
+
+            // Corner of the floor touching an edge of the block
+            assert_eq!(
+                brightness_at([4.5, 0.5, 5.5]),
+                AMBIENT_OCCLUSION_BRIGHTNESS[2]
+            );
+            // Corner of the floor away from it
+            assert_eq!(
+                brightness_at([-0.5, 0.5, -0.5]),
+                AMBIENT_OCCLUSION_BRIGHTNESS[3]
+            );
+            // The top of the block itself is not occluded
+            assert_eq!(
+                brightness_at([5.5, 1.5, 5.5]),
+                AMBIENT_OCCLUSION_BRIGHTNESS[3]
+            );
+        }
+    }
+
+    #[test]
+    fn occlusion_is_continuous_across_chunk_borders() {
+        let registry = game_block_registry();
+        let chunk = chunk_with(|_, y, _| (y == 0).then_some("rock"));
+        // A wall along the border in the chunk on the right
+        let right = chunk_with(|x, y, _| match (x, y) {
+            (_, 0) => Some("rock"),
+            (0, 1) => Some("rock"),
+            _ => None,
+        });
+
+        let neighborhood = ChunkNeighborhood::from_neighbors(|offset| match offset {
+            [1, 0, 0] => Some(&right),
+            _ => None,
+        });
+
+        let (_, vertices) = greedy_mesh(&chunk, 0, &neighborhood, &registry, RenderClass::Opaque);
+        let border = (CHUNK_SIZE as f32) - 0.5;
+
+        assert!(vertices
+            .iter()
+            .filter(|(p, normal, _, _, _)| p[0] == border && *normal == [0.0, 1.0, 0.0])
+            .all(|(_, _, _, _, color)| color[0] < 1.0));
+    }
+
+    #[test]
+    fn faces_under_a_roof_are_darker() {
+        let registry = game_block_registry();
+        // A roof over half of the floor
+        let chunk = chunk_with(|x, y, _| match (x, y) {
+            (_, 0) => Some("rock"),
+            (0..8, 3) => Some("rock"),
+            _ => None,
+        });
+
+        for (_, vertices) in [
+            naive_mesh(
+                &chunk,
+                0,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            ),
+            greedy_mesh(
+                &chunk,
+                0,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            ),
+        ] {
+            let floor_brightness = |x: f32| {
+                vertices
+                    .iter()
+                    .filter(|(p, normal, _, _, _)| {
+                        p[0] == x && p[1] == 0.5 && *normal == [0.0, 1.0, 0.0]
+                    })
+                    .map(|(_, _, _, _, color)| color[0])
+                    .fold(f32::MAX, f32::min)
+            };
+
+            // Far from the roof, along the border of the chunk
+            assert_eq!(floor_brightness(15.5), 1.0);
+            // The light fades the further it goes under the roof
+            assert!(floor_brightness(4.5) < floor_brightness(6.5));
+            assert!(floor_brightness(6.5) < floor_brightness(15.5));
+        }
+    }
+
+    #[test]
+    fn sections_are_meshed_on_their_own() {
+        let registry = game_block_registry();
+        // Solid ground up to the middle of the chunk
+        let chunk = chunk_with(|_, y, _| (y < CHUNK_HEIGHT / 2).then_some("rock"));
+
+        for section in 0..SECTION_COUNT {
+            let (naive_indices, naive_vertices) = naive_mesh(
+                &chunk,
+                section,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            );
+            let (greedy_indices, greedy_vertices) = greedy_mesh(
+                &chunk,
+                section,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            );
+
+            let min_y = (section as CoordSystemIntegerSize * SECTION_HEIGHT) as f32 - 0.5;
+            let max_y = min_y + SECTION_HEIGHT as f32;
+            assert!(naive_vertices
+                .iter()
+                .chain(greedy_vertices.iter())
+                .all(|(p, _, _, _, _)| (min_y..=max_y).contains(&p[1])));
+
+            assert_eq!(
+                area_per_normal(&naive_vertices),
+                area_per_normal(&greedy_vertices)
+            );
+
+            if chunk.section(section).is_empty() {
+                assert_eq!(face_count(&naive_indices), 0);
+                assert_eq!(face_count(&greedy_indices), 0);
+            }
+        }
+
+        // A full section buried in the ground has no visible face
+        let full = chunk_with(|_, _, _| Some("rock"));
+        let neighborhood = ChunkNeighborhood::from_neighbors(|_| Some(&full));
+
+        for section in 0..SECTION_COUNT {
+            let (naive_indices, _) = naive_mesh(
+                &full,
+                section,
+                &neighborhood,
+                &registry,
+                RenderClass::Opaque,
+            );
+            let (greedy_indices, _) = greedy_mesh(
+                &full,
+                section,
+                &neighborhood,
+                &registry,
+                RenderClass::Opaque,
+            );
+
+            assert_eq!(face_count(&naive_indices), 0);
+            assert_eq!(face_count(&greedy_indices), 0);
+        }
+    }
+
+    #[test]
+    fn transparent_blocks_only_hide_their_own_faces() {
+        let registry = game_block_registry();
+        // A row of 3 glass blocks against a rock, and 2 leaves further away
+        let chunk = chunk_with(|x, y, z| match (x, y, z) {
+            (0..=2, 0, 0) => Some("glass"),
+            (3, 0, 0) => Some("rock"),
+            (6..=7, 0, 0) => Some("leaves"),
+            _ => None,
+        });
+        let neighborhood = ChunkNeighborhood::default();
+
+        let faces = |class: RenderClass| {
+            let (naive_indices, naive_vertices) =
+                naive_mesh(&chunk, 0, &neighborhood, &registry, class);
+            let (_, greedy_vertices) = greedy_mesh(&chunk, 0, &neighborhood, &registry, class);
+
+            assert_eq!(
+                area_per_normal(&naive_vertices),
+                area_per_normal(&greedy_vertices)
+            );
+
+            face_count(&naive_indices)
+        };
+
+        // The faces between the glass blocks and the one against the rock are hidden
+        assert_eq!(faces(RenderClass::Transparent), 6 * 3 - 2 * 2 - 1);
+        // The rock is seen through the glass
+        assert_eq!(faces(RenderClass::Opaque), 6);
+        // Leaves show the faces of the leaves behind them
+        assert_eq!(faces(RenderClass::Cutout), 6 * 2);
+    }
+
+    #[test]
+    fn fluid_tops_slope_away_from_the_source() {
+        let registry = game_block_registry();
+        let water = registry.id("water").unwrap();
+        let mut chunk = chunk_with(|_, y, _| (y == 0).then_some("rock"));
+
+        // A source and the water flowing from it along x
+        for (x, level) in [(4, 0), (5, 1), (6, 2)] {
+            chunk.update_block(&LocalVoxelBlockCoord(Point3::new(x, 1, 8)), |block| {
+                *block = VoxelBlock {
+                    id: water,
+                    state: BlockState::default().with_fluid_level(level),
+                }
+            });
+        }
+
+        let (indices, vertices) = fluid_mesh(&chunk, 0, &ChunkNeighborhood::default(), &registry);
+        let surface = |x: f32| {
+            vertices
+                .iter()
+                .find(|(p, normal, _, _, _)| p[0] == x && *normal == [0.0, 1.0, 0.0])
+                .map(|(p, _, _, _, _)| p[1])
+                .unwrap()
+        };
+
+        // The tops and the sides, the bottom lies on the rock
+        assert_eq!(face_count(&indices), 3 + 3 * 2 + 2);
+        assert_eq!(surface(3.5), 0.5 + 8.0 / 9.0);
+        assert!(surface(3.5) > surface(4.5));
+        assert!(surface(4.5) > surface(5.5));
+        assert!(surface(5.5) > surface(6.5));
+    }
+
+    #[test]
+    fn oriented_blocks_turn_their_faces() {
+        let registry = BlockRegistry::from_definitions(
+            toml::from_str(
+                "[atlas]\ncolumns = 4\nrows = 1\ntile_size = 32\n\
+                [[blocks]]\nid = 1\nname = \"log\"\nsolid = true\n\
+                hardness = 1.0\ntextures = { all = 0, top = 1, bottom = 1 }\n\
+                states = { orientation = \"axis\" }\n",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let log = registry.id("log").unwrap();
+
+        let mesh_with = |orientation: Orientation| {
+            let mut chunk = VoxelChunk::default();
+            chunk.update_block(&LocalVoxelBlockCoord(Point3::new(3, 4, 5)), |block| {
+                *block = VoxelBlock {
+                    id: log,
+                    state: BlockState::default().with_orientation(orientation),
+                }
+            });
+
+            naive_mesh(
+                &chunk,
+                0,
+                &ChunkNeighborhood::default(),
+                &registry,
+                RenderClass::Opaque,
+            )
+            .1
+        };
+        let face = |vertices: &VertexBuffer, normal: [f32; 3]| {
+            vertices
+                .iter()
+                .filter(|(_, n, _, _, _)| *n == normal)
+                .map(|(p, _, uv, tile, _)| (*p, *uv, *tile))
+                .collect::<Vec<_>>()
+        };
+
+        let upright = mesh_with(Orientation::Up);
+        let lying = mesh_with(Orientation::East);
+        let rings = registry.atlas().tile_origin(1);
+        let bark = registry.atlas().tile_origin(0);
+
+        // The rings move from the top to the east and west faces
+        assert!(face(&upright, [0.0, 1.0, 0.0]).iter().all(|f| f.2 == rings));
+        assert!(face(&lying, [1.0, 0.0, 0.0]).iter().all(|f| f.2 == rings));
+        assert!(face(&lying, [-1.0, 0.0, 0.0]).iter().all(|f| f.2 == rings));
+        assert!(face(&lying, [0.0, 1.0, 0.0]).iter().all(|f| f.2 == bark));
+
+        // The bark on the sides is turned a quarter
+        let upright_side = face(&upright, [0.0, 0.0, 1.0]);
+        let lying_side = face(&lying, [0.0, 0.0, 1.0]);
+        assert_eq!(
+            upright_side.iter().map(|f| f.0).collect::<Vec<_>>(),
+            lying_side.iter().map(|f| f.0).collect::<Vec<_>>()
+        );
+        assert_ne!(
+            upright_side.iter().map(|f| f.1).collect::<Vec<_>>(),
+            lying_side.iter().map(|f| f.1).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,18 +1,83 @@
 pub mod block;
+pub mod collider;
+pub mod falling;
+pub mod fluid;
+pub mod light;
+pub mod mesher;
+pub mod neighborhood;
 pub mod noise;
+pub mod palette;
 pub mod procedural;
+pub mod random_tick;
+pub mod registry;
+pub mod state;
+pub mod structure;
+pub mod vox;
 pub mod voxel_chunk;
 
-use crate::chunk::block::{BlockMaterial, BlockMaterialMap};
-use crate::chunk::voxel_chunk::add_new_chunks_to_game_world;
+use crate::chunk::block::{BlockMaterial, VoxelMaterial};
+use crate::chunk::collider::update_chunk_colliders;
+use crate::chunk::falling::{start_falling_blocks, update_falling_blocks};
+use crate::chunk::fluid::{flow_fluids, schedule_fluid_updates, FluidUpdates, FLOW_TICKS};
+use crate::chunk::light::spread_light;
+use crate::chunk::random_tick::random_block_ticks;
+use crate::chunk::registry::{
+    listen_to_block_registry_loaded, load_block_registry, BlockRegistry, BlockRegistryLoader,
+};
+use crate::chunk::structure::{
+    load_structure_models, update_structure_templates, StructureModels, StructureTemplates,
+};
+use crate::chunk::vox::{VoxLoader, VoxModel};
+use crate::chunk::voxel_chunk::{
+    add_new_chunks_to_game_world, begin_remeshing_dirty_sections, receive_remeshed_sections,
+    remesh_borders_of_new_chunks, send_block_changed_events, BlockChangedEvent, ChunkRemeshTaskMap,
+};
+use crate::world_tick::{every_ticks, WorldTick};
 use bevy::prelude::*;
 
 pub struct ChunkPlugin;
 
 impl Plugin for ChunkPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<BlockMaterialMap>()
+        app.add_plugins(MaterialPlugin::<VoxelMaterial>::default())
+            .init_asset::<BlockRegistry>()
+            .register_asset_loader(BlockRegistryLoader)
+            .init_resource::<BlockRegistry>()
+            .init_asset::<VoxModel>()
+            .register_asset_loader(VoxLoader)
+            .init_resource::<StructureModels>()
+            .init_resource::<StructureTemplates>()
             .init_resource::<BlockMaterial>()
-            .add_systems(Update, add_new_chunks_to_game_world);
+            .init_resource::<ChunkRemeshTaskMap>()
+            .init_resource::<FluidUpdates>()
+            .add_event::<BlockChangedEvent>()
+            .add_systems(Startup, load_block_registry)
+            .add_systems(
+                Update,
+                (
+                    listen_to_block_registry_loaded,
+                    (load_structure_models, update_structure_templates).chain(),
+                    (
+                        add_new_chunks_to_game_world,
+                        send_block_changed_events,
+                        schedule_fluid_updates,
+                        start_falling_blocks,
+                        spread_light,
+                        remesh_borders_of_new_chunks,
+                        begin_remeshing_dirty_sections,
+                        receive_remeshed_sections,
+                    )
+                        .chain(),
+                    update_chunk_colliders,
+                    update_falling_blocks,
+                ),
+            )
+            .add_systems(
+                WorldTick,
+                (
+                    random_block_ticks,
+                    flow_fluids.run_if(every_ticks(FLOW_TICKS)),
+                ),
+            );
     }
 }
@@ -0,0 +1,123 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::light::Light;
+use crate::chunk::voxel_chunk::VoxelChunk;
+use crate::game_world::coord::LocalVoxelBlockCoord;
+use crate::settings::{CoordSystemIntegerSize, CHUNK_HEIGHT, CHUNK_SIZE};
+use bevy_rapier3d::na::Point3;
+
+const CHUNK_DIMENSIONS: [CoordSystemIntegerSize; 3] = [CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE];
+
+/// Copy of the blocks of the neighbouring chunks that touch a chunk, with
+/// their light: the one block thick slice of each adjacent chunk, plus the
+/// edges and corners of the diagonal ones. It is what the meshers use to look
+/// one block past the chunk borders.
+#[derive(Debug, Clone, Default)]
+pub struct ChunkNeighborhood {
+    /// Boundary slice of each neighbour, indexed by `neighbor_index`. `None`
+    /// when the neighbouring chunk is not loaded.
+    slices: [Option<Vec<(VoxelBlock, Light)>>; 27],
+}
+
+impl ChunkNeighborhood {
+    /// Builds the neighbourhood from a lookup of the chunk at an offset
+    /// (-1, 0 or 1 on each axis) from the center chunk
+    pub fn from_neighbors<'a, F>(neighbor: F) -> Self
+    where
+        F: Fn([CoordSystemIntegerSize; 3]) -> Option<&'a VoxelChunk>,
+    {
+        let mut neighborhood = Self::default();
+
+        for offset in neighbor_offsets() {
+            let Some(chunk) = neighbor(offset) else {
+                continue;
+            };
+
+            let extent = slice_extent(offset);
+            let mut slice = Vec::with_capacity((extent[0] * extent[1] * extent[2]) as usize);
+
+            for y in 0..extent[1] {
+                for z in 0..extent[2] {
+                    for x in 0..extent[0] {
+                        let position = [x, y, z];
+                        let source = [0, 1, 2].map(|axis| match offset[axis] {
+                            -1 => CHUNK_DIMENSIONS[axis] - 1,
+                            1 => 0,
+                            _ => position[axis],
+                        });
+
+                        let source = LocalVoxelBlockCoord(Point3::from(source));
+                        let block = chunk.get_block(&source).copied().unwrap_or_default();
+                        let light = chunk.light(&source).unwrap_or_default();
+
+                        slice.push((block, light));
+                    }
+                }
+            }
+
+            neighborhood.slices[neighbor_index(offset)] = Some(slice);
+        }
+
+        neighborhood
+    }
+
+    /// Block at a position given relative to the center chunk, at most one
+    /// block outside of it. Returns `None` for positions inside the center
+    /// chunk and for neighbours that are not loaded.
+    pub fn get_block(&self, position: [CoordSystemIntegerSize; 3]) -> Option<&VoxelBlock> {
+        self.get(position).map(|(block, _)| block)
+    }
+
+    /// Light of a block next to the center chunk, like `get_block`
+    pub fn get_light(&self, position: [CoordSystemIntegerSize; 3]) -> Option<Light> {
+        self.get(position).map(|(_, light)| *light)
+    }
+
+    fn get(&self, position: [CoordSystemIntegerSize; 3]) -> Option<&(VoxelBlock, Light)> {
+        let offset = [0, 1, 2].map(|axis| match position[axis] {
+            -1 => Some(-1),
+            p if p == CHUNK_DIMENSIONS[axis] => Some(1),
+            p if (0..CHUNK_DIMENSIONS[axis]).contains(&p) => Some(0),
+            _ => None,
+        });
+
+        let offset = [offset[0]?, offset[1]?, offset[2]?];
+
+        if offset == [0, 0, 0] {
+            return None;
+        }
+
+        let slice = self.slices[neighbor_index(offset)].as_ref()?;
+        let extent = slice_extent(offset);
+        let local = [0, 1, 2].map(|axis| match offset[axis] {
+            0 => position[axis],
+            _ => 0,
+        });
+
+        slice.get((local[0] + local[2] * extent[0] + local[1] * extent[0] * extent[2]) as usize)
+    }
+}
+
+/// Offsets of the 26 chunks around a chunk
+pub fn neighbor_offsets() -> impl Iterator<Item = [CoordSystemIntegerSize; 3]> {
+    (0..27)
+        .map(|index| [index % 3 - 1, (index / 3) % 3 - 1, index / 9 - 1])
+        .filter(|offset| *offset != [0, 0, 0])
+}
+
+/// Offsets of the 6 chunks sharing a face with a chunk
+pub fn face_neighbor_offsets() -> impl Iterator<Item = [CoordSystemIntegerSize; 3]> {
+    neighbor_offsets().filter(|offset| offset.iter().filter(|v| **v != 0).count() == 1)
+}
+
+fn neighbor_index(offset: [CoordSystemIntegerSize; 3]) -> usize {
+    ((offset[0] + 1) + (offset[1] + 1) * 3 + (offset[2] + 1) * 9) as usize
+}
+
+/// Size of the slice kept for a neighbour: a single layer along every axis
+/// on which the neighbour is offset
+fn slice_extent(offset: [CoordSystemIntegerSize; 3]) -> [CoordSystemIntegerSize; 3] {
+    [0, 1, 2].map(|axis| match offset[axis] {
+        0 => CHUNK_DIMENSIONS[axis],
+        _ => 1,
+    })
+}
@@ -101,4 +101,11 @@ impl Noise {
     pub fn set_seed(&mut self, seed: u32) {
         self.noise.set_seed(seed);
     }
+
+    /// Upper bound of the values returned by `get`
+    pub fn max_value(&self) -> f64 {
+        (0..self.octaves)
+            .map(|i| self.amplitude * self.gain.powi(i + 1))
+            .sum()
+    }
 }
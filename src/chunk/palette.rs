@@ -0,0 +1,340 @@
+use crate::chunk::block::VoxelBlock;
+use std::ops::{Deref, DerefMut};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// Value stored once per voxel in a `PalettedStorage`
+pub trait PaletteValue: Copy + PartialEq {
+    /// Empty values are counted, see `PalettedStorage::is_empty`
+    fn is_empty(&self) -> bool;
+}
+
+impl PaletteValue for VoxelBlock {
+    fn is_empty(&self) -> bool {
+        VoxelBlock::is_empty(self)
+    }
+}
+
+/// Block storage made of a palette of the distinct blocks of a chunk and a
+/// bit-packed array of palette indices, one per voxel. The light levels of
+/// the chunks are stored the same way.
+///
+/// When the palette holds a single block, no index is stored at all. This is
+/// the fast path for chunks that are all air or all rock.
+#[derive(Debug, Clone)]
+pub struct PalettedStorage<T = VoxelBlock> {
+    len: usize,
+    palette: Vec<T>,
+    bits_per_index: usize,
+    data: Vec<u64>,
+    /// Number of voxels that are not empty
+    non_empty: usize,
+}
+
+impl<T: PaletteValue> PalettedStorage<T> {
+    pub fn filled(len: usize, block: T) -> Self {
+        Self {
+            len,
+            palette: vec![block],
+            bits_per_index: 0,
+            data: vec![],
+            non_empty: if block.is_empty() { 0 } else { len },
+        }
+    }
+
+    /// True when every voxel is empty
+    pub fn is_empty(&self) -> bool {
+        self.non_empty == 0
+    }
+
+    /// True when no voxel is empty
+    pub fn is_full(&self) -> bool {
+        self.non_empty == self.len
+    }
+
+    /// Distinct blocks of the storage. It may still hold blocks that were
+    /// replaced since the last `compact`.
+    pub fn palette(&self) -> &[T] {
+        &self.palette
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+
+        self.palette.get(self.read_index(index))
+    }
+
+    /// Stores the block at the given index and returns the block it replaced
+    pub fn set(&mut self, index: usize, block: T) -> Option<T> {
+        if index >= self.len {
+            return None;
+        }
+
+        let previous = self.palette[self.read_index(index)];
+
+        if previous == block {
+            return Some(previous);
+        }
+
+        let palette_index = match self.palette.iter().position(|b| *b == block) {
+            Some(palette_index) => palette_index,
+            None => {
+                self.palette.push(block);
+                self.grow_to_fit_palette();
+
+                self.palette.len() - 1
+            }
+        };
+
+        self.write_index(index, palette_index);
+
+        match (previous.is_empty(), block.is_empty()) {
+            (true, false) => self.non_empty += 1,
+            (false, true) => self.non_empty -= 1,
+            _ => {}
+        }
+
+        Some(previous)
+    }
+
+    /// Drops the palette entries that are no longer referenced and shrinks
+    /// the indices back to the smallest width that fits the palette.
+    pub fn compact(&mut self) {
+        if self.bits_per_index == 0 {
+            return;
+        }
+
+        let mut used = vec![false; self.palette.len()];
+        for index in 0..self.len {
+            used[self.read_index(index)] = true;
+        }
+
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::with_capacity(self.palette.len());
+        for (old_index, block) in self.palette.iter().enumerate() {
+            if used[old_index] {
+                remap[old_index] = palette.len();
+                palette.push(*block);
+            }
+        }
+
+        let indices: Vec<usize> = (0..self.len)
+            .map(|index| remap[self.read_index(index)])
+            .collect();
+
+        self.palette = palette;
+        self.repack(bits_for_palette(self.palette.len()), indices);
+    }
+
+    fn read_index(&self, index: usize) -> usize {
+        if self.bits_per_index == 0 {
+            return 0;
+        }
+
+        let per_word = WORD_BITS / self.bits_per_index;
+        let word = self.data[index / per_word];
+        let shift = (index % per_word) * self.bits_per_index;
+        let mask = (1u64 << self.bits_per_index) - 1;
+
+        ((word >> shift) & mask) as usize
+    }
+
+    fn write_index(&mut self, index: usize, palette_index: usize) {
+        let per_word = WORD_BITS / self.bits_per_index;
+        let word = &mut self.data[index / per_word];
+        let shift = (index % per_word) * self.bits_per_index;
+        let mask = (1u64 << self.bits_per_index) - 1;
+
+        *word = (*word & !(mask << shift)) | ((palette_index as u64 & mask) << shift);
+    }
+
+    fn grow_to_fit_palette(&mut self) {
+        let bits_per_index = bits_for_palette(self.palette.len());
+
+        if bits_per_index > self.bits_per_index {
+            let indices: Vec<usize> = (0..self.len).map(|index| self.read_index(index)).collect();
+
+            self.repack(bits_per_index, indices);
+        }
+    }
+
+    fn repack(&mut self, bits_per_index: usize, indices: Vec<usize>) {
+        self.bits_per_index = bits_per_index;
+
+        if bits_per_index == 0 {
+            self.data = vec![];
+            return;
+        }
+
+        let per_word = WORD_BITS / bits_per_index;
+        self.data = vec![0; self.len.div_ceil(per_word)];
+
+        for (index, palette_index) in indices.into_iter().enumerate() {
+            self.write_index(index, palette_index);
+        }
+    }
+}
+
+/// Index widths are kept to powers of two so that an index never straddles two words
+fn bits_for_palette(palette_len: usize) -> usize {
+    match palette_len {
+        0..=1 => 0,
+        2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        17..=256 => 8,
+        _ => 16,
+    }
+}
+
+/// Mutable access to a single block of a `PalettedStorage`. The block is
+/// written back into the storage when the guard is dropped.
+pub struct VoxelBlockMut<'a> {
+    storage: &'a mut PalettedStorage,
+    index: usize,
+    block: VoxelBlock,
+    /// Block in the storage when the guard was taken
+    original: VoxelBlock,
+}
+
+impl<'a> VoxelBlockMut<'a> {
+    pub fn new(storage: &'a mut PalettedStorage, index: usize) -> Option<Self> {
+        let block = *storage.get(index)?;
+
+        Some(Self {
+            storage,
+            index,
+            block,
+            original: block,
+        })
+    }
+
+    /// Whether the block written back differs from the one in the storage
+    pub fn is_changed(&self) -> bool {
+        self.block != self.original
+    }
+}
+
+impl Deref for VoxelBlockMut<'_> {
+    type Target = VoxelBlock;
+
+    fn deref(&self) -> &Self::Target {
+        &self.block
+    }
+}
+
+impl DerefMut for VoxelBlockMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.block
+    }
+}
+
+impl Drop for VoxelBlockMut<'_> {
+    fn drop(&mut self) {
+        if self.is_changed() {
+            self.storage.set(self.index, self.block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::block::BlockId;
+
+    const ROCK: BlockId = BlockId(1);
+    const GRASS: BlockId = BlockId(2);
+    const GEM: BlockId = BlockId(3);
+    const DIRT: BlockId = BlockId(4);
+
+    fn block(id: BlockId) -> VoxelBlock {
+        VoxelBlock {
+            id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn uniform_storage_has_no_indices() {
+        let storage = PalettedStorage::filled(4096, block(ROCK));
+
+        assert_eq!(storage.palette(), [block(ROCK)]);
+        assert_eq!(storage.get(4095).unwrap().id, ROCK);
+        assert!(storage.get(4096).is_none());
+        assert!(storage.data.is_empty());
+    }
+
+    #[test]
+    fn set_grows_the_palette_and_keeps_values() {
+        let mut storage = PalettedStorage::filled(1000, VoxelBlock::default());
+        let ids = [ROCK, GRASS, GEM, DIRT];
+
+        for index in 0..1000 {
+            storage.set(index, block(ids[index % ids.len()]));
+        }
+
+        assert_eq!(storage.palette().len(), 5);
+        for index in 0..1000 {
+            assert_eq!(storage.get(index).unwrap().id, ids[index % ids.len()]);
+        }
+    }
+
+    #[test]
+    fn set_returns_previous_block() {
+        let mut storage = PalettedStorage::filled(16, VoxelBlock::default());
+
+        let previous = storage.set(3, block(GEM));
+        assert_eq!(previous.unwrap().id, BlockId::EMPTY);
+
+        let previous = storage.set(3, block(DIRT));
+        assert_eq!(previous.unwrap().id, GEM);
+
+        assert!(storage.set(16, block(DIRT)).is_none());
+    }
+
+    #[test]
+    fn empty_and_full_follow_the_edits() {
+        let mut storage = PalettedStorage::filled(8, VoxelBlock::default());
+        assert!(storage.is_empty());
+
+        storage.set(2, block(ROCK));
+        assert!(!storage.is_empty() && !storage.is_full());
+
+        for index in 0..8 {
+            storage.set(index, block(DIRT));
+        }
+        assert!(storage.is_full());
+
+        storage.set(5, VoxelBlock::default());
+        assert!(!storage.is_full());
+        assert!(PalettedStorage::filled(8, block(ROCK)).is_full());
+    }
+
+    #[test]
+    fn compact_returns_to_uniform() {
+        let mut storage = PalettedStorage::filled(256, VoxelBlock::default());
+
+        for index in 0..256 {
+            storage.set(index, block(ROCK));
+        }
+        storage.compact();
+
+        assert_eq!(storage.palette(), [block(ROCK)]);
+        assert_eq!(storage.palette.len(), 1);
+        assert!(storage.data.is_empty());
+    }
+
+    #[test]
+    fn guard_writes_back_on_drop() {
+        let mut storage = PalettedStorage::filled(64, VoxelBlock::default());
+
+        if let Some(mut block) = VoxelBlockMut::new(&mut storage, 10) {
+            block.id = GRASS;
+        }
+
+        assert_eq!(storage.get(10).unwrap().id, GRASS);
+        assert_eq!(storage.get(11).unwrap().id, BlockId::EMPTY);
+    }
+}
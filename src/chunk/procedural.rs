@@ -1,70 +1,79 @@
-use crate::chunk::block::VoxelBlockType;
+use crate::chunk::block::BlockId;
+use crate::chunk::light::light_chunk;
+use crate::chunk::neighborhood::ChunkNeighborhood;
+use crate::chunk::noise::Noise;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::structure::{place_structures, StructureTemplate};
 use crate::chunk::voxel_chunk::{ChunkData, VoxelChunk};
 use crate::game_world::coord::{ChunkCoord, LocalVoxelBlockCoord};
-use crate::settings::{CoordSystemIntegerSize, GameSettings, CHUNK_HEIGHT, CHUNK_SIZE};
+use crate::settings::{
+    CoordSystemIntegerSize, GameSettings, CHUNK_HEIGHT, CHUNK_SIZE, CUBIC_CHUNKS, SECTION_COUNT,
+};
 use crate::utils::render_mesh;
 use bevy::prelude::*;
-use bevy::render::mesh::Indices;
 use bevy_rapier3d::na::Point3;
-use bevy_rapier3d::prelude::Vect;
 use noise::NoiseFn;
 
-pub fn generate_chunk(chunk_coord: &ChunkCoord, game_settings: &GameSettings) -> ChunkData {
-    let chunk = generate_single_chunk(chunk_coord, game_settings);
-
-    let (indices, vertices) = chunk.render_indices_and_vertices();
-
-    // let mesh = Mesh3d(mesh_manager.add(render_mesh(&indices, &vertices)));
-    let mesh = render_mesh(&indices, &vertices);
-
-    // let mut block_transforms = vec![];
-    //
-    // for x in 0..CHUNK_SIZE {
-    //     for y in 0..CHUNK_HEIGHT {
-    //         for z in 0..CHUNK_SIZE {
-    //             if let Some(block) = chunk.get_block(&LocalVoxelBlockCoord(Point3::new(x, y, z))) {
-    //                 if !block.is_fully_surrounded && block.block_type != VoxelBlockType::Empty {
-    //                     let block_transform = Transform::from_xyz(
-    //                         (chunk_coord.x * CHUNK_SIZE + x) as f32,
-    //                         (CHUNK_HEIGHT + y) as f32,
-    //                         (chunk_coord.y * CHUNK_SIZE + z) as f32
-    //                     );
-    //
-    //                     block_transforms.push(block_transform);
-    //                     //commands.spawn(block_transform);
-    //                 }
-    //             }
-    //         }
-    //     }
-    // }
-
-    let v: Vec<Vect> = vertices
-        .iter()
-        .map(|(v, _, _)| Vec3::from_array(*v))
-        .collect();
-    let i: Vec<[u32; 3]> = match indices {
-        Indices::U16(_) => unimplemented!("Not used by the game"),
-        Indices::U32(indices) => indices
-            .chunks(3)
-            .map(|chunk| {
-                let mut vec: [u32; 3] = [0, 0, 0];
-
-                vec[0..3].clone_from_slice(&chunk[0..3]);
-
-                vec
-            })
-            .collect(),
-    };
+/// Layers skipped at once while looking for the terrain surface
+const SURFACE_STEP: usize = 4;
+
+pub fn generate_chunk(
+    chunk_coord: &ChunkCoord,
+    game_settings: &GameSettings,
+    block_registry: &BlockRegistry,
+    structures: &[StructureTemplate],
+) -> ChunkData {
+    let mut chunk = generate_single_chunk(chunk_coord, game_settings, block_registry);
+
+    place_structures(
+        &mut chunk,
+        *chunk_coord,
+        structures,
+        block_registry,
+        &game_settings.procedural.base_noise,
+        game_settings.procedural.sea_level,
+    );
+    chunk.compact();
+
+    // Cubic chunks below the highest possible terrain may be underground,
+    // their sky light comes from the chunks above once they are spawned
+    let max_height = game_settings.procedural.base_noise.max_value() as CoordSystemIntegerSize;
+    let open_sky = !CUBIC_CHUNKS || (chunk_coord.y + 1) * CHUNK_HEIGHT > max_height;
+    light_chunk(&mut chunk, block_registry, open_sky);
+    chunk.compact_light();
+
+    // Every section is meshed below, there is nothing left to re-mesh
+    chunk.take_dirty_sections();
+
+    let mut meshes = vec![];
+
+    for section in (0..SECTION_COUNT).filter(|section| !chunk.section(*section).is_empty()) {
+        for (section_mesh, section_indices, section_vertices) in chunk.render_section(
+            section,
+            // Neighbours are not known yet, the borders are re-meshed once the chunk is spawned
+            &ChunkNeighborhood::default(),
+            block_registry,
+            game_settings.render.mesher,
+        ) {
+            if section_vertices.is_empty() {
+                continue;
+            }
 
-    ChunkData {
-        mesh,
-        vertex: v,
-        indices: i,
-        chunk,
+            meshes.push((
+                section_mesh,
+                render_mesh(&section_indices, &section_vertices),
+            ));
+        }
     }
+
+    ChunkData { meshes, chunk }
 }
 
-pub fn generate_single_chunk<P>(coord: &P, game_settings: &GameSettings) -> VoxelChunk
+pub fn generate_single_chunk<P>(
+    coord: &P,
+    game_settings: &GameSettings,
+    block_registry: &BlockRegistry,
+) -> VoxelChunk
 where
     P: Into<ChunkCoord> + Clone,
 {
@@ -75,43 +84,65 @@ where
 
     let chunk_coord: ChunkCoord = (*coord).clone().into();
 
+    let block_id = |name: &str| block_registry.id(name).unwrap_or(BlockId::EMPTY);
+    let gem = block_id("gem");
+    let rock = block_id("rock");
+    let dirt = block_id("dirt");
+    let grass = block_id("grass");
+    let water = block_id("water");
+    let sea_level = game_settings.procedural.sea_level;
+
     let mut game_chunk = VoxelChunk::default();
 
     let mut min_value: f64 = 1000.0;
     let mut max_value: f64 = -1000.0;
 
+    let chunk_base = chunk_coord.y * CHUNK_HEIGHT;
+
+    // Layers above the highest possible terrain and the sea are left empty,
+    // which skips the upper sections and the chunks above the mountains
+    // altogether
+    let max_height = (height_noise.max_value() as CoordSystemIntegerSize).max(sea_level);
+    let generated_height = (max_height + 1 - chunk_base).clamp(0, CHUNK_HEIGHT);
+
     for x in 0..CHUNK_SIZE {
-        for y in 0..CHUNK_HEIGHT {
+        for y in 0..generated_height {
             for z in 0..CHUNK_SIZE {
                 let block_coord = LocalVoxelBlockCoord(Point3::new(x, y, z));
 
                 // https://www.reddit.com/r/proceduralgeneration/comments/6eubj7/how_can_i_add_octaves_persistence_lacunarity/
                 // New version of height map
+                let world_y = chunk_base + y;
+
                 let height: CoordSystemIntegerSize = height_noise.get([
                     (block_coord.x as f64) + (chunk_coord.x as f64 * CHUNK_SIZE as f64),
-                    (block_coord.y as f64),
-                    (block_coord.z as f64) + (chunk_coord.y as f64 * CHUNK_SIZE as f64),
+                    (world_y as f64),
+                    (block_coord.z as f64) + (chunk_coord.z as f64 * CHUNK_SIZE as f64),
                 ]) as CoordSystemIntegerSize;
 
                 let block_value = block_noise.get([
                     (block_coord.x as f64) + (chunk_coord.x as f64 * CHUNK_SIZE as f64),
-                    (block_coord.y as f64),
-                    (block_coord.z as f64) + (chunk_coord.y as f64 * CHUNK_SIZE as f64),
+                    (world_y as f64),
+                    (block_coord.z as f64) + (chunk_coord.z as f64 * CHUNK_SIZE as f64),
                 ]);
 
                 min_value = min_value.min(block_value);
                 max_value = max_value.max(block_value);
 
-                if height >= y {
-                    if let Some(block) = game_chunk.get_block_mut(&block_coord) {
-                        block.block_type = match block_value {
-                            0.40..0.41 => VoxelBlockType::Gem,
-                            0.41..0.60 => VoxelBlockType::Rock,
+                if height >= world_y {
+                    if let Some(mut block) = game_chunk.get_block_mut(&block_coord) {
+                        *block = block_registry.block(match block_value {
+                            0.40..0.41 => gem,
+                            0.41..0.60 => rock,
                             // 0.60..0.68 => GameBlockType::Empty,
-                            0.68..0.70 => VoxelBlockType::Dirt,
-                            0.70..1.0 => VoxelBlockType::Grass,
-                            _ => VoxelBlockType::Dirt,
-                        }
+                            0.68..0.70 => dirt,
+                            0.70..1.0 => grass,
+                            _ => dirt,
+                        })
+                    }
+                } else if world_y <= sea_level {
+                    if let Some(mut block) = game_chunk.get_block_mut(&block_coord) {
+                        *block = block_registry.block(water);
                     }
                 }
 
@@ -132,5 +163,35 @@ where
 
     // info!("perlin min: {}, max: {}", min_value, max_value);
 
+    game_chunk.compact();
+
     game_chunk
 }
+
+/// Height of the highest block of a column, or -1 when the column is empty,
+/// without generating its blocks. The column is walked down a few layers at a
+/// time from the highest possible terrain, so overhangs thinner than
+/// `SURFACE_STEP` may be missed.
+pub fn surface_height(
+    height_noise: &Noise,
+    x: CoordSystemIntegerSize,
+    z: CoordSystemIntegerSize,
+) -> CoordSystemIntegerSize {
+    // Same test as `generate_single_chunk`
+    let is_filled = |y: &CoordSystemIntegerSize| {
+        height_noise.get([x as f64, *y as f64, z as f64]) as CoordSystemIntegerSize >= *y
+    };
+
+    let max_height = match CUBIC_CHUNKS {
+        true => height_noise.max_value() as CoordSystemIntegerSize,
+        false => (height_noise.max_value() as CoordSystemIntegerSize).min(CHUNK_HEIGHT - 1),
+    };
+
+    match (0..=max_height).rev().step_by(SURFACE_STEP).find(is_filled) {
+        Some(y) => (y..=(y + SURFACE_STEP as CoordSystemIntegerSize - 1).min(max_height))
+            .rev()
+            .find(is_filled)
+            .unwrap_or(y),
+        None => -1,
+    }
+}
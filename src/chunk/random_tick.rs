@@ -0,0 +1,242 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::light::Light;
+use crate::chunk::registry::{BlockRegistry, RandomTick};
+use crate::game_world::coord::{
+    GlobalVoxelBlockCoord, LocalVoxelBlockCoord, LocalVoxelBlockOffset,
+};
+use crate::game_world::VoxelWorld;
+use crate::settings::{CoordSystemIntegerSize, GameSettingResource, SECTION_COUNT, SECTION_VOLUME};
+use bevy::prelude::*;
+
+/// Light level a dirt block needs above it for grass to spread onto it
+const GRASS_MIN_LIGHT: u8 = 9;
+
+/// Position of a block in world space
+type Position = [CoordSystemIntegerSize; 3];
+
+/// Pseudo-random numbers for picking the ticked blocks (splitmix64)
+#[derive(Debug)]
+pub struct TickRandom(u64);
+
+impl Default for TickRandom {
+    fn default() -> Self {
+        Self(0x9e37_79b9_7f4a_7c15)
+    }
+}
+
+impl TickRandom {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Between `min` and `max`, both included
+    fn between(
+        &mut self,
+        min: CoordSystemIntegerSize,
+        max: CoordSystemIntegerSize,
+    ) -> CoordSystemIntegerSize {
+        min + (self.next() % (max - min + 1) as u64) as CoordSystemIntegerSize
+    }
+}
+
+/// Blocks a block replaces when it gets a random tick. `target` is a random
+/// block near it, for the behaviours reaching out to the blocks around.
+pub fn random_tick_edits<B, L>(
+    registry: &BlockRegistry,
+    position: Position,
+    target: Position,
+    block_at: B,
+    light_at: L,
+) -> Vec<(Position, VoxelBlock)>
+where
+    B: Fn(Position) -> Option<VoxelBlock>,
+    L: Fn(Position) -> Option<Light>,
+{
+    let Some(block) = block_at(position) else {
+        return vec![];
+    };
+
+    match registry.random_tick(block.id) {
+        Some(RandomTick::Grass) => {
+            grass_tick(registry, block, position, target, block_at, light_at)
+        }
+        None => vec![],
+    }
+}
+
+/// Grass under an opaque block dies back to dirt. Otherwise, it spreads onto
+/// the target when it is dirt that is not covered and lit enough.
+fn grass_tick<B, L>(
+    registry: &BlockRegistry,
+    grass: VoxelBlock,
+    position: Position,
+    target: Position,
+    block_at: B,
+    light_at: L,
+) -> Vec<(Position, VoxelBlock)>
+where
+    B: Fn(Position) -> Option<VoxelBlock>,
+    L: Fn(Position) -> Option<Light>,
+{
+    let Some(dirt) = registry.id("dirt") else {
+        return vec![];
+    };
+
+    let above = |position: Position| [position[0], position[1] + 1, position[2]];
+    let is_covered = |position: Position| {
+        block_at(above(position)).is_some_and(|block| registry.is_opaque(block.id))
+    };
+
+    if is_covered(position) {
+        return vec![(position, registry.block(dirt))];
+    }
+
+    let grows = block_at(target).is_some_and(|block| block.id == dirt)
+        && !is_covered(target)
+        && light_at(above(target)).is_some_and(|light| light.level() >= GRASS_MIN_LIGHT);
+
+    match grows {
+        true => vec![(target, registry.block(grass.id))],
+        false => vec![],
+    }
+}
+
+/// Picks `random_ticks_per_section` blocks at random in every section of
+/// the loaded chunks, and lets them act. Sections without any block reacting
+/// to random ticks are skipped.
+pub fn random_block_ticks(
+    mut random: Local<TickRandom>,
+    mut voxel_world: VoxelWorld,
+    block_registry: Res<BlockRegistry>,
+    game_setting_resource: Res<GameSettingResource>,
+) {
+    let per_section = game_setting_resource
+        .settings
+        .world
+        .random_ticks_per_section;
+    let mut edits: Vec<(Position, VoxelBlock)> = vec![];
+
+    for (chunk_coord, chunk) in voxel_world.loaded_chunks() {
+        for section in 0..SECTION_COUNT {
+            if !chunk
+                .section(section)
+                .palette()
+                .iter()
+                .any(|block| block_registry.random_tick(block.id).is_some())
+            {
+                continue;
+            }
+
+            for _ in 0..per_section {
+                let offset = section * SECTION_VOLUME as usize
+                    + random.between(0, SECTION_VOLUME - 1) as usize;
+                let global = GlobalVoxelBlockCoord::from((
+                    chunk_coord,
+                    LocalVoxelBlockCoord::from(LocalVoxelBlockOffset(offset)),
+                ));
+                let position = [global.x, global.y, global.z];
+                let target = [
+                    position[0] + random.between(-1, 1),
+                    position[1] + random.between(-3, 1),
+                    position[2] + random.between(-1, 1),
+                ];
+
+                edits.extend(random_tick_edits(
+                    &block_registry,
+                    position,
+                    target,
+                    |at| voxel_world.get_block(GlobalVoxelBlockCoord::from(at)).ok(),
+                    |at| voxel_world.get_light(GlobalVoxelBlockCoord::from(at)).ok(),
+                ));
+            }
+        }
+    }
+
+    // The edits are on blocks read from loaded chunks
+    let edits = edits
+        .into_iter()
+        .map(|(position, block)| (GlobalVoxelBlockCoord::from(position), block));
+
+    if let Err(error) = voxel_world.set_blocks(edits) {
+        warn!("Random ticks could not be applied: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::light::{LightChannel, MAX_LIGHT};
+    use crate::chunk::registry::tests::game_block_registry;
+    use bevy::platform::collections::HashMap;
+
+    /// Grass at the origin, next to dirt in the open and dirt under rock
+    fn meadow(registry: &BlockRegistry) -> HashMap<Position, VoxelBlock> {
+        let block = |name: &str| registry.block(registry.id(name).unwrap());
+
+        HashMap::from([
+            ([0, 0, 0], block("grass")),
+            ([1, 0, 0], block("dirt")),
+            ([-1, 0, 0], block("dirt")),
+            ([-1, 1, 0], block("rock")),
+            ([0, -1, 0], block("dirt")),
+        ])
+    }
+
+    fn edits(
+        registry: &BlockRegistry,
+        blocks: &HashMap<Position, VoxelBlock>,
+        target: Position,
+        sky: u8,
+    ) -> Vec<(Position, VoxelBlock)> {
+        random_tick_edits(
+            registry,
+            [0, 0, 0],
+            target,
+            |at| Some(blocks.get(&at).copied().unwrap_or_default()),
+            |at| {
+                let level = if at[1] > 0 { sky } else { 0 };
+
+                Some(Light::default().with(LightChannel::Sky, level))
+            },
+        )
+    }
+
+    #[test]
+    fn grass_spreads_onto_lit_dirt() {
+        let registry = game_block_registry();
+        let blocks = meadow(&registry);
+        let grass = blocks[&[0, 0, 0]];
+
+        assert_eq!(
+            edits(&registry, &blocks, [1, 0, 0], MAX_LIGHT),
+            vec![([1, 0, 0], grass)]
+        );
+        // Too dark
+        assert!(edits(&registry, &blocks, [1, 0, 0], GRASS_MIN_LIGHT - 1).is_empty());
+        // Covered by the rock, or buried
+        assert!(edits(&registry, &blocks, [-1, 0, 0], MAX_LIGHT).is_empty());
+        assert!(edits(&registry, &blocks, [0, -1, 0], MAX_LIGHT).is_empty());
+        // Not dirt
+        assert!(edits(&registry, &blocks, [0, 1, 0], MAX_LIGHT).is_empty());
+    }
+
+    #[test]
+    fn covered_grass_turns_into_dirt() {
+        let registry = game_block_registry();
+        let mut blocks = meadow(&registry);
+        let rock = blocks[&[-1, 1, 0]];
+        blocks.insert([0, 1, 0], rock);
+
+        let dirt = registry.block(registry.id("dirt").unwrap());
+
+        assert_eq!(
+            edits(&registry, &blocks, [1, 0, 0], MAX_LIGHT),
+            vec![([0, 0, 0], dirt)]
+        );
+    }
+}
@@ -0,0 +1,623 @@
+use crate::chunk::block::{BlockAtlasExtension, BlockId, BlockMaterial, VoxelBlock, VoxelMaterial};
+use crate::chunk::light::MAX_LIGHT;
+use crate::chunk::state::{BlockStates, MAX_FLUID_LEVEL};
+use crate::settings::NoiseConfigurationChangedEvent;
+use crate::utils::UV;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use serde_derive::Deserialize;
+use std::sync::Arc;
+
+/// Content of `blocks.toml`
+#[derive(Debug, Deserialize, Clone)]
+pub struct BlockDefinitions {
+    pub atlas: AtlasLayout,
+    pub blocks: Vec<BlockDefinition>,
+}
+
+/// Layout of `atlas.png`: a grid of square tiles, numbered from left to right
+/// then from top to bottom
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct AtlasLayout {
+    pub columns: u16,
+    pub rows: u16,
+    /// Size of a tile, in pixels
+    pub tile_size: u16,
+}
+
+impl Default for AtlasLayout {
+    fn default() -> Self {
+        Self {
+            columns: 4,
+            rows: 3,
+            tile_size: 32,
+        }
+    }
+}
+
+impl AtlasLayout {
+    /// Size of a tile in UV units
+    pub fn tile_uv_size(&self) -> UV {
+        [1.0 / self.columns as f32, 1.0 / self.rows as f32]
+    }
+
+    /// UV of the top left corner of a tile
+    pub fn tile_origin(&self, tile: u16) -> UV {
+        let [width, height] = self.tile_uv_size();
+
+        [
+            (tile % self.columns) as f32 * width,
+            (tile / self.columns) as f32 * height,
+        ]
+    }
+
+    /// Half a texel, in tile units. Sampling is kept that far from the tile
+    /// edges so that it never bleeds into the neighbouring tiles.
+    pub fn half_texel_inset(&self) -> f32 {
+        0.5 / self.tile_size as f32
+    }
+
+    pub fn tile_count(&self) -> u16 {
+        self.columns * self.rows
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BlockDefinition {
+    pub id: BlockId,
+    pub name: String,
+    /// Solid blocks stop the entities moving through the world
+    pub solid: bool,
+    /// How the block is drawn, opaque by default
+    #[serde(default)]
+    pub render: RenderClass,
+    /// Level of the block light the block gives off, up to `MAX_LIGHT`
+    #[serde(default)]
+    pub emission: u8,
+    /// Falls when the block below it is empty, like sand
+    #[serde(default)]
+    pub falls: bool,
+    /// What the block does when it gets a random tick, nothing by default
+    pub random_tick: Option<RandomTick>,
+    pub textures: BlockTextures,
+    /// States the block supports, none by default
+    #[serde(default)]
+    pub states: BlockStates,
+    /// How long the block resists being broken
+    #[allow(dead_code)]
+    pub hardness: f32,
+}
+
+/// How the faces of a block are drawn. Each class gets its own mesh and
+/// material in every chunk section.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderClass {
+    /// Hides the faces of the blocks touching it
+    #[default]
+    Opaque,
+    /// Pixels of the texture are either fully opaque or discarded, like leaves
+    Cutout,
+    /// Blended with what is behind it, like glass or water. The faces
+    /// between two blocks of the same type are hidden.
+    Transparent,
+    /// Opaque, but drawn at full brightness whatever the lighting of the
+    /// scene, like glowing ores
+    Emissive,
+    /// Blended like transparent blocks, with a top that follows the fluid
+    /// level. Only for the blocks with a `fluid` state.
+    Fluid,
+}
+
+impl RenderClass {
+    pub const ALL: [RenderClass; 5] = [
+        RenderClass::Opaque,
+        RenderClass::Cutout,
+        RenderClass::Transparent,
+        RenderClass::Emissive,
+        RenderClass::Fluid,
+    ];
+}
+
+/// Behaviour of a block on the random ticks of the world tick, see
+/// `chunk::random_tick`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RandomTick {
+    /// Spreads onto the lit dirt around it, and turns into dirt once an
+    /// opaque block covers it
+    Grass,
+}
+
+/// Atlas tile of each face of a block. `top`, `bottom` and `side` fall back
+/// to `all`, `front` falls back to `side`.
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct BlockTextures {
+    pub all: u16,
+    pub top: Option<u16>,
+    pub bottom: Option<u16>,
+    pub side: Option<u16>,
+    /// Side facing the orientation of blocks with a horizontal orientation
+    pub front: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockFace {
+    Top,
+    Bottom,
+    Side,
+    Front,
+}
+
+impl BlockFace {
+    pub const ALL: [BlockFace; 4] = [
+        BlockFace::Top,
+        BlockFace::Bottom,
+        BlockFace::Side,
+        BlockFace::Front,
+    ];
+}
+
+impl BlockTextures {
+    pub fn tile(&self, face: BlockFace) -> u16 {
+        match face {
+            BlockFace::Top => self.top,
+            BlockFace::Bottom => self.bottom,
+            BlockFace::Side => self.side,
+            BlockFace::Front => self.front.or(self.side),
+        }
+        .unwrap_or(self.all)
+    }
+}
+
+/// Every block type of the game, indexed by `BlockId`, and the atlas layout
+/// their textures refer to. It is cheap to clone so that it can be handed to
+/// the chunk generation tasks.
+///
+/// The registry only knows the empty block until `blocks.toml` is loaded.
+#[derive(Resource, Asset, TypePath, Clone, Debug)]
+pub struct BlockRegistry {
+    blocks: Arc<Vec<BlockDefinition>>,
+    atlas: AtlasLayout,
+}
+
+impl Default for BlockRegistry {
+    fn default() -> Self {
+        Self {
+            blocks: Arc::new(vec![empty_block_definition()]),
+            atlas: AtlasLayout::default(),
+        }
+    }
+}
+
+impl BlockRegistry {
+    pub fn from_definitions(
+        definitions: BlockDefinitions,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut blocks: Vec<Option<BlockDefinition>> = vec![Some(empty_block_definition())];
+        let mut names: HashMap<String, BlockId> = HashMap::new();
+        names.insert(empty_block_definition().name, BlockId::EMPTY);
+
+        let atlas = definitions.atlas;
+
+        if atlas.columns.checked_mul(atlas.rows).is_none() {
+            return Err(format!("the atlas can hold at most {} tiles", u16::MAX).into());
+        }
+
+        if atlas.tile_count() == 0 || atlas.tile_size == 0 {
+            return Err("the atlas must have at least one tile".into());
+        }
+
+        for definition in definitions.blocks {
+            let index = definition.id.0 as usize;
+
+            let growth_stages = definition.states.growth_stages.unwrap_or(1);
+            if !(1..=16).contains(&growth_stages) {
+                return Err(format!(
+                    "block `{}` has {} growth stages, it must be between 1 and 16",
+                    definition.name, growth_stages
+                )
+                .into());
+            }
+
+            // Each growth stage uses the tile after the previous one. A tile
+            // index past u16 is past the atlas too.
+            let textures = definition.textures;
+            let last_tiles = BlockFace::ALL.map(|face| {
+                u16::from(growth_stages)
+                    .checked_sub(1)
+                    .and_then(|stages| textures.tile(face).checked_add(stages))
+            });
+            if last_tiles
+                .iter()
+                .any(|tile| tile.is_none_or(|tile| tile >= atlas.tile_count()))
+            {
+                return Err(format!(
+                    "block `{}` uses a tile past the {} tiles of the atlas",
+                    definition.name,
+                    atlas.tile_count()
+                )
+                .into());
+            }
+
+            match (definition.render, definition.states.fluid) {
+                (RenderClass::Fluid, Some(1..=MAX_FLUID_LEVEL)) => {}
+                (RenderClass::Fluid, _) => {
+                    return Err(format!(
+                        "fluid `{}` must flow between 1 and {} blocks",
+                        definition.name, MAX_FLUID_LEVEL
+                    )
+                    .into());
+                }
+                (_, Some(_)) => {
+                    return Err(format!(
+                        "block `{}` has a fluid state but is not rendered as a fluid",
+                        definition.name
+                    )
+                    .into());
+                }
+                (_, None) => {}
+            }
+
+            if definition.emission > MAX_LIGHT {
+                return Err(format!(
+                    "block `{}` has an emission of {}, it must be at most {}",
+                    definition.name, definition.emission, MAX_LIGHT
+                )
+                .into());
+            }
+
+            if definition.id == BlockId::EMPTY {
+                return Err(
+                    format!("block id 0 is reserved, used by `{}`", definition.name).into(),
+                );
+            }
+
+            if let Some(other) = names.insert(definition.name.clone(), definition.id) {
+                return Err(format!(
+                    "block `{}` is defined twice, with ids {} and {}",
+                    definition.name, other.0, definition.id.0
+                )
+                .into());
+            }
+
+            if blocks.len() <= index {
+                blocks.resize(index + 1, None);
+            }
+
+            if let Some(other) = &blocks[index] {
+                return Err(format!(
+                    "blocks `{}` and `{}` share the id {}",
+                    other.name, definition.name, definition.id.0
+                )
+                .into());
+            }
+
+            blocks[index] = Some(definition);
+        }
+
+        if let Some(grass) = blocks.iter().flatten().find(|definition| {
+            definition.random_tick == Some(RandomTick::Grass) && !names.contains_key("dirt")
+        }) {
+            return Err(format!(
+                "block `{}` spreads like grass, which needs a `dirt` block",
+                grass.name
+            )
+            .into());
+        }
+
+        let blocks = blocks
+            .into_iter()
+            .enumerate()
+            .map(|(index, definition)| {
+                definition.ok_or_else(|| format!("no block is defined for the id {}", index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            blocks: Arc::new(blocks),
+            atlas,
+        })
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&BlockDefinition> {
+        self.blocks.get(id.0 as usize)
+    }
+
+    pub fn atlas(&self) -> &AtlasLayout {
+        &self.atlas
+    }
+
+    /// Looks a block up by the name it has in `blocks.toml`
+    pub fn id(&self, name: &str) -> Option<BlockId> {
+        self.blocks
+            .iter()
+            .find(|definition| definition.name == name)
+            .map(|definition| definition.id)
+    }
+
+    /// True once `blocks.toml` has been loaded
+    pub fn is_loaded(&self) -> bool {
+        self.blocks.len() > 1
+    }
+
+    /// A block of the given type in its default state
+    pub fn block(&self, id: BlockId) -> VoxelBlock {
+        VoxelBlock {
+            id,
+            state: self
+                .get(id)
+                .map(|definition| definition.states.default_state())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Whether the state of a block is one its type supports
+    pub fn is_valid(&self, block: VoxelBlock) -> bool {
+        self.get(block.id)
+            .is_some_and(|definition| definition.states.allows(block.state))
+    }
+
+    pub fn render_class(&self, id: BlockId) -> RenderClass {
+        self.get(id)
+            .map(|definition| definition.render)
+            .unwrap_or(RenderClass::Transparent)
+    }
+
+    /// Whether a block is part of the chunk colliders
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|definition| definition.solid)
+    }
+
+    /// Whether a block hides the faces of the blocks touching it
+    pub fn is_opaque(&self, id: BlockId) -> bool {
+        id != BlockId::EMPTY
+            && matches!(
+                self.render_class(id),
+                RenderClass::Opaque | RenderClass::Emissive
+            )
+    }
+
+    /// Level of the block light a block gives off
+    pub fn emission(&self, id: BlockId) -> u8 {
+        self.get(id).map_or(0, |definition| definition.emission)
+    }
+
+    /// Whether a block falls when the block below it is empty
+    pub fn falls(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|definition| definition.falls)
+    }
+
+    pub fn random_tick(&self, id: BlockId) -> Option<RandomTick> {
+        self.get(id).and_then(|definition| definition.random_tick)
+    }
+
+    /// Number of blocks a fluid flows sideways, `None` for the other blocks
+    pub fn fluid_flow(&self, id: BlockId) -> Option<u8> {
+        self.get(id).and_then(|definition| definition.states.fluid)
+    }
+}
+
+fn empty_block_definition() -> BlockDefinition {
+    BlockDefinition {
+        id: BlockId::EMPTY,
+        name: "empty".to_string(),
+        solid: false,
+        render: RenderClass::Transparent,
+        emission: 0,
+        falls: false,
+        random_tick: None,
+        textures: BlockTextures {
+            all: 0,
+            top: None,
+            bottom: None,
+            side: None,
+            front: None,
+        },
+        states: BlockStates::default(),
+        hardness: 0.0,
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct BlockRegistryHandle {
+    pub handle: Handle<BlockRegistry>,
+}
+
+pub fn load_block_registry(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let handle: Handle<BlockRegistry> = asset_server.load("blocks.toml");
+
+    commands.insert_resource(BlockRegistryHandle { handle });
+}
+
+/// Chunks store block ids, so the whole world is generated again when the
+/// block definitions change. The block material follows the atlas layout.
+pub fn listen_to_block_registry_loaded(
+    mut ev_asset: EventReader<AssetEvent<BlockRegistry>>,
+    mut block_registry: ResMut<BlockRegistry>,
+    block_registry_handle: Res<BlockRegistryHandle>,
+    block_registry_assets: Res<Assets<BlockRegistry>>,
+    block_material: Res<BlockMaterial>,
+    mut materials: ResMut<Assets<VoxelMaterial>>,
+    mut event_writer: EventWriter<NoiseConfigurationChangedEvent>,
+) {
+    for ev in ev_asset.read() {
+        match ev {
+            AssetEvent::Added { id } | AssetEvent::Modified { id }
+                if block_registry_handle.handle.id() == *id =>
+            {
+                if let Some(registry) = block_registry_assets.get(*id) {
+                    info!("Loaded {} block definitions", registry.blocks.len());
+
+                    *block_registry = registry.clone();
+
+                    for class in RenderClass::ALL {
+                        if let Some(material) = materials.get_mut(block_material.get(class)) {
+                            material.extension = BlockAtlasExtension::from(registry.atlas());
+                        }
+                    }
+                }
+
+                event_writer.write(NoiseConfigurationChangedEvent);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct BlockRegistryLoader;
+
+impl AssetLoader for BlockRegistryLoader {
+    type Asset = BlockRegistry;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<BlockRegistry, Self::Error> {
+        info!("Loading block definitions");
+
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        let text = std::str::from_utf8(&bytes)?;
+
+        let definitions: BlockDefinitions = toml::from_str(text)?;
+
+        BlockRegistry::from_definitions(definitions)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["blocks.toml"]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chunk::light::light_chunk;
+    use crate::chunk::voxel_chunk::VoxelChunk;
+    use crate::game_world::coord::LocalVoxelBlockCoord;
+    use crate::settings::{CoordSystemIntegerSize, CHUNK_HEIGHT, CHUNK_SIZE};
+    use bevy_rapier3d::na::Point3;
+
+    /// The registry of the game, as defined in the assets
+    pub fn game_block_registry() -> BlockRegistry {
+        let definitions: BlockDefinitions =
+            toml::from_str(include_str!("../../assets/blocks.toml")).unwrap();
+
+        BlockRegistry::from_definitions(definitions).unwrap()
+    }
+
+    /// A lit chunk with the named blocks, `None` leaving the block empty
+    pub fn chunk_with<F>(is_filled: F) -> VoxelChunk
+    where
+        F: Fn(
+            CoordSystemIntegerSize,
+            CoordSystemIntegerSize,
+            CoordSystemIntegerSize,
+        ) -> Option<&'static str>,
+    {
+        let registry = game_block_registry();
+        let mut chunk = VoxelChunk::default();
+
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_HEIGHT {
+                for z in 0..CHUNK_SIZE {
+                    if let Some(name) = is_filled(x, y, z) {
+                        let id = registry.id(name).unwrap();
+
+                        chunk.update_block(&LocalVoxelBlockCoord(Point3::new(x, y, z)), |block| {
+                            *block = registry.block(id)
+                        });
+                    }
+                }
+            }
+        }
+
+        light_chunk(&mut chunk, &registry, true);
+
+        chunk
+    }
+
+    #[test]
+    fn game_blocks_are_valid() {
+        let registry = game_block_registry();
+
+        assert!(registry.is_loaded());
+        assert_eq!(registry.id("empty"), Some(BlockId::EMPTY));
+
+        for name in ["rock", "grass", "gem", "dirt", "sand"] {
+            let id = registry.id(name).unwrap();
+
+            assert_eq!(registry.get(id).unwrap().name, name);
+            assert!(registry.is_opaque(id));
+        }
+
+        assert!(!registry.is_opaque(BlockId::EMPTY));
+
+        let gem = registry.id("gem").unwrap();
+        assert_eq!(registry.render_class(gem), RenderClass::Emissive);
+        assert!(registry.emission(gem) > 0);
+        assert_eq!(registry.emission(registry.id("rock").unwrap()), 0);
+        assert!(registry.falls(registry.id("sand").unwrap()));
+        assert!(!registry.falls(registry.id("dirt").unwrap()));
+
+        for (name, class) in [
+            ("glass", RenderClass::Transparent),
+            ("leaves", RenderClass::Cutout),
+            ("water", RenderClass::Fluid),
+        ] {
+            let id = registry.id(name).unwrap();
+
+            assert_eq!(registry.render_class(id), class);
+            assert!(!registry.is_opaque(id));
+        }
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        let parse = |text: &str| {
+            let text = format!("[atlas]\ncolumns = 4\nrows = 1\ntile_size = 32\n{text}");
+
+            BlockRegistry::from_definitions(toml::from_str::<BlockDefinitions>(&text).unwrap())
+        };
+        let block = |id: u16, name: &str| {
+            format!(
+                "[[blocks]]\nid = {id}\nname = \"{name}\"\nsolid = true\nhardness = 1.0\ntextures = {{ all = 0 }}\n"
+            )
+        };
+
+        assert!(parse(&block(1, "rock")).is_ok());
+        assert!(parse(&block(0, "rock")).is_err());
+        assert!(parse(&(block(1, "rock") + &block(1, "dirt"))).is_err());
+        assert!(parse(&(block(1, "rock") + &block(2, "rock"))).is_err());
+        assert!(parse(&block(2, "rock")).is_err());
+        assert!(parse(&block(1, "rock").replace("all = 0", "all = 4")).is_err());
+        assert!(parse(&(block(1, "lamp") + "emission = 15\n")).is_ok());
+        assert!(parse(&(block(1, "lamp") + "emission = 16\n")).is_err());
+        assert!(parse(&(block(1, "grass") + "random_tick = \"grass\"\n")).is_err());
+        assert!(
+            parse(&(block(1, "grass") + "random_tick = \"grass\"\n" + &block(2, "dirt"))).is_ok()
+        );
+
+        let fluid = block(1, "water") + "render = \"fluid\"\n";
+        assert!(parse(&(fluid.clone() + "states = { fluid = 7 }\n")).is_ok());
+        assert!(parse(&fluid).is_err());
+        assert!(parse(&(fluid + "states = { fluid = 8 }\n")).is_err());
+        assert!(parse(&(block(1, "rock") + "states = { fluid = 7 }\n")).is_err());
+
+        // The last growth stage uses tile 3 + 2
+        let crop =
+            block(1, "wheat").replace("all = 0", "all = 3") + "states = { growth_stages = 3 }\n";
+        assert!(parse(&crop).is_err());
+        assert!(parse(&crop.replace("all = 3", "all = 1")).is_ok());
+        // Out of range of a tile index, not only of the atlas
+        assert!(parse(&crop.replace("all = 3", "all = 65535")).is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::settings::CoordSystemIntegerSize;
+use serde_derive::Deserialize;
+
+const ORIENTATION_MASK: u16 = 0b111;
+const GROWTH_SHIFT: u16 = 3;
+const GROWTH_MASK: u16 = 0b1111;
+const OPEN_SHIFT: u16 = 7;
+const LEVEL_SHIFT: u16 = 8;
+const LEVEL_MASK: u16 = 0b111;
+const FALLING_SHIFT: u16 = 11;
+
+/// Highest fluid level, the one of the blocks furthest from the source
+pub const MAX_FLUID_LEVEL: u8 = 7;
+
+/// Metadata stored next to the id of each block, packed in 16 bits:
+///
+/// - bits 0..3: `Orientation`
+/// - bits 3..7: growth stage
+/// - bit 7: open or closed
+/// - bits 8..11: fluid level
+/// - bit 11: falling fluid
+///
+/// Which of them a block uses is declared by its `BlockStates` in `blocks.toml`.
+/// The default state is oriented up, at the first growth stage, closed and
+/// a fluid source.
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BlockState(pub u16);
+
+impl BlockState {
+    pub fn orientation(&self) -> Orientation {
+        Orientation::ALL
+            .get((self.0 & ORIENTATION_MASK) as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn with_orientation(self, orientation: Orientation) -> Self {
+        Self((self.0 & !ORIENTATION_MASK) | orientation as u16)
+    }
+
+    pub fn growth(&self) -> u8 {
+        ((self.0 >> GROWTH_SHIFT) & GROWTH_MASK) as u8
+    }
+
+    pub fn with_growth(self, growth: u8) -> Self {
+        Self(
+            (self.0 & !(GROWTH_MASK << GROWTH_SHIFT))
+                | ((growth as u16 & GROWTH_MASK) << GROWTH_SHIFT),
+        )
+    }
+
+    pub fn is_open(&self) -> bool {
+        (self.0 >> OPEN_SHIFT) & 1 == 1
+    }
+
+    pub fn with_open(self, open: bool) -> Self {
+        Self((self.0 & !(1 << OPEN_SHIFT)) | ((open as u16) << OPEN_SHIFT))
+    }
+
+    /// Distance of a fluid block from its source, 0 for the source itself
+    pub fn fluid_level(&self) -> u8 {
+        ((self.0 >> LEVEL_SHIFT) & LEVEL_MASK) as u8
+    }
+
+    pub fn with_fluid_level(self, level: u8) -> Self {
+        Self((self.0 & !(LEVEL_MASK << LEVEL_SHIFT)) | ((level as u16 & LEVEL_MASK) << LEVEL_SHIFT))
+    }
+
+    /// Whether a fluid block is fed by the fluid above it
+    pub fn is_falling(&self) -> bool {
+        (self.0 >> FALLING_SHIFT) & 1 == 1
+    }
+
+    pub fn with_falling(self, falling: bool) -> Self {
+        Self((self.0 & !(1 << FALLING_SHIFT)) | ((falling as u16) << FALLING_SHIFT))
+    }
+
+    /// A fluid source stays where it is, the other fluid blocks flow from one
+    pub fn is_fluid_source(&self) -> bool {
+        self.fluid_level() == 0 && !self.is_falling()
+    }
+}
+
+/// Direction a block is turned toward
+#[derive(Default, Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Orientation {
+    #[default]
+    Up = 0,
+    Down = 1,
+    North = 2,
+    South = 3,
+    East = 4,
+    West = 5,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 6] = [
+        Orientation::Up,
+        Orientation::Down,
+        Orientation::North,
+        Orientation::South,
+        Orientation::East,
+        Orientation::West,
+    ];
+
+    /// Unit vector of the direction. North is toward -z and east toward +x.
+    pub fn normal(&self) -> [CoordSystemIntegerSize; 3] {
+        match self {
+            Orientation::Up => [0, 1, 0],
+            Orientation::Down => [0, -1, 0],
+            Orientation::North => [0, 0, -1],
+            Orientation::South => [0, 0, 1],
+            Orientation::East => [1, 0, 0],
+            Orientation::West => [-1, 0, 0],
+        }
+    }
+
+    pub fn is_horizontal(&self) -> bool {
+        !matches!(self, Orientation::Up | Orientation::Down)
+    }
+
+    pub fn from_normal(normal: [CoordSystemIntegerSize; 3]) -> Option<Self> {
+        Orientation::ALL
+            .into_iter()
+            .find(|orientation| orientation.normal() == normal)
+    }
+}
+
+/// How a block can be oriented
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrientationKind {
+    /// Along one of the 3 axes, like logs. `Up`, `East` and `South` stand
+    /// for the y, x and z axes.
+    Axis,
+    /// Toward one of the 4 horizontal directions, like stairs or furnaces.
+    /// The front face is the one looking toward the orientation.
+    Horizontal,
+    /// Toward any of the 6 directions
+    All,
+}
+
+impl OrientationKind {
+    pub fn allows(&self, orientation: Orientation) -> bool {
+        match self {
+            OrientationKind::Axis => matches!(
+                orientation,
+                Orientation::Up | Orientation::East | Orientation::South
+            ),
+            OrientationKind::Horizontal => orientation.is_horizontal(),
+            OrientationKind::All => true,
+        }
+    }
+
+    /// Orientation of the kind pointing the same way. Blocks along an axis
+    /// do not tell its two directions apart.
+    pub fn fit(&self, orientation: Orientation) -> Orientation {
+        match (self, orientation) {
+            (OrientationKind::Axis, Orientation::Down) => Orientation::Up,
+            (OrientationKind::Axis, Orientation::West) => Orientation::East,
+            (OrientationKind::Axis, Orientation::North) => Orientation::South,
+            _ => orientation,
+        }
+    }
+
+    /// Orientation of a block that was just placed
+    pub fn default_orientation(&self) -> Orientation {
+        match self {
+            OrientationKind::Horizontal => Orientation::North,
+            _ => Orientation::Up,
+        }
+    }
+}
+
+/// States a block supports, declared in `blocks.toml`. A block without any
+/// only uses the default state.
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct BlockStates {
+    pub orientation: Option<OrientationKind>,
+    /// Number of growth stages, at most 16. Each stage uses the tile after
+    /// the one of the previous stage.
+    pub growth_stages: Option<u8>,
+    /// Whether the block can be opened and closed, like a door
+    #[serde(default)]
+    pub open: bool,
+    /// Fluids flow this many blocks sideways from their source, at most
+    /// `MAX_FLUID_LEVEL`
+    pub fluid: Option<u8>,
+}
+
+impl BlockStates {
+    pub fn default_state(&self) -> BlockState {
+        let orientation = self
+            .orientation
+            .map(|kind| kind.default_orientation())
+            .unwrap_or_default();
+
+        BlockState::default().with_orientation(orientation)
+    }
+
+    /// Whether every part of the state is one the block supports
+    pub fn allows(&self, state: BlockState) -> bool {
+        let orientation = match self.orientation {
+            Some(kind) => kind.allows(state.orientation()),
+            None => state.orientation() == Orientation::default(),
+        };
+
+        let fluid = match self.fluid {
+            Some(flow) => state.fluid_level() <= flow,
+            None => state.fluid_level() == 0 && !state.is_falling(),
+        };
+
+        orientation
+            && state.growth() < self.growth_stages.unwrap_or(1)
+            && (self.open || !state.is_open())
+            && fluid
+            && state == pack(state)
+    }
+}
+
+/// Keeps only the bits used by the state, to detect garbage in the others
+fn pack(state: BlockState) -> BlockState {
+    BlockState::default()
+        .with_orientation(state.orientation())
+        .with_growth(state.growth())
+        .with_open(state.is_open())
+        .with_fluid_level(state.fluid_level())
+        .with_falling(state.is_falling())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::block::{BlockId, VoxelBlock};
+
+    #[test]
+    fn state_fields_are_independent() {
+        let state = BlockState::default()
+            .with_orientation(Orientation::West)
+            .with_growth(11)
+            .with_open(true)
+            .with_fluid_level(MAX_FLUID_LEVEL)
+            .with_falling(true);
+
+        assert_eq!(state.orientation(), Orientation::West);
+        assert_eq!(state.growth(), 11);
+        assert!(state.is_open());
+        assert_eq!(state.fluid_level(), MAX_FLUID_LEVEL);
+        assert!(state.is_falling());
+
+        let state = state.with_growth(2).with_open(false).with_fluid_level(3);
+
+        assert_eq!(state.orientation(), Orientation::West);
+        assert_eq!(state.growth(), 2);
+        assert!(!state.is_open());
+        assert_eq!(state.fluid_level(), 3);
+        assert!(state.is_falling());
+        assert!(BlockState::default().is_fluid_source());
+    }
+
+    #[test]
+    fn block_and_state_are_packed_together() {
+        let block = VoxelBlock {
+            id: BlockId(513),
+            state: BlockState::default()
+                .with_orientation(Orientation::South)
+                .with_growth(7),
+        };
+
+        assert_eq!(VoxelBlock::from_raw(block.to_raw()), block);
+        assert_eq!(block.to_raw() & 0xffff, 513);
+    }
+
+    #[test]
+    fn blocks_only_allow_the_states_they_declare() {
+        let plain = BlockStates::default();
+        let log = BlockStates {
+            orientation: Some(OrientationKind::Axis),
+            ..Default::default()
+        };
+        let crop = BlockStates {
+            growth_stages: Some(4),
+            ..Default::default()
+        };
+
+        assert!(plain.allows(BlockState::default()));
+        assert!(!plain.allows(BlockState::default().with_open(true)));
+        assert!(!plain.allows(BlockState(0xff00)));
+
+        assert!(log.allows(BlockState::default().with_orientation(Orientation::East)));
+        assert!(!log.allows(BlockState::default().with_orientation(Orientation::West)));
+
+        assert!(crop.allows(BlockState::default().with_growth(3)));
+        assert!(!crop.allows(BlockState::default().with_growth(4)));
+
+        let furnace = BlockStates {
+            orientation: Some(OrientationKind::Horizontal),
+            ..Default::default()
+        };
+        assert!(furnace.allows(furnace.default_state()));
+
+        let water = BlockStates {
+            fluid: Some(5),
+            ..Default::default()
+        };
+        assert!(water.allows(BlockState::default().with_fluid_level(5)));
+        assert!(!water.allows(BlockState::default().with_fluid_level(6)));
+        assert!(water.allows(BlockState::default().with_falling(true)));
+        assert!(!plain.allows(BlockState::default().with_falling(true)));
+    }
+}
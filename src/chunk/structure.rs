@@ -0,0 +1,322 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::noise::Noise;
+use crate::chunk::procedural::surface_height;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::vox::VoxModel;
+use crate::chunk::voxel_chunk::VoxelChunk;
+use crate::game_world::coord::{ChunkCoord, GlobalVoxelBlockCoord, LocalVoxelBlockCoord};
+use crate::region_edit::clipboard::{transform_block, Mirror, Rotation};
+use crate::settings::{
+    CoordSystemIntegerSize, GameSettingResource, NoiseConfigurationChangedEvent, CHUNK_SIZE,
+};
+use bevy::prelude::*;
+use std::sync::Arc;
+
+/// Position of a block in a model, from its lowest corner
+type Position = [CoordSystemIntegerSize; 3];
+
+/// Model the world generator places on the terrain
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructureTemplate {
+    size: Position,
+    blocks: Vec<(Position, VoxelBlock)>,
+    /// Chance for a chunk column to get one
+    chance: f64,
+}
+
+impl StructureTemplate {
+    pub fn new(model: &VoxModel, registry: &BlockRegistry, chance: f64) -> Self {
+        Self {
+            size: model.size(),
+            blocks: model.blocks(registry),
+            chance,
+        }
+    }
+}
+
+/// Templates of the `structures` of the `[procedural]` settings whose model
+/// is loaded. They are shared with the generation tasks.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct StructureTemplates(pub Arc<Vec<StructureTemplate>>);
+
+/// Models of the `structures` settings, in the same order
+#[derive(Resource, Debug, Default)]
+pub struct StructureModels {
+    paths: Vec<String>,
+    handles: Vec<Handle<VoxModel>>,
+}
+
+impl StructureModels {
+    pub fn handles(&self) -> &[Handle<VoxModel>] {
+        &self.handles
+    }
+}
+
+/// Where a structure is placed in a chunk column
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placement {
+    /// Lowest corner of the structure
+    origin: GlobalVoxelBlockCoord,
+    rotation: Rotation,
+}
+
+/// Pseudo-random number of a chunk column, the same every time the world is
+/// generated with the same seed (splitmix64)
+fn column_random(seed: u32, template: usize, column: [CoordSystemIntegerSize; 2]) -> u64 {
+    let mut z = (seed as u64)
+        ^ (template as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (column[0] as u32 as u64).wrapping_mul(0xbf58_476d_1ce4_e5b9)
+        ^ ((column[1] as u32 as u64) << 32);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Where the chunk column places the structure, if it gets one. It stands on
+/// the terrain, and is left out when the terrain is under the sea.
+fn placement(
+    template: &StructureTemplate,
+    index: usize,
+    column: [CoordSystemIntegerSize; 2],
+    height_noise: &Noise,
+    sea_level: CoordSystemIntegerSize,
+) -> Option<Placement> {
+    let random = column_random(height_noise.seed(), index, column);
+
+    if (random % 1_000_000) as f64 / 1_000_000.0 >= template.chance {
+        return None;
+    }
+
+    let x = column[0] * CHUNK_SIZE + ((random >> 20) % CHUNK_SIZE as u64) as CoordSystemIntegerSize;
+    let z = column[1] * CHUNK_SIZE + ((random >> 28) % CHUNK_SIZE as u64) as CoordSystemIntegerSize;
+    let rotation = Rotation::ALL[(random >> 36) as usize % 4];
+    let ground = surface_height(height_noise, x, z);
+
+    (ground >= sea_level.max(0)).then_some(Placement {
+        origin: GlobalVoxelBlockCoord::new(x, ground + 1, z),
+        rotation,
+    })
+}
+
+/// Puts the blocks of the structures overlapping the chunk in it. A
+/// structure belongs to the chunk column of its lowest corner, so the columns
+/// before this one are looked at too for the structures reaching into it.
+pub fn place_structures(
+    chunk: &mut VoxelChunk,
+    chunk_coord: ChunkCoord,
+    templates: &[StructureTemplate],
+    registry: &BlockRegistry,
+    height_noise: &Noise,
+    sea_level: CoordSystemIntegerSize,
+) {
+    for (index, template) in templates.iter().enumerate() {
+        let reach = (template.size[0].max(template.size[2]) + CHUNK_SIZE - 2) / CHUNK_SIZE;
+
+        for column_x in chunk_coord.x - reach..=chunk_coord.x {
+            for column_z in chunk_coord.z - reach..=chunk_coord.z {
+                let Some(placement) = placement(
+                    template,
+                    index,
+                    [column_x, column_z],
+                    height_noise,
+                    sea_level,
+                ) else {
+                    continue;
+                };
+
+                for (position, block) in template.blocks.iter() {
+                    let coord = placement
+                        .origin
+                        .offset(placement.rotation.apply(*position, template.size));
+                    let (block_chunk, block_coord): (ChunkCoord, LocalVoxelBlockCoord) =
+                        coord.into();
+
+                    if block_chunk != chunk_coord {
+                        continue;
+                    }
+
+                    if let Some(mut current) = chunk.get_block_mut(&block_coord) {
+                        *current =
+                            transform_block(*block, placement.rotation, Mirror::None, registry);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Loads the models of the `structures` settings when they change
+pub fn load_structure_models(
+    asset_server: Res<AssetServer>,
+    game_setting_resource: Res<GameSettingResource>,
+    mut structure_models: ResMut<StructureModels>,
+) {
+    let structures = &game_setting_resource.settings.procedural.structures;
+
+    if structures
+        .iter()
+        .map(|structure| &structure.model)
+        .eq(structure_models.paths.iter())
+    {
+        return;
+    }
+
+    structure_models.paths = structures
+        .iter()
+        .map(|structure| structure.model.clone())
+        .collect();
+    structure_models.handles = structures
+        .iter()
+        .map(|structure| asset_server.load(&structure.model))
+        .collect();
+}
+
+/// Builds the templates again when a model, the settings or the block
+/// definitions change. The world is generated again when they differ.
+pub fn update_structure_templates(
+    mut ev_asset: EventReader<AssetEvent<VoxModel>>,
+    mut structure_templates: ResMut<StructureTemplates>,
+    mut event_writer: EventWriter<NoiseConfigurationChangedEvent>,
+    structure_models: Res<StructureModels>,
+    models: Res<Assets<VoxModel>>,
+    block_registry: Res<BlockRegistry>,
+    game_setting_resource: Res<GameSettingResource>,
+) {
+    let models_changed = ev_asset.read().any(|ev| match ev {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => structure_models
+            .handles
+            .iter()
+            .any(|handle| handle.id() == *id),
+        _ => false,
+    });
+
+    if !models_changed
+        && !block_registry.is_changed()
+        && !structure_models.is_changed()
+        && !game_setting_resource.is_changed()
+    {
+        return;
+    }
+
+    let templates: Vec<StructureTemplate> = structure_models
+        .handles
+        .iter()
+        .zip(game_setting_resource.settings.procedural.structures.iter())
+        .filter_map(|(handle, structure)| {
+            models
+                .get(handle)
+                .map(|model| StructureTemplate::new(model, &block_registry, structure.chance))
+        })
+        .collect();
+
+    if *structure_templates.0 == templates {
+        return;
+    }
+
+    structure_templates.0 = Arc::new(templates);
+    event_writer.write(NoiseConfigurationChangedEvent);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::game_block_registry;
+    use crate::chunk::vox::parse_vox;
+    use crate::chunk::vox::tests::{vox_file, vox_palette};
+    use crate::settings::tests::game_settings;
+    use crate::settings::{CHUNK_HEIGHT, CUBIC_CHUNKS};
+    use bevy_rapier3d::na::Point3;
+
+    /// A wall of rock 20 blocks long, 1 deep and 2 high, in every column
+    fn wall() -> StructureTemplate {
+        let voxels: Vec<[u8; 4]> = (0..20).flat_map(|x| [[x, 0, 0, 1], [x, 0, 1, 1]]).collect();
+        let model = parse_vox(&vox_file([20, 1, 2], &voxels), &vox_palette()).unwrap();
+
+        StructureTemplate::new(&model, &game_block_registry(), 1.0)
+    }
+
+    #[test]
+    fn structures_are_placed_the_same_way_every_time() {
+        let noise = game_settings().procedural.base_noise;
+        let template = wall();
+
+        let placements: Vec<_> = (0..20)
+            .map(|x| placement(&template, 0, [x, 3], &noise, 0))
+            .collect();
+
+        assert!(placements.iter().all(Option::is_some));
+        assert_eq!(
+            placements,
+            (0..20)
+                .map(|x| placement(&template, 0, [x, 3], &noise, 0))
+                .collect::<Vec<_>>()
+        );
+        // Not all in the same place nor turned the same way
+        let rotations: Vec<Rotation> = placements
+            .iter()
+            .map(|placement| placement.unwrap().rotation)
+            .collect();
+        assert!(rotations.iter().any(|rotation| *rotation != rotations[0]));
+
+        // Never placed under the sea
+        assert!(placement(&template, 0, [0, 3], &noise, 1000).is_none());
+    }
+
+    #[test]
+    fn structures_start_in_their_own_column() {
+        let noise = game_settings().procedural.base_noise;
+        let template = wall();
+
+        for column_x in -10..10 {
+            for column_z in -10..10 {
+                let Placement { origin, .. } =
+                    placement(&template, 0, [column_x, column_z], &noise, 0).unwrap();
+                let (chunk_coord, _): (ChunkCoord, LocalVoxelBlockCoord) = origin.into();
+
+                assert_eq!([chunk_coord.x, chunk_coord.z], [column_x, column_z]);
+            }
+        }
+    }
+
+    #[test]
+    fn structures_reach_into_the_next_chunks() {
+        let noise = game_settings().procedural.base_noise;
+        let template = wall();
+        let registry = game_block_registry();
+        let rock = registry.block(registry.id("rock").unwrap());
+
+        // The wall of the column 0, 0, in every chunk it goes through
+        let Placement { origin, rotation } = placement(&template, 0, [0, 0], &noise, 0).unwrap();
+        let wall: Vec<GlobalVoxelBlockCoord> = template
+            .blocks
+            .iter()
+            .map(|(position, _)| origin.offset(rotation.apply(*position, template.size)))
+            .collect();
+
+        for coord in wall {
+            let (chunk_coord, block_coord): (ChunkCoord, LocalVoxelBlockCoord) = coord.into();
+            let mut chunk = VoxelChunk::default();
+
+            place_structures(
+                &mut chunk,
+                chunk_coord,
+                std::slice::from_ref(&template),
+                &registry,
+                &noise,
+                0,
+            );
+
+            assert_eq!(chunk.get_block(&block_coord), Some(&rock));
+        }
+
+        // Nothing is placed in the chunks above the terrain
+        if CUBIC_CHUNKS {
+            let mut chunk = VoxelChunk::default();
+            let above = ChunkCoord(Point3::new(0, 1000 / CHUNK_HEIGHT, 0));
+
+            place_structures(&mut chunk, above, &[template], &registry, &noise, 0);
+
+            assert!(chunk.section(0).is_empty());
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::registry::BlockRegistry;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::region_edit::clipboard::{transform_block, Mirror, Rotation};
+use crate::settings::CoordSystemIntegerSize;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+
+/// Mapping of the palette colours to block types, shared by all the models
+const VOX_PALETTE_PATH: &str = "vox_palette.toml";
+
+/// Position of a block in a model, from its lowest corner
+type Position = [CoordSystemIntegerSize; 3];
+
+/// Block types of the colours of MagicaVoxel palettes, `vox_palette.toml`
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct VoxPalette {
+    /// Block name by colour, as in `"#7f7f7f" = "rock"`
+    colors: BTreeMap<String, String>,
+}
+
+impl VoxPalette {
+    fn block_name(&self, [r, g, b, _]: [u8; 4]) -> Option<&String> {
+        let hex = format!("#{:02x}{:02x}{:02x}", r, g, b);
+
+        self.colors
+            .iter()
+            .find_map(|(color, name)| color.eq_ignore_ascii_case(&hex).then_some(name))
+    }
+}
+
+/// Model made in MagicaVoxel, with the y axis up like the world. The block
+/// types are kept by name, so that the model follows the changes of the
+/// block definitions.
+#[derive(Asset, TypePath, Debug, Clone, Default)]
+pub struct VoxModel {
+    /// Blocks along each axis
+    size: Position,
+    /// Position of the voxels and the index of their type in `block_names`
+    voxels: Vec<(Position, usize)>,
+    block_names: Vec<String>,
+}
+
+impl VoxModel {
+    pub fn size(&self) -> Position {
+        self.size
+    }
+
+    /// Blocks of the model. The ones whose type the registry does not know
+    /// are left out.
+    pub fn blocks(&self, registry: &BlockRegistry) -> Vec<(Position, VoxelBlock)> {
+        let blocks: Vec<Option<VoxelBlock>> = self
+            .block_names
+            .iter()
+            .map(|name| registry.id(name).map(|id| registry.block(id)))
+            .collect();
+
+        self.voxels
+            .iter()
+            .filter_map(|(position, index)| blocks[*index].map(|block| (*position, block)))
+            .collect()
+    }
+
+    /// Blocks of the model turned, with the lowest corner of the result at
+    /// `at`. The empty voxels keep the blocks that are there.
+    pub fn stamp(
+        &self,
+        registry: &BlockRegistry,
+        at: GlobalVoxelBlockCoord,
+        rotation: Rotation,
+    ) -> Vec<(GlobalVoxelBlockCoord, VoxelBlock)> {
+        self.blocks(registry)
+            .into_iter()
+            .map(|(position, block)| {
+                (
+                    at.offset(rotation.apply(position, self.size)),
+                    transform_block(block, rotation, Mirror::None, registry),
+                )
+            })
+            .collect()
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+    bytes
+        .get(at..at + 4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .ok_or_else(|| "the file ends in the middle of a chunk".into())
+}
+
+/// Reads the first model of a `.vox` file. Its voxels whose colour is not in
+/// the palette mapping are left out.
+pub fn parse_vox(
+    bytes: &[u8],
+    palette: &VoxPalette,
+) -> Result<VoxModel, Box<dyn std::error::Error + Send + Sync>> {
+    if bytes.get(0..4) != Some(b"VOX ") || bytes.get(8..12) != Some(b"MAIN") {
+        return Err("not a MagicaVoxel file".into());
+    }
+
+    let mut size = None;
+    let mut voxels: Option<Vec<([u8; 3], u8)>> = None;
+    let mut colors: Option<Vec<[u8; 4]>> = None;
+
+    // The chunks of the file are the children of MAIN, one after the other
+    let mut at = 20;
+
+    while at < bytes.len() {
+        let id = bytes
+            .get(at..at + 4)
+            .ok_or("the file ends in a chunk header")?;
+        let content_size = read_u32(bytes, at + 4)? as usize;
+        let children_size = read_u32(bytes, at + 8)? as usize;
+        let content = bytes
+            .get(at + 12..at + 12 + content_size)
+            .ok_or("the file ends in the middle of a chunk")?;
+
+        match id {
+            b"SIZE" if size.is_none() => {
+                size = Some([0, 4, 8].map(|offset| read_u32(content, offset)));
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = read_u32(content, 0)? as usize;
+                let data = content
+                    .get(4..4 + count * 4)
+                    .ok_or("the voxels of the model are cut")?;
+
+                voxels = Some(
+                    data.chunks_exact(4)
+                        .map(|voxel| ([voxel[0], voxel[1], voxel[2]], voxel[3]))
+                        .collect(),
+                );
+            }
+            b"RGBA" => {
+                colors = Some(
+                    content
+                        .chunks_exact(4)
+                        .map(|color| [color[0], color[1], color[2], color[3]])
+                        .collect(),
+                );
+            }
+            _ => {}
+        }
+
+        at += 12 + content_size + children_size;
+    }
+
+    let [size_x, size_y, size_z] = size.ok_or("the file holds no model")?;
+    let [size_x, size_y, size_z] = [size_x?, size_y?, size_z?].map(|v| v as CoordSystemIntegerSize);
+    let voxels = voxels.ok_or("the file holds no voxels")?;
+    let colors = colors.ok_or("the file uses the default palette, which has no mapping")?;
+
+    let mut model = VoxModel {
+        // MagicaVoxel has z up
+        size: [size_x, size_z, size_y],
+        ..default()
+    };
+
+    for ([x, y, z], color_index) in voxels {
+        // Colour indices start at 1
+        let Some(name) = colors
+            .get((color_index as usize).wrapping_sub(1))
+            .and_then(|color| palette.block_name(*color))
+        else {
+            continue;
+        };
+
+        let index = match model.block_names.iter().position(|other| other == name) {
+            Some(index) => index,
+            None => {
+                model.block_names.push(name.clone());
+                model.block_names.len() - 1
+            }
+        };
+
+        // z up becomes y up, and y is flipped so that the model is turned
+        // rather than mirrored
+        let position = [
+            x as CoordSystemIntegerSize,
+            z as CoordSystemIntegerSize,
+            size_y - 1 - y as CoordSystemIntegerSize,
+        ];
+
+        model.voxels.push((position, index));
+    }
+
+    Ok(model)
+}
+
+pub struct VoxLoader;
+
+impl AssetLoader for VoxLoader {
+    type Asset = VoxModel;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<VoxModel, Self::Error> {
+        info!("Loading {}", load_context.path().display());
+
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+
+        // The models are loaded again when the mapping changes
+        let palette = load_context.read_asset_bytes(VOX_PALETTE_PATH).await?;
+        let palette: VoxPalette = toml::from_str(std::str::from_utf8(&palette)?)?;
+
+        parse_vox(&bytes, &palette)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vox"]
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::game_block_registry;
+
+    fn chunk(id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend((content.len() as u32).to_le_bytes());
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend(content);
+        bytes
+    }
+
+    /// A `.vox` file of the size (in MagicaVoxel axes, z up) holding the
+    /// voxels, with the grey of colour 1 and the green of colour 2
+    pub fn vox_file(size: [u32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut xyzi = (voxels.len() as u32).to_le_bytes().to_vec();
+        xyzi.extend(voxels.iter().flatten());
+
+        let mut rgba = vec![0; 256 * 4];
+        rgba[0..4].copy_from_slice(&[0x7f, 0x7f, 0x7f, 0xff]);
+        rgba[4..8].copy_from_slice(&[0x3c, 0xa0, 0x2c, 0xff]);
+
+        let mut children = chunk(b"SIZE", &size.map(u32::to_le_bytes).concat());
+        children.extend(chunk(b"XYZI", &xyzi));
+        children.extend(chunk(b"RGBA", &rgba));
+
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend(150u32.to_le_bytes());
+        bytes.extend(b"MAIN");
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((children.len() as u32).to_le_bytes());
+        bytes.extend(children);
+        bytes
+    }
+
+    pub fn vox_palette() -> VoxPalette {
+        toml::from_str(include_str!("../../assets/vox_palette.toml")).unwrap()
+    }
+
+    #[test]
+    fn models_stand_up_and_map_their_colours_to_blocks() {
+        let registry = game_block_registry();
+        let rock = registry.block(registry.id("rock").unwrap());
+        let grass = registry.block(registry.id("grass").unwrap());
+
+        // 2 wide, 3 deep and 4 high in MagicaVoxel. Colour 3 is not mapped.
+        let bytes = vox_file([2, 3, 4], &[[0, 0, 0, 1], [1, 2, 3, 2], [1, 1, 1, 3]]);
+        let model = parse_vox(&bytes, &vox_palette()).unwrap();
+
+        assert_eq!(model.size(), [2, 4, 3]);
+        assert_eq!(
+            model.blocks(&registry),
+            vec![([0, 0, 2], rock), ([1, 3, 0], grass)]
+        );
+
+        let at = GlobalVoxelBlockCoord::new(10, 20, 30);
+        assert_eq!(
+            model.stamp(&registry, at, Rotation::Quarter),
+            vec![
+                (GlobalVoxelBlockCoord::new(10, 20, 30), rock),
+                (GlobalVoxelBlockCoord::new(12, 23, 31), grass)
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let bytes = vox_file([1, 1, 1], &[[0, 0, 0, 1]]);
+
+        assert!(parse_vox(&bytes[..bytes.len() - 10], &vox_palette()).is_err());
+        assert!(parse_vox(b"PNG", &vox_palette()).is_err());
+    }
+}
@@ -1,30 +1,206 @@
-use crate::chunk::block::{BlockMaterial, VoxelBlock, VoxelBlockType};
-use crate::game_world::coord::{ChunkCoord, LocalVoxelBlockCoord, LocalVoxelBlockOffset};
+use crate::chunk::block::{BlockMaterial, VoxelBlock};
+use crate::chunk::light::Light;
+use crate::chunk::mesher::{fluid_mesh, greedy_mesh, naive_mesh, Mesher};
+use crate::chunk::neighborhood::{face_neighbor_offsets, neighbor_offsets, ChunkNeighborhood};
+use crate::chunk::palette::{PalettedStorage, VoxelBlockMut};
+use crate::chunk::registry::{BlockRegistry, RenderClass};
+use crate::game_world::coord::{
+    ChunkCoord, GlobalVoxelBlockCoord, LocalVoxelBlockCoord, LocalVoxelBlockOffset,
+};
 use crate::game_world::GameWorld;
-use crate::settings::{CHUNK_HEIGHT, CHUNK_SIZE, MAX_OFFSET};
-use crate::utils::{VertexBuffer, UV};
+use crate::settings::{
+    CoordSystemIntegerSize, GameSettingResource, CHUNK_HEIGHT, CHUNK_SIZE, SECTION_COUNT,
+    SECTION_HEIGHT, SECTION_VOLUME,
+};
+use crate::utils::{render_mesh, VertexBuffer};
+use bevy::ecs::system::SystemParam;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
 use bevy::render::mesh::Indices;
-use bevy_rapier3d::math::Vect;
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use bevy_rapier3d::na::Point3;
+use std::ops::{Deref, DerefMut};
 
 impl Default for VoxelChunk {
     fn default() -> Self {
-        Self([VoxelBlock::default(); (CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE) as usize])
+        Self::filled(VoxelBlock::default())
     }
 }
 
-#[derive(Component, Debug, Clone, Deref, DerefMut)]
-pub struct VoxelChunk(pub [VoxelBlock; (CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE) as usize]);
+/// Chunks spawned since the system last ran
+pub type NewChunks<'w, 's> = Query<'w, 's, &'static ChunkCoord, Added<VoxelChunk>>;
+
+/// Blocks of a chunk, split into `SECTION_COUNT` sections of
+/// `SECTION_HEIGHT` layers. Each section is stored as a palette plus
+/// bit-packed indices (see `PalettedStorage`) and is meshed on its own.
+#[derive(Component, Debug, Clone)]
+pub struct VoxelChunk {
+    sections: [PalettedStorage; SECTION_COUNT],
+    /// One bit per section edited since it was last meshed
+    dirty_sections: u32,
+    /// Whether a block changed since the collider was last built
+    collider_dirty: bool,
+    /// Light levels of each section, stored like the blocks so that the
+    /// sections all dark or all in the sun take no room
+    light: [PalettedStorage<Light>; SECTION_COUNT],
+    /// Blocks replaced since they were last sent as `BlockChangedEvent`
+    block_updates: Vec<LocalVoxelBlockOffset>,
+}
 
 impl VoxelChunk {
-    #[allow(dead_code)]
+    pub fn filled(block: VoxelBlock) -> Self {
+        Self {
+            sections: std::array::from_fn(|_| {
+                PalettedStorage::filled(SECTION_VOLUME as usize, block)
+            }),
+            dirty_sections: 0,
+            collider_dirty: false,
+            light: std::array::from_fn(|_| {
+                PalettedStorage::filled(SECTION_VOLUME as usize, Light::default())
+            }),
+            block_updates: vec![],
+        }
+    }
+
+    pub fn section(&self, index: usize) -> &PalettedStorage {
+        &self.sections[index]
+    }
+
+    /// Shrinks the palettes once a batch of edits is done
+    pub fn compact(&mut self) {
+        self.sections.iter_mut().for_each(PalettedStorage::compact);
+    }
+
+    /// Shrinks the palette of a single section once its edits are done
+    pub fn compact_section(&mut self, index: usize) {
+        self.sections[index].compact();
+    }
+
+    /// Shrinks the light palettes once the light is spread
+    pub fn compact_light(&mut self) {
+        self.light.iter_mut().for_each(PalettedStorage::compact);
+    }
+
+    /// Sections edited since the last call, which need to be meshed again
+    pub fn take_dirty_sections(&mut self) -> Vec<usize> {
+        let dirty = (0..SECTION_COUNT)
+            .filter(|index| self.dirty_sections & (1 << index) != 0)
+            .collect();
+
+        self.dirty_sections = 0;
+
+        dirty
+    }
+
+    /// Whether a block changed since the last call, and the collider of the
+    /// chunk has to be built again
+    pub fn take_collider_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.collider_dirty)
+    }
+
+    /// Marks a section to be meshed again, when a block next to it changed
+    pub fn mark_dirty(&mut self, section: usize) {
+        self.dirty_sections |= 1 << section;
+    }
+
+    /// Marks dirty the sections showing the neighbour at `offset`, after it
+    /// was loaded, shown, hidden or unloaded
+    pub fn mark_dirty_toward(&mut self, offset: [CoordSystemIntegerSize; 3]) {
+        for section in 0..SECTION_COUNT {
+            if self.has_blocks_toward(section, offset) {
+                self.mark_dirty(section);
+            }
+        }
+    }
+
+    /// Whether a section has blocks on the face, the edge or the corner the
+    /// chunk shares with the neighbour at `offset` (-1, 0 or 1 on each axis).
+    /// Only these blocks show the neighbour in their mesh.
+    pub fn has_blocks_toward(&self, section: usize, offset: [CoordSystemIntegerSize; 3]) -> bool {
+        let storage = &self.sections[section];
+
+        if storage.is_empty() {
+            return false;
+        }
+
+        let dimensions = [CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE];
+        let [x, y, z] = [0, 1, 2].map(|axis| match offset[axis] {
+            -1 => 0..=0,
+            0 => 0..=dimensions[axis] - 1,
+            _ => dimensions[axis] - 1..=dimensions[axis] - 1,
+        });
+        let first_y = section as CoordSystemIntegerSize * SECTION_HEIGHT;
+        let y = *y.start().max(&first_y)..=*y.end().min(&(first_y + SECTION_HEIGHT - 1));
+
+        if y.is_empty() {
+            return false;
+        }
+
+        if storage.is_full() {
+            return true;
+        }
+
+        for y in y {
+            for z in z.clone() {
+                for x in x.clone() {
+                    let block = self.get_block(&LocalVoxelBlockCoord(Point3::new(x, y, z)));
+
+                    if block.is_some_and(|block| !block.is_empty()) {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    pub fn light<P>(&self, into_coord: &P) -> Option<Light>
+    where
+        P: Into<LocalVoxelBlockOffset> + Clone,
+    {
+        let (section, offset) = section_and_offset(into_coord.clone().into());
+
+        self.light.get(section)?.get(offset).copied()
+    }
+
+    /// Light levels are not part of the mesh until the sections showing
+    /// them are marked as dirty
+    pub fn set_light<P>(&mut self, into_coord: &P, light: Light)
+    where
+        P: Into<LocalVoxelBlockOffset> + Clone,
+    {
+        let (section, offset) = section_and_offset(into_coord.clone().into());
+
+        if let Some(storage) = self.light.get_mut(section) {
+            storage.set(offset, light);
+        }
+    }
+
+    /// Announces a replaced block with a `BlockChangedEvent` at the next frame
+    pub fn queue_block_update<P>(&mut self, into_coord: &P)
+    where
+        P: Into<LocalVoxelBlockOffset> + Clone,
+    {
+        self.block_updates.push(into_coord.clone().into());
+    }
+
+    pub fn has_block_updates(&self) -> bool {
+        !self.block_updates.is_empty()
+    }
+
+    pub fn take_block_updates(&mut self) -> Vec<LocalVoxelBlockOffset> {
+        std::mem::take(&mut self.block_updates)
+    }
+
     pub fn update_block<P, F>(&mut self, into_coord: &P, update: F)
     where
         P: Into<LocalVoxelBlockOffset> + Clone,
         F: Fn(&mut VoxelBlock),
     {
-        if let Some(block) = self.get_block_mut(into_coord) {
-            update(block);
+        if let Some(mut block) = self.get_block_mut(into_coord) {
+            update(&mut block);
         }
     }
 
@@ -32,281 +208,185 @@ impl VoxelChunk {
     where
         P: Into<LocalVoxelBlockOffset> + Clone,
     {
-        let coord = into_coord.clone().into();
+        let (section, offset) = section_and_offset(into_coord.clone().into());
 
-        self.0.get(*coord)
+        self.sections.get(section)?.get(offset)
     }
 
-    pub fn get_block_mut<P>(&mut self, into_coord: &P) -> Option<&mut VoxelBlock>
+    /// The returned guard writes the block back into the chunk when dropped.
+    /// The section holding the block and the collider are marked as dirty
+    /// when the block changed.
+    pub fn get_block_mut<P>(&mut self, into_coord: &P) -> Option<ChunkBlockMut<'_>>
     where
         P: Into<LocalVoxelBlockOffset> + Clone,
     {
-        let coord = into_coord.clone().into();
-
-        self.0.get_mut(*coord)
-    }
-
-    pub fn render_indices_and_vertices(&self) -> (Indices, VertexBuffer) {
-        let mut indices: Vec<u32> = vec![];
-        let mut total_nb_faces: u32 = 0;
-        let mut vertices: VertexBuffer = vec![];
-
-        for coord in all_block_offsets_iter() {
-            if let Some(block) = self.get_block(&coord) {
-                match block.block_type {
-                    VoxelBlockType::Empty => (),
-                    _ => self.render_chunk_block(
-                        &coord,
-                        &mut indices,
-                        &mut total_nb_faces,
-                        &mut vertices,
-                    ),
-                }
-            }
-        }
+        let (section, offset) = section_and_offset(into_coord.clone().into());
 
-        let indices = Indices::U32(indices);
+        let storage = self.sections.get_mut(section)?;
 
-        (indices, vertices)
+        Some(ChunkBlockMut {
+            block: VoxelBlockMut::new(storage, offset)?,
+            section,
+            dirty_sections: &mut self.dirty_sections,
+            collider_dirty: &mut self.collider_dirty,
+        })
     }
 
-    fn render_chunk_block(
+    /// Mesh of each render class of a single section, empty for the classes
+    /// without visible faces
+    pub fn render_section(
         &self,
-        coord: &LocalVoxelBlockOffset,
-        indices: &mut Vec<u32>,
-        total_nb_faces: &mut u32,
-        vertices: &mut VertexBuffer,
-    ) {
-        // suppose Y-up right hand, and camera look from +z to -z
-        let sp = Cuboid::new(1.0, 1.0, 1.0);
-        // let sp = shape::Box::new(1.0, 1.0, 1.0);
-
-        let indices_template = [0, 1, 2, 2, 3, 0];
-
-        let block = self
-            .get_block(coord)
-            .expect("A block was expected here, but no block found");
-
-        let uv_offset = match block.block_type {
-            VoxelBlockType::Rock => 0.25,
-            VoxelBlockType::Grass => 0.00,
-            VoxelBlockType::Gem => 0.50,
-            VoxelBlockType::Dirt => 0.75,
-            _ => 0.00,
-        };
+        section: usize,
+        neighborhood: &ChunkNeighborhood,
+        block_registry: &BlockRegistry,
+        mesher: Mesher,
+    ) -> [(ChunkSectionMesh, Indices, VertexBuffer); RenderClass::ALL.len()] {
+        RenderClass::ALL.map(|class| {
+            let (indices, vertices) = self.render_indices_and_vertices(
+                section,
+                neighborhood,
+                block_registry,
+                mesher,
+                class,
+            );
 
-        // println!("Current: {:?}", coord);
-        // println!("Front: {:?}", coord + [0, 0, 1]);
-        // println!("Back: {:?}", coord + [0, 0, -1]);
-        // println!("Right: {:?}", coord + [1, 0, 0]);
-        // println!("Left: {:?}", coord + [-1, 0, 0]);
-        // println!("Top: {:?}", coord + [0, 1, 0]);
-        // println!("Bottom: {:?}", coord + [0, -1, 0]);
-
-        let block_coord: LocalVoxelBlockCoord = LocalVoxelBlockCoord::from(*coord);
-
-        let faces: [_; 6] = [
-            (
-                block_coord + [0, 0, 1],
-                [
-                    (
-                        [-sp.half_size.x, -sp.half_size.y, sp.half_size.z],
-                        [0., 0., 1.0],
-                        apply_uv_offset([0., 0.], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, -sp.half_size.y, sp.half_size.z],
-                        [0., 0., 1.0],
-                        apply_uv_offset([1.0, 0.], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, sp.half_size.y, sp.half_size.z],
-                        [0., 0., 1.0],
-                        apply_uv_offset([1.0, 1.0], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, sp.half_size.y, sp.half_size.z],
-                        [0., 0., 1.0],
-                        apply_uv_offset([0., 1.0], uv_offset),
-                    ),
-                ],
-            ),
-            (
-                block_coord + [0, 0, -1],
-                [
-                    (
-                        [-sp.half_size.x, sp.half_size.y, -sp.half_size.z],
-                        [0., 0., -1.0],
-                        apply_uv_offset([1.0, 0.], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, sp.half_size.y, -sp.half_size.z],
-                        [0., 0., -1.0],
-                        apply_uv_offset([0., 0.], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, -sp.half_size.y, -sp.half_size.z],
-                        [0., 0., -1.0],
-                        apply_uv_offset([0., 1.0], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, -sp.half_size.y, -sp.half_size.z],
-                        [0., 0., -1.0],
-                        apply_uv_offset([1.0, 1.0], uv_offset),
-                    ),
-                ],
-            ),
-            (
-                block_coord + [1, 0, 0],
-                [
-                    (
-                        [sp.half_size.x, -sp.half_size.y, -sp.half_size.z],
-                        [1.0, 0., 0.],
-                        apply_uv_offset([0., 0.], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, sp.half_size.y, -sp.half_size.z],
-                        [1.0, 0., 0.],
-                        apply_uv_offset([1.0, 0.], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, sp.half_size.y, sp.half_size.z],
-                        [1.0, 0., 0.],
-                        apply_uv_offset([1.0, 1.0], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, -sp.half_size.y, sp.half_size.z],
-                        [1.0, 0., 0.],
-                        apply_uv_offset([0., 1.0], uv_offset),
-                    ),
-                ],
-            ),
-            (
-                block_coord + [-1, 0, 0],
-                [
-                    (
-                        [-sp.half_size.x, -sp.half_size.y, sp.half_size.z],
-                        [-1.0, 0., 0.],
-                        apply_uv_offset([1.0, 0.], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, sp.half_size.y, sp.half_size.z],
-                        [-1.0, 0., 0.],
-                        apply_uv_offset([0., 0.], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, sp.half_size.y, -sp.half_size.z],
-                        [-1.0, 0., 0.],
-                        apply_uv_offset([0., 1.0], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, -sp.half_size.y, -sp.half_size.z],
-                        [-1.0, 0., 0.],
-                        apply_uv_offset([1.0, 1.0], uv_offset),
-                    ),
-                ],
-            ),
-            (
-                block_coord + [0, 1, 0],
-                [
-                    (
-                        [sp.half_size.x, sp.half_size.y, -sp.half_size.z],
-                        [0., 1.0, 0.],
-                        apply_uv_offset([1.0, 0.], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, sp.half_size.y, -sp.half_size.z],
-                        [0., 1.0, 0.],
-                        apply_uv_offset([0., 0.], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, sp.half_size.y, sp.half_size.z],
-                        [0., 1.0, 0.],
-                        apply_uv_offset([0., 1.0], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, sp.half_size.y, sp.half_size.z],
-                        [0., 1.0, 0.],
-                        apply_uv_offset([1.0, 1.0], uv_offset),
-                    ),
-                ],
-            ),
-            (
-                block_coord + [0, -1, 0],
-                [
-                    (
-                        [sp.half_size.x, -sp.half_size.y, sp.half_size.z],
-                        [0., -1.0, 0.],
-                        apply_uv_offset([0., 0.], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, -sp.half_size.y, sp.half_size.z],
-                        [0., -1.0, 0.],
-                        apply_uv_offset([1.0, 0.], uv_offset),
-                    ),
-                    (
-                        [-sp.half_size.x, -sp.half_size.y, -sp.half_size.z],
-                        [0., -1.0, 0.],
-                        apply_uv_offset([1.0, 1.0], uv_offset),
-                    ),
-                    (
-                        [sp.half_size.x, -sp.half_size.y, -sp.half_size.z],
-                        [0., -1.0, 0.],
-                        apply_uv_offset([0., 1.0], uv_offset),
-                    ),
-                ],
-            ),
-        ];
-
-        let block_offset: [f32; 3] = [
-            block_coord.x as f32,
-            block_coord.y as f32,
-            block_coord.z as f32,
-        ];
-
-        for (coord, attributes) in faces.iter() {
-            let mut should_render_face = true;
-            if let Some(coord) = coord {
-                if coord.is_valid_chunk_voxel_coord() {
-                    if let Some(cmp_block) = self.get_block(coord) {
-                        if cmp_block.block_type != VoxelBlockType::Empty {
-                            should_render_face = false;
-                        }
-                    }
+            (ChunkSectionMesh { section, class }, indices, vertices)
+        })
+    }
+
+    /// Mesh of the blocks of a render class in a single section, with the
+    /// vertices in chunk space. Fluids have their own mesher whatever the
+    /// settings say.
+    pub fn render_indices_and_vertices(
+        &self,
+        section: usize,
+        neighborhood: &ChunkNeighborhood,
+        block_registry: &BlockRegistry,
+        mesher: Mesher,
+        class: RenderClass,
+    ) -> (Indices, VertexBuffer) {
+        match (mesher, class) {
+            (_, RenderClass::Fluid) => fluid_mesh(self, section, neighborhood, block_registry),
+            (Mesher::Naive, _) => naive_mesh(self, section, neighborhood, block_registry, class),
+            (Mesher::Greedy, _) => greedy_mesh(self, section, neighborhood, block_registry, class),
+        }
+    }
+}
+
+/// Mutable access to a block of a chunk, see `VoxelChunk::get_block_mut`
+pub struct ChunkBlockMut<'a> {
+    block: VoxelBlockMut<'a>,
+    section: usize,
+    dirty_sections: &'a mut u32,
+    collider_dirty: &'a mut bool,
+}
+
+impl Deref for ChunkBlockMut<'_> {
+    type Target = VoxelBlock;
+
+    fn deref(&self) -> &Self::Target {
+        &self.block
+    }
+}
+
+impl DerefMut for ChunkBlockMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.block
+    }
+}
+
+/// The block itself is written back when the inner guard is dropped, right
+/// after this
+impl Drop for ChunkBlockMut<'_> {
+    fn drop(&mut self) {
+        if self.block.is_changed() {
+            *self.dirty_sections |= 1 << self.section;
+            *self.collider_dirty = true;
+        }
+    }
+}
+
+/// Sections are stacked along y, which is the most significant part of the
+/// offset, so they each hold a contiguous range of offsets
+fn section_and_offset(offset: LocalVoxelBlockOffset) -> (usize, usize) {
+    let section_volume = SECTION_VOLUME as usize;
+
+    (*offset / section_volume, *offset % section_volume)
+}
+
+/// Sections whose mesh shows a block: the one holding it and the ones
+/// holding its 26 neighbours, which can be in the neighbouring chunks when
+/// the block is on a border. Neighbours matter because of face culling and
+/// ambient occlusion.
+pub fn sections_around_block(
+    chunk_coord: ChunkCoord,
+    block_coord: &LocalVoxelBlockCoord,
+) -> Vec<(ChunkCoord, usize)> {
+    let mut sections = vec![];
+
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                // Past the borders, the local coordinate is outside of the chunk
+                let outside = LocalVoxelBlockCoord(Point3::new(
+                    block_coord.x + x,
+                    block_coord.y + y,
+                    block_coord.z + z,
+                ));
+                let neighbor = GlobalVoxelBlockCoord::from((chunk_coord, outside));
+                let (neighbor_chunk, neighbor_block): (ChunkCoord, LocalVoxelBlockCoord) =
+                    neighbor.into();
+                let section = (neighbor_block.y / SECTION_HEIGHT) as usize;
+
+                if !sections.contains(&(neighbor_chunk, section)) {
+                    sections.push((neighbor_chunk, section));
                 }
             }
+        }
+    }
 
-            if should_render_face {
-                attributes.iter().for_each(|(position, normals, uv)| {
-                    let v: [f32; 3] = position
-                        .iter()
-                        .zip(block_offset)
-                        .map(|(v, offset)| v + offset)
-                        .collect::<Vec<_>>()
-                        .try_into()
-                        .unwrap();
-
-                    vertices.push((v, *normals, *uv))
-                });
-
-                indices_template
-                    .iter()
-                    .map(|i| i + (*total_nb_faces) * 4)
-                    .for_each(|i| indices.push(i));
-
-                *total_nb_faces += 1;
-            }
+    sections
+}
+
+/// A block of a loaded chunk was replaced. The light, the fluids and the
+/// falling blocks react to these.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BlockChangedEvent {
+    pub coord: GlobalVoxelBlockCoord,
+}
+
+/// Sends the blocks replaced since the last frame as events. Chunks keep
+/// their edits until then, so that edits made anywhere in the frame are
+/// announced once.
+pub fn send_block_changed_events(
+    mut chunks: Query<(&ChunkCoord, &mut VoxelChunk)>,
+    mut ev_block_changed: EventWriter<BlockChangedEvent>,
+) {
+    for (coord, mut chunk) in chunks.iter_mut() {
+        if !chunk.has_block_updates() {
+            continue;
+        }
+
+        for offset in chunk.take_block_updates() {
+            ev_block_changed.write(BlockChangedEvent {
+                coord: GlobalVoxelBlockCoord::from((*coord, offset.into())),
+            });
         }
     }
 }
 
+/// Child entity of a chunk holding the mesh of the blocks of a render class
+/// in one of its sections
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ChunkSectionMesh {
+    pub section: usize,
+    pub class: RenderClass,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChunkData {
-    pub mesh: Mesh,
-    #[allow(dead_code)]
-    pub vertex: Vec<Vect>,
-    #[allow(dead_code)]
-    pub indices: Vec<[u32; 3]>,
+    /// Mesh of each section and render class that has visible faces
+    pub meshes: Vec<(ChunkSectionMesh, Mesh)>,
     pub chunk: VoxelChunk,
 }
 
@@ -319,41 +399,387 @@ pub fn add_new_chunks_to_game_world(
     }
 }
 
+/// Collects the boundary slices of the loaded chunks around `chunk_coord`
+pub fn neighborhood_of<'a, F>(
+    game_world: &GameWorld,
+    chunk_coord: &ChunkCoord,
+    chunk_of: F,
+) -> ChunkNeighborhood
+where
+    F: Fn(Entity) -> Option<&'a VoxelChunk>,
+{
+    ChunkNeighborhood::from_neighbors(|offset| {
+        game_world
+            .get(&chunk_coord.offset(offset))
+            .and_then(|entity| chunk_of(*entity))
+    })
+}
+
+/// Re-meshing task of each chunk with dirty sections. A chunk has at most
+/// one task in flight, the sections edited in the meantime wait for the next
+/// one.
+#[derive(Resource, Debug, Default)]
+pub struct ChunkRemeshTaskMap {
+    chunks: HashMap<Entity, Task<Vec<(ChunkSectionMesh, Mesh)>>>,
+}
+
+/// What the systems re-meshing chunk sections need to update the section
+/// meshes in place, or to spawn the ones that do not exist yet
+#[derive(SystemParam)]
+pub struct SectionMeshes<'w, 's> {
+    commands: Commands<'w, 's>,
+    section_meshes: Query<'w, 's, (&'static ChunkSectionMesh, &'static Mesh3d)>,
+    mesh_manager: ResMut<'w, Assets<Mesh>>,
+    block_material: Res<'w, BlockMaterial>,
+}
+
+impl SectionMeshes<'_, '_> {
+    /// Swaps the mesh asset of a section in place, so that the entity and its
+    /// material are kept
+    pub fn replace(
+        &mut self,
+        chunk_entity: Entity,
+        children: Option<&Children>,
+        section_mesh: ChunkSectionMesh,
+        mesh: Mesh,
+    ) {
+        let existing = children.and_then(|children| {
+            children
+                .iter()
+                .filter_map(|child| self.section_meshes.get(child).ok())
+                .find(|(other, _)| {
+                    other.section == section_mesh.section && other.class == section_mesh.class
+                })
+                .map(|(_, mesh)| mesh.0.clone())
+        });
+
+        match existing {
+            Some(handle) => {
+                self.mesh_manager.insert(&handle, mesh);
+            }
+            // Most sections have no block of some of the classes
+            None if mesh.count_vertices() == 0 => {}
+            None => {
+                let handle = self.mesh_manager.add(mesh);
+
+                spawn_section_mesh(
+                    &mut self.commands,
+                    chunk_entity,
+                    section_mesh,
+                    handle,
+                    &self.block_material,
+                );
+            }
+        }
+    }
+}
+
+/// A chunk is meshed without knowing its neighbours. Once it is in the game
+/// world, the sections of it and of the loaded chunks around it that touch
+/// the other chunk are meshed again, so that the faces between them are
+/// culled and the ambient occlusion matches across the borders.
+pub fn remesh_borders_of_new_chunks(
+    game_world: Res<GameWorld>,
+    mut chunks: ParamSet<(NewChunks, Query<&mut VoxelChunk>)>,
+) {
+    let new_chunks: Vec<ChunkCoord> = chunks.p0().iter().copied().collect();
+    // Chunks to re-mesh, with the direction of the chunk they touch
+    let mut borders: HashSet<(ChunkCoord, [CoordSystemIntegerSize; 3])> = HashSet::new();
+
+    for chunk_coord in new_chunks {
+        // The diagonal neighbours matter too since ambient occlusion looks
+        // at the blocks past the corners
+        for offset in neighbor_offsets() {
+            let neighbor_coord = chunk_coord.offset(offset);
+
+            if game_world.contains_key(&neighbor_coord) {
+                borders.insert((chunk_coord, offset));
+                borders.insert((neighbor_coord, offset.map(|v| -v)));
+            }
+        }
+    }
+
+    let mut chunks = chunks.p1();
+
+    for (chunk_coord, toward) in borders {
+        let Some(Ok(mut chunk)) = game_world
+            .get(&chunk_coord)
+            .map(|entity| chunks.get_mut(*entity))
+        else {
+            continue;
+        };
+
+        chunk.mark_dirty_toward(toward);
+    }
+}
+
+/// The loaded chunks sharing a face with `chunk_coord`, with the direction
+/// of `chunk_coord` from each of them. Their faces toward a chunk that is
+/// hidden or unloaded are culled until they are meshed again.
+pub fn face_neighbors<'a>(
+    game_world: &'a GameWorld,
+    chunk_coord: &'a ChunkCoord,
+) -> impl Iterator<Item = (Entity, [CoordSystemIntegerSize; 3])> + 'a {
+    face_neighbor_offsets().filter_map(move |offset| {
+        game_world
+            .get(&chunk_coord.offset(offset))
+            .map(|entity| (*entity, offset.map(|v| -v)))
+    })
+}
+
+/// Meshes the dirty sections of each chunk off the main thread. All the
+/// edits made to a chunk since its last re-mesh end up in a single task.
+pub fn begin_remeshing_dirty_sections(
+    game_world: Res<GameWorld>,
+    mut chunks: Query<(Entity, &ChunkCoord, &mut VoxelChunk, &Visibility)>,
+    mut remesh_tasks: ResMut<ChunkRemeshTaskMap>,
+    game_setting_resource: Res<GameSettingResource>,
+    block_registry: Res<BlockRegistry>,
+) {
+    let task_pool = AsyncComputeTaskPool::get();
+    let mesher = game_setting_resource.settings.render.mesher;
+
+    let dirty: Vec<(Entity, ChunkCoord)> = chunks
+        .iter()
+        .filter(|(entity, _, chunk, _)| {
+            chunk.dirty_sections != 0 && !remesh_tasks.chunks.contains_key(entity)
+        })
+        .map(|(entity, coord, _, _)| (entity, *coord))
+        .collect();
+
+    for (entity, chunk_coord) in dirty {
+        // Hidden chunks are left out so that the faces toward them are drawn
+        // at the edge of the visible range
+        let neighborhood = neighborhood_of(&game_world, &chunk_coord, |entity| {
+            chunks
+                .get(entity)
+                .ok()
+                .filter(|(_, _, _, visibility)| **visibility != Visibility::Hidden)
+                .map(|(_, _, chunk, _)| chunk)
+        });
+
+        let Ok((_, _, mut chunk, _)) = chunks.get_mut(entity) else {
+            continue;
+        };
+
+        // The edits that made the sections dirty may have left unused
+        // entries in their palettes
+        let sections = chunk.take_dirty_sections();
+        for section in &sections {
+            chunk.compact_section(*section);
+        }
+
+        let chunk = chunk.clone();
+        let registry = block_registry.clone();
+
+        let task = task_pool.spawn(async move {
+            sections
+                .into_iter()
+                .flat_map(|section| chunk.render_section(section, &neighborhood, &registry, mesher))
+                .map(|(section_mesh, indices, vertices)| {
+                    (section_mesh, render_mesh(&indices, &vertices))
+                })
+                .collect()
+        });
+
+        remesh_tasks.chunks.insert(entity, task);
+    }
+}
+
+/// Swaps in the meshes of the re-meshing tasks that are done
+pub fn receive_remeshed_sections(
+    mut remesh_tasks: ResMut<ChunkRemeshTaskMap>,
+    chunks: Query<Option<&Children>, With<VoxelChunk>>,
+    mut section_meshes: SectionMeshes,
+) {
+    remesh_tasks.chunks.retain(|entity, task| {
+        let Some(meshes) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+
+        // The chunk may have been despawned while it was meshed
+        if let Ok(children) = chunks.get(*entity) {
+            for (section_mesh, mesh) in meshes {
+                section_meshes.replace(*entity, children, section_mesh, mesh);
+            }
+        }
+
+        false
+    });
+}
+
+fn spawn_section_mesh(
+    commands: &mut Commands,
+    chunk_entity: Entity,
+    section_mesh: ChunkSectionMesh,
+    mesh: Handle<Mesh>,
+    block_material: &BlockMaterial,
+) {
+    commands.entity(chunk_entity).with_child((
+        section_mesh,
+        Mesh3d(mesh),
+        MeshMaterial3d(block_material.get(section_mesh.class).clone()),
+        // Section meshes are in chunk space
+        Transform::default(),
+    ));
+}
+
 pub fn spawn_chunk_from_data(
     chunk_data: ChunkData,
     chunk_coord: ChunkCoord,
     block_material: &Res<BlockMaterial>,
     mesh_manager: &mut Assets<Mesh>,
     commands: &mut Commands,
-) {
+) -> Entity {
     info!("Spawning chunk from data");
-    commands.spawn((
-        Transform::from(chunk_coord),
-        Mesh3d(mesh_manager.add(chunk_data.mesh)),
-        MeshMaterial3d(block_material.0.clone()),
-        chunk_data.chunk,
-        chunk_coord,
-        // todo: re-enabled collisions laterss
-        // RigidBody::Fixed,
-        // Collider::trimesh(
-        //     chunk_data.vertex,
-        //     chunk_data.indices
-        // ),
-        Visibility::Visible,
-    ));
-}
+    let chunk_entity = commands
+        .spawn((
+            Transform::from(chunk_coord),
+            chunk_data.chunk,
+            chunk_coord,
+            // Colliders are added by `update_chunk_colliders` once a body comes near
+            Visibility::Visible,
+        ))
+        .id();
 
-fn apply_uv_offset(uv: UV, offset: f32) -> UV {
-    let [u, v] = uv;
+    for (section_mesh, mesh) in chunk_data.meshes {
+        spawn_section_mesh(
+            commands,
+            chunk_entity,
+            section_mesh,
+            mesh_manager.add(mesh),
+            block_material,
+        );
+    }
 
-    [u / 4.0 + offset, v]
+    chunk_entity
 }
 
-#[allow(dead_code)]
-fn all_block_coords_iter() -> impl Iterator<Item = LocalVoxelBlockCoord> {
-    (0..MAX_OFFSET).map(|index| LocalVoxelBlockCoord::from(LocalVoxelBlockOffset(index as usize)))
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::block::BlockId;
+    use crate::chunk::light::{light_chunk, LightChannel, MAX_LIGHT};
+    use crate::chunk::registry::tests::game_block_registry;
+
+    fn sections_around(
+        chunk: [CoordSystemIntegerSize; 3],
+        block: [CoordSystemIntegerSize; 3],
+    ) -> Vec<(ChunkCoord, usize)> {
+        sections_around_block(
+            ChunkCoord(Point3::from(chunk)),
+            &LocalVoxelBlockCoord(Point3::from(block)),
+        )
+    }
+
+    #[test]
+    fn edits_on_borders_dirty_the_neighbors() {
+        // Inside a section, only that section shows the block
+        assert_eq!(
+            sections_around([0, 0, 0], [8, 5, 8]),
+            vec![(ChunkCoord(Point3::new(0, 0, 0)), 0)]
+        );
 
-fn all_block_offsets_iter() -> impl Iterator<Item = LocalVoxelBlockOffset> {
-    (0..MAX_OFFSET).map(|index| LocalVoxelBlockOffset(index as usize))
+        // On the border of the chunk
+        let sections = sections_around([0, 0, 0], [0, 5, 8]);
+        assert_eq!(sections.len(), 2);
+        assert!(sections.contains(&(ChunkCoord(Point3::new(-1, 0, 0)), 0)));
+
+        // On the corner of the chunk and the top of the section, which is
+        // either the next section or the chunk above
+        assert_eq!(
+            sections_around([0, 0, 0], [0, SECTION_HEIGHT - 1, 0]).len(),
+            8
+        );
+
+        // Negative chunks, on the border toward positive x and z
+        let sections = sections_around([-1, 0, -1], [CHUNK_SIZE - 1, 5, CHUNK_SIZE - 1]);
+        assert_eq!(sections.len(), 4);
+        assert!(sections.contains(&(ChunkCoord(Point3::new(0, 0, 0)), 0)));
+    }
+
+    // Needs a section above the lowest one
+    #[cfg(not(feature = "cubic_chunks"))]
+    #[test]
+    fn sections_in_the_sun_or_in_the_dark_store_a_single_light() {
+        let registry = game_block_registry();
+        let mut chunk = VoxelChunk::default();
+        let rock = registry.block(registry.id("rock").unwrap());
+
+        // The lowest section is dark rock, the ones above are open to the sky
+        for offset in 0..SECTION_VOLUME as usize {
+            chunk.update_block(&LocalVoxelBlockOffset(offset), |block| *block = rock);
+        }
+        light_chunk(&mut chunk, &registry, true);
+        chunk.compact_light();
+
+        assert_eq!(chunk.light[0].palette(), [Light::default()]);
+        for section in 1..SECTION_COUNT {
+            assert_eq!(
+                chunk.light[section].palette(),
+                [Light::default().with(LightChannel::Sky, MAX_LIGHT)]
+            );
+        }
+    }
+
+    #[test]
+    fn only_the_blocks_on_a_border_show_the_neighbor() {
+        let mut chunk = VoxelChunk::default();
+        assert!(!chunk.has_blocks_toward(0, [-1, 0, 0]));
+
+        chunk.update_block(&LocalVoxelBlockCoord(Point3::new(0, 5, 8)), |block| {
+            block.id = BlockId(1)
+        });
+
+        assert!(chunk.has_blocks_toward(0, [-1, 0, 0]));
+        assert!(!chunk.has_blocks_toward(0, [1, 0, 0]));
+        assert!(!chunk.has_blocks_toward(0, [0, 1, 0]));
+        // Not on the edge shared with the diagonal neighbour
+        assert!(!chunk.has_blocks_toward(0, [-1, 0, -1]));
+
+        chunk.update_block(&LocalVoxelBlockCoord(Point3::new(0, 5, 0)), |block| {
+            block.id = BlockId(1)
+        });
+        assert!(chunk.has_blocks_toward(0, [-1, 0, -1]));
+
+        // A full section only touches the chunk above when it is the top one
+        let rock = VoxelChunk::filled(VoxelBlock {
+            id: BlockId(1),
+            ..default()
+        });
+        assert!(rock.has_blocks_toward(SECTION_COUNT - 1, [0, 1, 0]));
+        assert_eq!(rock.has_blocks_toward(0, [0, 1, 0]), SECTION_COUNT == 1);
+    }
+
+    #[test]
+    fn only_changed_blocks_dirty_their_section_and_collider() {
+        let mut chunk = VoxelChunk::default();
+        let coord = LocalVoxelBlockCoord(Point3::new(3, 4, 5));
+
+        chunk.update_block(&coord, |_| {});
+        chunk.update_block(&coord, |block| *block = VoxelBlock::default());
+        assert!(chunk.take_dirty_sections().is_empty());
+        assert!(!chunk.take_collider_dirty());
+
+        chunk.update_block(&coord, |block| block.id = BlockId(1));
+        assert_eq!(chunk.take_dirty_sections(), vec![0]);
+        assert!(chunk.take_collider_dirty());
+        assert_eq!(chunk.get_block(&coord).unwrap().id, BlockId(1));
+    }
+
+    #[test]
+    fn face_neighbors_point_back_at_the_chunk() {
+        let mut game_world = GameWorld::default();
+        let center = ChunkCoord(Point3::new(0, 0, 0));
+        let east = Entity::from_raw(1);
+        let diagonal = Entity::from_raw(2);
+
+        game_world.insert(center, Entity::from_raw(0));
+        game_world.insert(ChunkCoord(Point3::new(1, 0, 0)), east);
+        game_world.insert(ChunkCoord(Point3::new(1, 0, 1)), diagonal);
+
+        let neighbors: Vec<_> = face_neighbors(&game_world, &center).collect();
+
+        assert_eq!(neighbors, vec![(east, [-1, 0, 0])]);
+    }
 }
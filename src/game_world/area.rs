@@ -0,0 +1,113 @@
+use crate::game_world::coord::ChunkCoord;
+use crate::settings::CoordSystemIntegerSize;
+use bevy_rapier3d::na::Point3;
+use std::ops::RangeInclusive;
+
+/// Box of chunks around a center chunk, `radius` chunks away from it
+/// horizontally and `vertical_radius` chunks vertically
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkArea {
+    pub center: ChunkCoord,
+    pub radius: CoordSystemIntegerSize,
+    pub vertical_radius: CoordSystemIntegerSize,
+}
+
+impl ChunkArea {
+    pub fn new(
+        center: ChunkCoord,
+        radius: CoordSystemIntegerSize,
+        vertical_radius: CoordSystemIntegerSize,
+    ) -> Self {
+        Self {
+            center,
+            radius,
+            vertical_radius,
+        }
+    }
+
+    fn range(&self, axis: usize) -> RangeInclusive<CoordSystemIntegerSize> {
+        let radius = match axis {
+            1 => self.vertical_radius,
+            _ => self.radius,
+        };
+
+        (self.center[axis] - radius)..=(self.center[axis] + radius)
+    }
+
+    pub fn contains(&self, coord: &ChunkCoord) -> bool {
+        (0..3).all(|axis| self.range(axis).contains(&coord[axis]))
+    }
+
+    /// Every chunk of the area
+    pub fn chunks(&self) -> Vec<ChunkCoord> {
+        self.difference(None)
+    }
+
+    /// Chunks of this area that are not in `other`. Only the rows outside of
+    /// `other` are walked, so moving by one chunk costs a single ring.
+    pub fn difference<'a>(&self, other: impl Into<Option<&'a ChunkArea>>) -> Vec<ChunkCoord> {
+        let other = other.into();
+        let is_outside = |axis: usize, value: CoordSystemIntegerSize| {
+            other.is_none_or(|other| !other.range(axis).contains(&value))
+        };
+
+        let mut chunks = vec![];
+
+        for x in self.range(0) {
+            for y in self.range(1) {
+                let row_outside = is_outside(0, x) || is_outside(1, y);
+
+                for z in self.range(2).filter(|z| row_outside || is_outside(2, *z)) {
+                    chunks.push(ChunkCoord(Point3::new(x, y, z)));
+                }
+            }
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(x: CoordSystemIntegerSize, z: CoordSystemIntegerSize) -> ChunkArea {
+        ChunkArea::new(ChunkCoord(Point3::new(x, 0, z)), 2, 0)
+    }
+
+    #[test]
+    fn moving_one_chunk_loads_and_unloads_a_row() {
+        let before = area(0, 0);
+        let after = area(1, 0);
+
+        let to_load = after.difference(&before);
+        let to_unload = before.difference(&after);
+
+        assert_eq!(to_load.len(), 5);
+        assert!(to_load.iter().all(|coord| coord.x == 3));
+        assert_eq!(to_unload.len(), 5);
+        assert!(to_unload.iter().all(|coord| coord.x == -2));
+    }
+
+    #[test]
+    fn difference_matches_contains() {
+        let before = area(-3, 4);
+        let after = area(-1, 3);
+
+        let to_load = after.difference(&before);
+
+        assert_eq!(
+            to_load,
+            after
+                .chunks()
+                .into_iter()
+                .filter(|coord| !before.contains(coord))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(after.chunks().len(), 25);
+        assert!(after.difference(&after).is_empty());
+
+        // Areas far apart share nothing
+        assert_eq!(area(20, 20).difference(&before).len(), 25);
+    }
+}
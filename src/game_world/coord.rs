@@ -1,36 +1,81 @@
-use crate::settings::{CoordSystemIntegerSize, CHUNK_HEIGHT, CHUNK_SIZE, MAX_OFFSET};
+use crate::settings::{CoordSystemIntegerSize, CHUNK_HEIGHT, CHUNK_SIZE, CUBIC_CHUNKS, MAX_OFFSET};
 use bevy::prelude::{Component, Deref, DerefMut, Transform};
-use bevy_rapier3d::na::{Point2, Point3};
+use bevy_rapier3d::na::Point3;
 use std::ops::Add;
 
 /// ChunkCoord is the coordinate of the chunk in using the
-/// value 1 for each chunk. Multiply ChunkCoord by CHUNK_SIZE
-/// to get offset in real world
+/// value 1 for each chunk. Multiply x and z by CHUNK_SIZE and
+/// y by CHUNK_HEIGHT to get offset in real world.
+///
+/// y is always 0 unless the `cubic_chunks` feature is enabled
 #[derive(Deref, DerefMut, Clone, PartialEq, Eq, Hash, Component, Debug, Default, Copy)]
-pub struct ChunkCoord(pub Point2<CoordSystemIntegerSize>);
+pub struct ChunkCoord(pub Point3<CoordSystemIntegerSize>);
 
 impl From<ChunkCoord> for Transform {
     fn from(chunk: ChunkCoord) -> Self {
         Transform::from_xyz(
             (chunk.x * CHUNK_SIZE) as f32,
-            0.0,
-            (chunk.y * CHUNK_SIZE) as f32,
+            (chunk.y * CHUNK_HEIGHT) as f32,
+            (chunk.z * CHUNK_SIZE) as f32,
         )
     }
 }
 
+impl ChunkCoord {
+    pub fn offset(&self, offset: [CoordSystemIntegerSize; 3]) -> Self {
+        ChunkCoord(Point3::new(
+            self.x + offset[0],
+            self.y + offset[1],
+            self.z + offset[2],
+        ))
+    }
+}
+
 impl From<Transform> for ChunkCoord {
     fn from(transform: Transform) -> Self {
-        ChunkCoord(Point2::new(
+        let y = match CUBIC_CHUNKS {
+            true => {
+                (transform.translation.y / CHUNK_HEIGHT as f32).floor() as CoordSystemIntegerSize
+            }
+            false => 0,
+        };
+
+        ChunkCoord(Point3::new(
             (transform.translation.x / CHUNK_SIZE as f32).floor() as CoordSystemIntegerSize,
+            y,
             (transform.translation.z / CHUNK_SIZE as f32).floor() as CoordSystemIntegerSize,
         ))
     }
 }
 
+/// Coordinate of a block in the whole world, whatever chunk it is in
 #[derive(Deref, DerefMut, Clone, PartialEq, Eq, Hash, Component, Debug, Default, Copy)]
 pub struct GlobalVoxelBlockCoord(Point3<CoordSystemIntegerSize>);
 
+impl GlobalVoxelBlockCoord {
+    pub fn new(
+        x: CoordSystemIntegerSize,
+        y: CoordSystemIntegerSize,
+        z: CoordSystemIntegerSize,
+    ) -> Self {
+        GlobalVoxelBlockCoord(Point3::new(x, y, z))
+    }
+
+    /// Whether the block is above or below the chunk columns, where there is
+    /// nothing but air. Never the case with the `cubic_chunks` feature.
+    pub fn is_outside_column(&self) -> bool {
+        !CUBIC_CHUNKS && !(0..CHUNK_HEIGHT).contains(&self.y)
+    }
+
+    pub fn offset(&self, offset: [CoordSystemIntegerSize; 3]) -> Self {
+        GlobalVoxelBlockCoord(Point3::new(
+            self.x + offset[0],
+            self.y + offset[1],
+            self.z + offset[2],
+        ))
+    }
+}
+
 #[derive(Deref, DerefMut, Clone, PartialEq, Eq, Hash, Component, Debug, Default, Copy)]
 pub struct LocalVoxelBlockCoord(pub Point3<CoordSystemIntegerSize>);
 
@@ -146,25 +191,29 @@ impl From<(ChunkCoord, LocalVoxelBlockCoord)> for GlobalVoxelBlockCoord {
     fn from((chunk_coord, local_block_coord): (ChunkCoord, LocalVoxelBlockCoord)) -> Self {
         GlobalVoxelBlockCoord(Point3::new(
             chunk_coord.x * CHUNK_SIZE + local_block_coord.x,
-            local_block_coord.y,
-            chunk_coord.y * CHUNK_SIZE + local_block_coord.z,
+            chunk_coord.y * CHUNK_HEIGHT + local_block_coord.y,
+            chunk_coord.z * CHUNK_SIZE + local_block_coord.z,
         ))
     }
 }
 
+/// Negative coordinates belong to the chunks below zero: -1 is the last block
+/// of chunk -1, not the first block of chunk 0
 impl From<GlobalVoxelBlockCoord> for (ChunkCoord, LocalVoxelBlockCoord) {
     fn from(global_voxel_block_coord: GlobalVoxelBlockCoord) -> Self {
+        let dimensions = [CHUNK_SIZE, CHUNK_HEIGHT, CHUNK_SIZE];
+        let global = [
+            global_voxel_block_coord.x,
+            global_voxel_block_coord.y,
+            global_voxel_block_coord.z,
+        ];
+
         (
-            ChunkCoord(Point2::new(
-                (global_voxel_block_coord.x as f32 / CHUNK_SIZE as f32).floor()
-                    as CoordSystemIntegerSize,
-                (global_voxel_block_coord.z as f32 / CHUNK_SIZE as f32).floor()
-                    as CoordSystemIntegerSize,
+            ChunkCoord(Point3::from(
+                [0, 1, 2].map(|axis| global[axis].div_euclid(dimensions[axis])),
             )),
-            LocalVoxelBlockCoord(Point3::new(
-                ((global_voxel_block_coord.x % CHUNK_SIZE) + CHUNK_SIZE) % CHUNK_SIZE,
-                global_voxel_block_coord.y,
-                ((global_voxel_block_coord.z % CHUNK_SIZE) + CHUNK_SIZE) % CHUNK_SIZE,
+            LocalVoxelBlockCoord(Point3::from(
+                [0, 1, 2].map(|axis| global[axis].rem_euclid(dimensions[axis])),
             )),
         )
     }
@@ -192,6 +241,12 @@ impl From<[CoordSystemIntegerSize; 3]> for LocalVoxelBlockCoord {
     }
 }
 
+impl From<[CoordSystemIntegerSize; 3]> for GlobalVoxelBlockCoord {
+    fn from(value: [CoordSystemIntegerSize; 3]) -> Self {
+        Self(Point3::from(value))
+    }
+}
+
 impl TryFrom<[CoordSystemIntegerSize; 3]> for LocalVoxelBlockOffset {
     type Error = ();
     fn try_from(value: [CoordSystemIntegerSize; 3]) -> Result<Self, Self::Error> {
@@ -249,6 +304,7 @@ mod tests {
 
         assert_eq!(chunk_coord.x, 0);
         assert_eq!(chunk_coord.y, 0);
+        assert_eq!(chunk_coord.z, 0);
 
         assert_eq!(local_block_coord.x, 0);
         assert_eq!(local_block_coord.y, 0);
@@ -261,23 +317,25 @@ mod tests {
         println!("{:?} - {:?}", chunk_coord, local_block_coord);
 
         assert_eq!(chunk_coord.x, 1);
-        assert_eq!(chunk_coord.y, 2);
+        assert_eq!(chunk_coord.y, 0);
+        assert_eq!(chunk_coord.z, 2);
 
         assert_eq!(local_block_coord.x, 0);
         assert_eq!(local_block_coord.y, 0);
         assert_eq!(local_block_coord.z, 0);
 
-        let global = GlobalVoxelBlockCoord(Point3::new(-1, 100, -17));
+        let global = GlobalVoxelBlockCoord(Point3::new(-1, CHUNK_HEIGHT + 5, -17));
 
         let (chunk_coord, local_block_coord) = global.into();
 
         println!("{:?} - {:?}", chunk_coord, local_block_coord);
 
         assert_eq!(chunk_coord.x, -1);
-        assert_eq!(chunk_coord.y, -2);
+        assert_eq!(chunk_coord.y, 1);
+        assert_eq!(chunk_coord.z, -2);
 
         assert_eq!(local_block_coord.x, 15);
-        assert_eq!(local_block_coord.y, 100);
+        assert_eq!(local_block_coord.y, 5);
         assert_eq!(local_block_coord.z, 15);
 
         let global = GlobalVoxelBlockCoord(Point3::new(100, 22, -100));
@@ -287,16 +345,38 @@ mod tests {
         println!("{:?} - {:?}", chunk_coord, local_block_coord);
 
         assert_eq!(chunk_coord.x, 6);
-        assert_eq!(chunk_coord.y, -7);
+        assert_eq!(chunk_coord.y, 22 / CHUNK_HEIGHT);
+        assert_eq!(chunk_coord.z, -7);
 
         assert_eq!(local_block_coord.x, 4);
-        assert_eq!(local_block_coord.y, 22);
+        assert_eq!(local_block_coord.y, 22 % CHUNK_HEIGHT);
         assert_eq!(local_block_coord.z, 12);
     }
 
+    #[test]
+    fn negative_y_from_global_to_local() {
+        let global = GlobalVoxelBlockCoord(Point3::new(3, -1, 5));
+
+        let (chunk_coord, local_block_coord) = global.into();
+
+        assert_eq!(chunk_coord.y, -1);
+        assert_eq!(local_block_coord.y, CHUNK_HEIGHT - 1);
+
+        let global = GlobalVoxelBlockCoord(Point3::new(3, -CHUNK_HEIGHT, 5));
+
+        let (chunk_coord, local_block_coord) = global.into();
+
+        assert_eq!(chunk_coord.y, -1);
+        assert_eq!(local_block_coord.y, 0);
+
+        let back: GlobalVoxelBlockCoord = (chunk_coord, local_block_coord).into();
+
+        assert_eq!(back, global);
+    }
+
     #[test]
     fn from_local_to_global() {
-        let chunk_coord = ChunkCoord(Point2::new(0, 0));
+        let chunk_coord = ChunkCoord(Point3::new(0, 0, 0));
         let local_block_coord = LocalVoxelBlockCoord(Point3::new(0, 0, 0));
 
         let global_coord: GlobalVoxelBlockCoord = (chunk_coord, local_block_coord).into();
@@ -305,7 +385,7 @@ mod tests {
         assert_eq!(global_coord.y, 0);
         assert_eq!(global_coord.z, 0);
 
-        let chunk_coord = ChunkCoord(Point2::new(3, 4));
+        let chunk_coord = ChunkCoord(Point3::new(3, 0, 4));
         let local_block_coord = LocalVoxelBlockCoord(Point3::new(2, 5, 8));
 
         let global_coord: GlobalVoxelBlockCoord = (chunk_coord, local_block_coord).into();
@@ -314,7 +394,7 @@ mod tests {
         assert_eq!(global_coord.y, 5);
         assert_eq!(global_coord.z, 72);
 
-        let chunk_coord = ChunkCoord(Point2::new(-2, -4));
+        let chunk_coord = ChunkCoord(Point3::new(-2, 0, -4));
         let local_block_coord = LocalVoxelBlockCoord(Point3::new(3, 7, 15));
 
         let global_coord: GlobalVoxelBlockCoord = (chunk_coord, local_block_coord).into();
@@ -322,6 +402,15 @@ mod tests {
         assert_eq!(global_coord.x, -29);
         assert_eq!(global_coord.y, 7);
         assert_eq!(global_coord.z, -49);
+
+        let chunk_coord = ChunkCoord(Point3::new(1, -2, 0));
+        let local_block_coord = LocalVoxelBlockCoord(Point3::new(0, 3, 0));
+
+        let global_coord: GlobalVoxelBlockCoord = (chunk_coord, local_block_coord).into();
+
+        assert_eq!(global_coord.x, 16);
+        assert_eq!(global_coord.y, 3 - 2 * CHUNK_HEIGHT);
+        assert_eq!(global_coord.z, 0);
     }
 
     #[test]
@@ -1,20 +1,26 @@
 use crate::chunk::block::BlockMaterial;
+use crate::chunk::neighborhood::face_neighbor_offsets;
 use crate::chunk::procedural::generate_chunk;
-use crate::chunk::voxel_chunk::{spawn_chunk_from_data, ChunkData};
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::structure::StructureTemplates;
+use crate::chunk::voxel_chunk::{face_neighbors, spawn_chunk_from_data, ChunkData, VoxelChunk};
+use crate::game_world::area::ChunkArea;
 use crate::game_world::coord::ChunkCoord;
 use crate::game_world::player_position::{PlayerChangedChunkCoordEvent, PlayerLastChunkCoord};
 use crate::game_world::GameWorld;
+use crate::player::ThePlayer;
 use crate::settings::GameSettingResource;
-use bevy::platform::collections::HashMap;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
 use bevy::tasks::futures_lite::future;
 use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
 
-use bevy_rapier3d::na::Point2;
-
+/// Chunks waiting to be generated and the generation tasks in flight. A
+/// chunk is in at most one of them.
 #[derive(Resource, Debug, Default)]
 pub struct ChunkGenerationTaskMap {
     chunks: HashMap<ChunkCoord, Task<ChunkData>>,
+    queued: HashSet<ChunkCoord>,
 }
 
 #[derive(Component, Default, Debug)]
@@ -23,52 +29,56 @@ pub struct ChunkKeepAlive {
     pub last_touch: f32,
 }
 
-pub fn begin_generating_map_chunks(
+fn generation_area(game_setting_resource: &GameSettingResource, center: ChunkCoord) -> ChunkArea {
+    let world = &game_setting_resource.settings.world;
+
+    ChunkArea::new(
+        center,
+        world.generation_radius(),
+        world.vertical_generation_radius(),
+    )
+}
+
+/// Queues the chunks that came into range since the previous position,
+/// or the whole area when it is asked to be generated again. In that case
+/// the chunks in flight are dropped too since they are out of date.
+pub fn queue_map_chunks(
     mut ev_changed_coord: EventReader<PlayerChangedChunkCoordEvent>,
     mut generation_tasks: ResMut<ChunkGenerationTaskMap>,
     game_world: Res<GameWorld>,
     game_setting_resource: Res<GameSettingResource>,
+    block_registry: Res<BlockRegistry>,
 ) {
-    if ev_changed_coord.is_empty() {
+    // The world is generated again once the block definitions are loaded
+    if ev_changed_coord.is_empty() || !block_registry.is_loaded() {
         return;
     }
     let mut total = 0;
 
-    let task_pool = AsyncComputeTaskPool::get();
-
     for ev in ev_changed_coord.read() {
-        for x in (ev.new_position.x
-            - game_setting_resource.settings.world.world_dimension
-            - game_setting_resource.settings.world.preload_extra_distance)
-            ..(ev.new_position.x
-                + game_setting_resource.settings.world.world_dimension
-                + game_setting_resource.settings.world.preload_extra_distance)
-        {
-            for y in (ev.new_position.y
-                - game_setting_resource.settings.world.world_dimension
-                - game_setting_resource.settings.world.preload_extra_distance)
-                ..(ev.new_position.y
-                    + game_setting_resource.settings.world.world_dimension
-                    + game_setting_resource.settings.world.preload_extra_distance)
-            {
-                let chunk_coord = ChunkCoord(Point2::new(x, y));
-
-                if game_world.get(&chunk_coord).is_none() {
-                    total += 1;
+        let area = generation_area(&game_setting_resource, ev.new_position);
+        let chunk_coords = match ev.is_reload() {
+            true => {
+                generation_tasks.chunks.clear();
+                generation_tasks.queued.clear();
 
-                    if game_setting_resource
-                        .settings
-                        .logs
-                        .update_as_we_move_enabled
-                    {
-                        info!("{:?} has been updated", chunk_coord);
-                    }
+                area.chunks()
+            }
+            false => area.difference(&generation_area(
+                &game_setting_resource,
+                ev.previous_position,
+            )),
+        };
 
-                    let gs = game_setting_resource.settings.clone();
-                    let task = task_pool.spawn(async move { generate_chunk(&chunk_coord, &gs) });
+        for chunk_coord in chunk_coords {
+            if game_world.contains_key(&chunk_coord)
+                || generation_tasks.chunks.contains_key(&chunk_coord)
+            {
+                continue;
+            }
 
-                    generation_tasks.chunks.insert(chunk_coord, task);
-                };
+            if generation_tasks.queued.insert(chunk_coord) {
+                total += 1;
             }
         }
     }
@@ -78,7 +88,98 @@ pub fn begin_generating_map_chunks(
         .logs
         .update_as_we_move_enabled
     {
-        info!("Generated {} chunks", total);
+        info!("Queued {} chunks", total);
+    }
+}
+
+/// Lower is generated first. It is the distance to the player, stretched up
+/// to twice for the chunks behind the camera, so that the chunk under the
+/// player comes first and the ones in view come before the ones behind.
+pub fn generation_priority(player: &ChunkCoord, forward: Vec3, chunk: &ChunkCoord) -> f32 {
+    let offset = Vec3::new(
+        (chunk.x - player.x) as f32,
+        (chunk.y - player.y) as f32,
+        (chunk.z - player.z) as f32,
+    );
+
+    let facing = offset.normalize_or_zero().dot(forward.normalize_or_zero());
+
+    offset.length() * (1.5 - 0.5 * facing)
+}
+
+/// Starts the generation of the queued chunks with the best priority, up to
+/// `max_generation_tasks` in flight. Queued chunks and tasks that left the
+/// generation area are dropped, which cancels the tasks.
+pub fn begin_generating_map_chunks(
+    mut generation_tasks: ResMut<ChunkGenerationTaskMap>,
+    game_world: Res<GameWorld>,
+    player_last_chunk_coord: Res<PlayerLastChunkCoord>,
+    player: Query<&Transform, With<ThePlayer>>,
+    game_setting_resource: Res<GameSettingResource>,
+    block_registry: Res<BlockRegistry>,
+    structure_templates: Res<StructureTemplates>,
+) {
+    if generation_tasks.queued.is_empty() && generation_tasks.chunks.is_empty() {
+        return;
+    }
+
+    let player_coord = **player_last_chunk_coord;
+    let area = generation_area(&game_setting_resource, player_coord);
+
+    let generation_tasks = generation_tasks.as_mut();
+    generation_tasks
+        .queued
+        .retain(|chunk_coord| area.contains(chunk_coord) && !game_world.contains_key(chunk_coord));
+    generation_tasks
+        .chunks
+        .retain(|chunk_coord, _| area.contains(chunk_coord));
+
+    let free = game_setting_resource
+        .settings
+        .world
+        .max_generation_tasks
+        .saturating_sub(generation_tasks.chunks.len());
+
+    if free == 0 || generation_tasks.queued.is_empty() {
+        return;
+    }
+
+    let forward = player
+        .single()
+        .map(|transform| *transform.forward())
+        .unwrap_or_default();
+
+    let mut queued: Vec<(f32, ChunkCoord)> = generation_tasks
+        .queued
+        .iter()
+        .map(|chunk_coord| {
+            (
+                generation_priority(&player_coord, forward, chunk_coord),
+                *chunk_coord,
+            )
+        })
+        .collect();
+    queued.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for (_, chunk_coord) in queued.into_iter().take(free) {
+        if game_setting_resource
+            .settings
+            .logs
+            .update_as_we_move_enabled
+        {
+            info!("{:?} has been updated", chunk_coord);
+        }
+
+        let gs = game_setting_resource.settings.clone();
+        let registry = block_registry.clone();
+        let structures = structure_templates.0.clone();
+        let task = task_pool
+            .spawn(async move { generate_chunk(&chunk_coord, &gs, &registry, &structures) });
+
+        generation_tasks.queued.remove(&chunk_coord);
+        generation_tasks.chunks.insert(chunk_coord, task);
     }
 }
 
@@ -87,54 +188,153 @@ pub fn receive_generated_map_chunks(
     mut mesh_manager: ResMut<Assets<Mesh>>,
     mut commands: Commands,
     mut generation_tasks: ResMut<ChunkGenerationTaskMap>,
+    player_last_chunk_coord: Res<PlayerLastChunkCoord>,
+    time: Res<Time>,
+    game_setting_resource: Res<GameSettingResource>,
 ) {
+    let area = unload_area(&game_setting_resource, **player_last_chunk_coord);
+
     generation_tasks.chunks.retain(|chunk_coord, task| {
         let status = block_on(future::poll_once(task));
 
         let retain = status.is_none();
 
         if let Some(chunk_data) = status {
-            spawn_chunk_from_data(
+            let chunk_entity = spawn_chunk_from_data(
                 chunk_data,
                 *chunk_coord,
                 &block_material,
                 &mut mesh_manager,
                 &mut commands,
             );
+
+            commands.entity(chunk_entity).insert(ChunkKeepAlive {
+                last_touch: time.elapsed_secs(),
+            });
+
+            // The player moved away while the chunk was generated
+            if !area.contains(chunk_coord) {
+                commands.entity(chunk_entity).insert(Visibility::Hidden);
+            }
         }
 
         retain
     });
 }
 
-pub fn touch_chunks_around_player_at_interval(
-    mut query: Query<(&ChunkCoord, &mut ChunkKeepAlive, &mut Visibility)>,
-    player_last_chunk_coord: Res<PlayerLastChunkCoord>,
+fn unload_area(game_setting_resource: &GameSettingResource, center: ChunkCoord) -> ChunkArea {
+    let world = &game_setting_resource.settings.world;
+
+    ChunkArea::new(
+        center,
+        world.unload_distance,
+        world.vertical_unload_radius(),
+    )
+}
+
+/// Chunks leaving the unload distance are hidden and their grace period
+/// starts. The ones coming back before it ends are shown again. The borders
+/// of the chunks around them are meshed again, to show or cull the faces
+/// toward them.
+pub fn touch_chunks_leaving_and_entering_range(
+    mut ev_changed_coord: EventReader<PlayerChangedChunkCoordEvent>,
+    mut query: Query<(&mut ChunkKeepAlive, &mut Visibility)>,
+    mut chunks: Query<&mut VoxelChunk>,
+    game_world: Res<GameWorld>,
     time: Res<Time>,
     game_setting_resource: Res<GameSettingResource>,
 ) {
-    let mut total = 0;
+    for ev in ev_changed_coord.read() {
+        if ev.is_reload() {
+            continue;
+        }
+
+        let previous = unload_area(&game_setting_resource, ev.previous_position);
+        let new = unload_area(&game_setting_resource, ev.new_position);
+
+        let leaving = previous.difference(&new);
+        let entering = new.difference(&previous);
 
-    for (coord, mut keepalive, mut visibility) in query.iter_mut() {
-        if (coord.x - player_last_chunk_coord.x).abs()
-            < (game_setting_resource.settings.world.world_dimension
-                + game_setting_resource.settings.world.preload_extra_distance)
-            && (coord.y - player_last_chunk_coord.y).abs()
-                < (game_setting_resource.settings.world.world_dimension
-                    + game_setting_resource.settings.world.preload_extra_distance)
+        if game_setting_resource
+            .settings
+            .logs
+            .update_as_we_move_enabled
         {
-            keepalive.last_touch = time.elapsed_secs();
+            info!(
+                "{} chunks leave the range, {} chunks enter it",
+                leaving.len(),
+                entering.len()
+            );
+        }
+
+        for (chunk_coords, visibility) in [
+            (leaving, Visibility::Hidden),
+            (entering, Visibility::Visible),
+        ] {
+            for chunk_coord in chunk_coords {
+                let Some(Ok((mut keepalive, mut chunk_visibility))) = game_world
+                    .get(&chunk_coord)
+                    .map(|entity| query.get_mut(*entity))
+                else {
+                    continue;
+                };
 
-            total += 1;
+                keepalive.last_touch = time.elapsed_secs();
+                *chunk_visibility = visibility;
 
-            if (coord.x - player_last_chunk_coord.x).abs()
-                < game_setting_resource.settings.world.world_dimension
-                && (coord.y - player_last_chunk_coord.y).abs()
-                    < game_setting_resource.settings.world.world_dimension
-            {
-                *visibility = Visibility::Visible;
+                for (neighbor, toward) in face_neighbors(&game_world, &chunk_coord) {
+                    if let Ok(mut neighbor_chunk) = chunks.get_mut(neighbor) {
+                        neighbor_chunk.mark_dirty_toward(toward);
+                    }
+                }
+
+                // The chunks around one shown again may have been hidden or
+                // unloaded in the meantime
+                if visibility == Visibility::Visible {
+                    if let Some(Ok(mut chunk)) = game_world
+                        .get(&chunk_coord)
+                        .map(|entity| chunks.get_mut(*entity))
+                    {
+                        for offset in face_neighbor_offsets() {
+                            chunk.mark_dirty_toward(offset);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Despawns the chunks that stayed out of range for the whole grace period,
+/// and meshes again the borders of the chunks around them
+pub fn unload_stale_chunks_at_interval(
+    mut commands: Commands,
+    mut game_world: ResMut<GameWorld>,
+    query: Query<(Entity, &ChunkCoord, &ChunkKeepAlive)>,
+    mut chunks: Query<&mut VoxelChunk>,
+    player_last_chunk_coord: Res<PlayerLastChunkCoord>,
+    time: Res<Time>,
+    game_setting_resource: Res<GameSettingResource>,
+) {
+    let area = unload_area(&game_setting_resource, **player_last_chunk_coord);
+    let grace_period = game_setting_resource.settings.world.unload_grace_period;
+    let mut total = 0;
+
+    for (entity, chunk_coord, keepalive) in query.iter() {
+        if area.contains(chunk_coord) || time.elapsed_secs() - keepalive.last_touch < grace_period {
+            continue;
+        }
+
+        for (neighbor, toward) in face_neighbors(&game_world, chunk_coord) {
+            if let Ok(mut neighbor_chunk) = chunks.get_mut(neighbor) {
+                neighbor_chunk.mark_dirty_toward(toward);
             }
         }
+
+        commands.entity(entity).despawn();
+        game_world.remove(chunk_coord);
+
+        total += 1;
     }
 
     if total > 0
@@ -143,6 +343,31 @@ pub fn touch_chunks_around_player_at_interval(
             .logs
             .update_as_we_move_enabled
     {
-        info!("Touch chunks {} times", total);
+        info!("Unloaded {} chunks", total);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_rapier3d::na::Point3;
+
+    fn chunk(x: i32, z: i32) -> ChunkCoord {
+        ChunkCoord(Point3::new(x, 0, z))
+    }
+
+    #[test]
+    fn chunks_in_view_are_generated_first() {
+        let player = chunk(4, 4);
+        // Looking toward -z
+        let forward = Vec3::NEG_Z;
+        let priority = |x, z| generation_priority(&player, forward, &chunk(x, z));
+
+        assert_eq!(priority(4, 4), 0.0);
+        assert!(priority(4, 3) < priority(4, 5));
+        assert!(priority(4, 3) < priority(5, 4));
+        assert!(priority(5, 4) < priority(4, 5));
+        // Close chunks behind still come before the far ones in view
+        assert!(priority(4, 5) < priority(4, 1));
     }
 }
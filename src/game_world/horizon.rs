@@ -0,0 +1,302 @@
+use crate::chunk::noise::Noise;
+use crate::chunk::procedural::surface_height;
+use crate::game_world::coord::ChunkCoord;
+use crate::game_world::{GameWorld, PlayerLastChunkCoord};
+use crate::settings::{
+    CoordSystemIntegerSize, GameSettingResource, NoiseConfigurationChangedEvent, CHUNK_SIZE,
+};
+use bevy::asset::RenderAssetUsages;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use bevy::tasks::futures_lite::future;
+use bevy::tasks::{block_on, AsyncComputeTaskPool, Task};
+use bevy_rapier3d::na::Point3;
+
+/// Horizontal coordinate of a column of chunks
+type Column = [CoordSystemIntegerSize; 2];
+
+/// Coarse heightmap tiles drawn past the generated chunks, one for each
+/// column of chunks, so that the terrain goes on up to the horizon. The
+/// tile of a column is removed as soon as one of its chunks is loaded.
+#[derive(Resource)]
+pub struct HorizonTiles {
+    tiles: HashMap<Column, Entity>,
+    /// Tiles built off the main thread, at most `max_tile_tasks` at once
+    tasks: HashMap<Column, Task<Mesh>>,
+    material: Handle<StandardMaterial>,
+    /// Some tiles were left for the next frames
+    incomplete: bool,
+}
+
+impl FromWorld for HorizonTiles {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+
+        Self {
+            tiles: HashMap::new(),
+            tasks: HashMap::new(),
+            // Roughly the colour of grass seen from afar
+            material: materials.add(StandardMaterial {
+                base_color: Color::srgb(0.42, 0.72, 0.19),
+                perceptual_roughness: 1.0,
+                ..default()
+            }),
+            incomplete: false,
+        }
+    }
+}
+
+#[derive(Component, Debug)]
+pub struct HorizonTile;
+
+/// Heightmap of the terrain surface of a column, sampled every `step` blocks.
+/// The samples on the edges are shared with the neighbouring tiles so that
+/// they join without gaps. Vertices are in chunk space, like the chunk meshes.
+pub fn horizon_tile_mesh(
+    height_noise: &Noise,
+    column: Column,
+    step: CoordSystemIntegerSize,
+) -> Mesh {
+    let samples = (CHUNK_SIZE / step) as usize + 1;
+    let origin = column.map(|c| c * CHUNK_SIZE);
+
+    // One more sample on each side for the normals of the edges
+    let padded = samples + 2;
+    let heights: Vec<f32> = (0..padded * padded)
+        .map(|index| {
+            let i = (index % padded) as CoordSystemIntegerSize - 1;
+            let j = (index / padded) as CoordSystemIntegerSize - 1;
+
+            surface_height(height_noise, origin[0] + i * step, origin[1] + j * step) as f32
+        })
+        .collect();
+    let height = |i: usize, j: usize| heights[(i + 1) + (j + 1) * padded];
+
+    let mut positions = Vec::with_capacity(samples * samples);
+    let mut normals = Vec::with_capacity(samples * samples);
+
+    for j in 0..samples {
+        for i in 0..samples {
+            // Top of the surface block
+            positions.push([
+                (i as CoordSystemIntegerSize * step) as f32 - 0.5,
+                height(i, j) + 0.5,
+                (j as CoordSystemIntegerSize * step) as f32 - 0.5,
+            ]);
+
+            let normal = Vec3::new(
+                heights[i + (j + 1) * padded] - heights[(i + 2) + (j + 1) * padded],
+                2.0 * step as f32,
+                heights[(i + 1) + j * padded] - heights[(i + 1) + (j + 2) * padded],
+            )
+            .normalize();
+            normals.push(normal.to_array());
+        }
+    }
+
+    let mut indices: Vec<u32> = vec![];
+    for j in 0..samples - 1 {
+        for i in 0..samples - 1 {
+            let a = (i + j * samples) as u32;
+            let b = a + samples as u32;
+            let c = b + 1;
+            let d = a + 1;
+
+            indices.extend([a, b, c, a, c, d]);
+        }
+    }
+
+    let mut mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_indices(Indices::U32(indices));
+
+    mesh
+}
+
+/// Keeps a tile on every column within the horizon distance that has no
+/// loaded chunk. The closest missing tiles are built first, a few at a time
+/// off the main thread. Tiles and tasks that are no longer wanted are
+/// dropped, which cancels the tasks.
+pub fn update_horizon_tiles(
+    mut commands: Commands,
+    mut horizon_tiles: ResMut<HorizonTiles>,
+    mut ev_noise_changed: EventReader<NoiseConfigurationChangedEvent>,
+    game_world: Res<GameWorld>,
+    player_last_chunk_coord: Res<PlayerLastChunkCoord>,
+    game_setting_resource: Res<GameSettingResource>,
+) {
+    let horizon_tiles = horizon_tiles.as_mut();
+
+    // The terrain changed, every tile is out of date
+    let noise_changed = !ev_noise_changed.is_empty();
+    ev_noise_changed.clear();
+
+    if noise_changed {
+        for (_, entity) in horizon_tiles.tiles.drain() {
+            commands.entity(entity).despawn();
+        }
+
+        horizon_tiles.tasks.clear();
+    }
+
+    if !noise_changed
+        && !horizon_tiles.incomplete
+        && !game_world.is_changed()
+        && !player_last_chunk_coord.is_changed()
+    {
+        return;
+    }
+
+    let settings = &game_setting_resource.settings.horizon;
+    let player: Column = [player_last_chunk_coord.x, player_last_chunk_coord.z];
+    // Chunks past the unload distance are hidden until they are despawned
+    let unload_distance = game_setting_resource.settings.world.unload_distance;
+    let loaded: HashSet<Column> = game_world
+        .keys()
+        .map(|coord| [coord.x, coord.z])
+        .filter(|column| (0..2).all(|axis| (column[axis] - player[axis]).abs() <= unload_distance))
+        .collect();
+
+    // A distance of 0 disables the horizon, even under the player
+    let is_wanted = |column: &Column| {
+        settings.distance > 0
+            && (0..2).all(|axis| (column[axis] - player[axis]).abs() <= settings.distance)
+            && !loaded.contains(column)
+    };
+
+    horizon_tiles.tiles.retain(|column, entity| {
+        let keep = is_wanted(column);
+
+        if !keep {
+            commands.entity(*entity).despawn();
+        }
+
+        keep
+    });
+    horizon_tiles.tasks.retain(|column, _| is_wanted(column));
+
+    let mut missing: Vec<Column> = vec![];
+    for x in (player[0] - settings.distance)..=(player[0] + settings.distance) {
+        for z in (player[1] - settings.distance)..=(player[1] + settings.distance) {
+            let column = [x, z];
+
+            if is_wanted(&column)
+                && !horizon_tiles.tiles.contains_key(&column)
+                && !horizon_tiles.tasks.contains_key(&column)
+            {
+                missing.push(column);
+            }
+        }
+    }
+
+    missing.sort_by_key(|column| (column[0] - player[0]).pow(2) + (column[1] - player[1]).pow(2));
+
+    let free = settings
+        .max_tile_tasks
+        .saturating_sub(horizon_tiles.tasks.len());
+    horizon_tiles.incomplete = missing.len() > free;
+
+    let task_pool = AsyncComputeTaskPool::get();
+
+    for column in missing.into_iter().take(free) {
+        let height_noise = game_setting_resource.settings.procedural.base_noise.clone();
+        let step = settings.step;
+        let task = task_pool.spawn(async move { horizon_tile_mesh(&height_noise, column, step) });
+
+        horizon_tiles.tasks.insert(column, task);
+    }
+}
+
+/// Spawns the tiles whose mesh is built
+pub fn receive_horizon_tiles(
+    mut commands: Commands,
+    mut horizon_tiles: ResMut<HorizonTiles>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    let horizon_tiles = horizon_tiles.as_mut();
+    let material = &horizon_tiles.material;
+    let tiles = &mut horizon_tiles.tiles;
+
+    horizon_tiles.tasks.retain(|column, task| {
+        let Some(mesh) = block_on(future::poll_once(task)) else {
+            return true;
+        };
+
+        let entity = commands
+            .spawn((
+                HorizonTile,
+                Mesh3d(meshes.add(mesh)),
+                MeshMaterial3d(material.clone()),
+                Transform::from(ChunkCoord(Point3::new(column[0], 0, column[1]))),
+            ))
+            .id();
+
+        tiles.insert(*column, entity);
+
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::tests::game_settings;
+    use crate::settings::{CHUNK_HEIGHT, CUBIC_CHUNKS};
+    use bevy::render::mesh::VertexAttributeValues;
+    use noise::NoiseFn;
+
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+            _ => panic!("the tile has no positions"),
+        }
+    }
+
+    #[test]
+    fn surface_matches_the_generated_blocks() {
+        let noise = game_settings().procedural.base_noise;
+
+        for (x, z) in [(0, 0), (37, -12), (-150, 260), (411, 5)] {
+            // Highest block the generator fills in this column
+            let world_height = match CUBIC_CHUNKS {
+                true => noise.max_value() as CoordSystemIntegerSize + 1,
+                false => CHUNK_HEIGHT,
+            };
+            let top = (0..world_height)
+                .rev()
+                .find(|y| {
+                    noise.get([x as f64, *y as f64, z as f64]) as CoordSystemIntegerSize >= *y
+                })
+                .unwrap_or(-1);
+
+            assert_eq!(surface_height(&noise, x, z), top);
+        }
+    }
+
+    #[test]
+    fn neighboring_tiles_share_their_edge() {
+        let noise = game_settings().procedural.base_noise;
+        let step = 4;
+        let samples = (CHUNK_SIZE / step) as usize + 1;
+
+        let left = positions(&horizon_tile_mesh(&noise, [2, -3], step));
+        let right = positions(&horizon_tile_mesh(&noise, [3, -3], step));
+
+        assert_eq!(left.len(), samples * samples);
+
+        for j in 0..samples {
+            let left_edge = left[samples - 1 + j * samples];
+            let right_edge = right[j * samples];
+
+            assert_eq!(left_edge[0] - CHUNK_SIZE as f32, right_edge[0]);
+            assert_eq!(left_edge[1], right_edge[1]);
+            assert_eq!(left_edge[2], right_edge[2]);
+        }
+    }
+}
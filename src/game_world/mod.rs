@@ -1,20 +1,29 @@
+mod area;
 pub mod coord;
 mod generation;
+mod horizon;
 mod player_position;
+pub mod raycast;
+pub mod voxel_world;
 
 use crate::game_world::coord::ChunkCoord;
 use crate::game_world::generation::{
-    begin_generating_map_chunks, receive_generated_map_chunks,
-    touch_chunks_around_player_at_interval, ChunkGenerationTaskMap,
+    begin_generating_map_chunks, queue_map_chunks, receive_generated_map_chunks,
+    touch_chunks_leaving_and_entering_range, unload_stale_chunks_at_interval,
+    ChunkGenerationTaskMap,
 };
+use crate::game_world::horizon::{receive_horizon_tiles, update_horizon_tiles, HorizonTiles};
 use crate::game_world::player_position::{
     check_for_player_chunk_position_update, update_player_last_chunk_coord,
 };
 use bevy::platform::collections::HashMap;
 use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use std::time::Duration;
 
 pub use player_position::PlayerChangedChunkCoordEvent;
 pub use player_position::PlayerLastChunkCoord;
+pub use voxel_world::VoxelWorld;
 
 /// Holds the currently loaded chunks of the game world
 /// Depends on ChunkPlugin and PlayerPlugin
@@ -27,15 +36,22 @@ impl Plugin for GameWorldPlugin {
             .init_resource::<PlayerLastChunkCoord>()
             .init_resource::<ChunkGenerationTaskMap>()
             .init_resource::<GameWorld>()
+            .init_resource::<HorizonTiles>()
             .add_event::<PlayerChangedChunkCoordEvent>()
             .add_systems(
                 Update,
                 (
                     check_for_player_chunk_position_update,
                     update_player_last_chunk_coord,
-                    begin_generating_map_chunks,
-                    receive_generated_map_chunks,
-                    touch_chunks_around_player_at_interval,
+                    (
+                        queue_map_chunks,
+                        begin_generating_map_chunks,
+                        receive_generated_map_chunks,
+                    )
+                        .chain(),
+                    touch_chunks_leaving_and_entering_range,
+                    unload_stale_chunks_at_interval.run_if(on_timer(Duration::from_secs(1))),
+                    (update_horizon_tiles, receive_horizon_tiles).chain(),
                 ),
             );
     }
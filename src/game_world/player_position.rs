@@ -6,13 +6,20 @@ use bevy::prelude::*;
 #[derive(Resource, Deref, DerefMut, Debug, Clone, Default)]
 pub struct PlayerLastChunkCoord(ChunkCoord);
 
+/// The player moved into another chunk. When both positions are the same,
+/// the whole area around the player is asked to be generated again.
 #[derive(Event)]
 pub struct PlayerChangedChunkCoordEvent {
     pub new_position: ChunkCoord,
-    #[allow(dead_code)]
     pub previous_position: ChunkCoord,
 }
 
+impl PlayerChangedChunkCoordEvent {
+    pub fn is_reload(&self) -> bool {
+        self.new_position == self.previous_position
+    }
+}
+
 pub fn check_for_player_chunk_position_update(
     player_last_chunk_coord: Res<PlayerLastChunkCoord>,
     player: Query<&Transform, With<ThePlayer>>,
@@ -35,12 +42,10 @@ pub fn update_player_last_chunk_coord(
     mut ev_changed_coord: EventReader<PlayerChangedChunkCoordEvent>,
     game_setting_resource: Res<GameSettingResource>,
 ) {
-    if !game_setting_resource.settings.logs.change_chunk_enabled {
-        return;
-    }
-
     for ev in ev_changed_coord.read() {
-        info!("Player is now in chunk {}", *ev.new_position);
+        if game_setting_resource.settings.logs.change_chunk_enabled {
+            info!("Player is now in chunk {}", *ev.new_position);
+        }
 
         player_last_chunk_coord.0 = ev.new_position;
     }
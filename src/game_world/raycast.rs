@@ -0,0 +1,256 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::voxel_chunk::VoxelChunk;
+use crate::game_world::coord::{ChunkCoord, GlobalVoxelBlockCoord, LocalVoxelBlockCoord};
+use crate::game_world::GameWorld;
+use crate::settings::CoordSystemIntegerSize;
+use bevy::prelude::*;
+
+/// Block hit by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub coord: GlobalVoxelBlockCoord,
+    /// Normal of the face the ray entered the block through. Zero when the
+    /// ray starts inside the block.
+    pub normal: [CoordSystemIntegerSize; 3],
+    /// Distance from the origin of the ray to the face, in blocks
+    pub distance: f32,
+}
+
+impl GameWorld {
+    /// First block along a ray that `hits` accepts, no further than
+    /// `max_distance`. A chunk that is not loaded stops the ray, since what
+    /// is behind it is not known. Above and below the chunk columns, the ray
+    /// goes through air.
+    pub fn raycast<H>(
+        &self,
+        chunks: &Query<&VoxelChunk>,
+        ray: Ray3d,
+        max_distance: f32,
+        hits: H,
+    ) -> Option<RaycastHit>
+    where
+        H: Fn(VoxelBlock) -> bool,
+    {
+        raycast_blocks(
+            ray.origin,
+            *ray.direction,
+            max_distance,
+            |coord| {
+                if coord.is_outside_column() {
+                    return Some(VoxelBlock::default());
+                }
+
+                let (chunk_coord, block_coord): (ChunkCoord, LocalVoxelBlockCoord) = coord.into();
+
+                self.get(&chunk_coord)
+                    .and_then(|entity| chunks.get(*entity).ok())
+                    .and_then(|chunk| chunk.get_block(&block_coord).copied())
+            },
+            hits,
+        )
+    }
+}
+
+/// Walks the blocks a ray goes through in order, one face crossing at a time
+/// (Amanatides and Woo's DDA), until `hits` accepts one of them. Blocks are
+/// centered on their coordinates: block x spans x - 0.5 to x + 0.5. The walk
+/// stops past `max_distance`, or at a block `block_at` has no answer for.
+pub fn raycast_blocks<B, H>(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    block_at: B,
+    hits: H,
+) -> Option<RaycastHit>
+where
+    B: Fn(GlobalVoxelBlockCoord) -> Option<VoxelBlock>,
+    H: Fn(VoxelBlock) -> bool,
+{
+    let direction = direction.try_normalize()?;
+    // In this space, block x spans x to x + 1
+    let start = (origin + Vec3::splat(0.5)).to_array();
+    let direction = direction.to_array();
+
+    let mut cell = start.map(|v| v.floor() as CoordSystemIntegerSize);
+    let step = direction.map(|d| match d < 0.0 {
+        true => -1,
+        false => 1,
+    });
+    // Distance along the ray to cross a whole block on each axis
+    let delta = direction.map(|d| match d == 0.0 {
+        true => f32::INFINITY,
+        false => 1.0 / d.abs(),
+    });
+    // Distance along the ray to the next face on each axis
+    let mut next: [f32; 3] = std::array::from_fn(|axis| {
+        let face = match step[axis] > 0 {
+            true => cell[axis] as f32 + 1.0,
+            false => cell[axis] as f32,
+        };
+
+        match direction[axis] == 0.0 {
+            true => f32::INFINITY,
+            false => (face - start[axis]) / direction[axis],
+        }
+    });
+
+    let mut normal = [0; 3];
+    let mut distance = 0.0;
+
+    loop {
+        let coord = GlobalVoxelBlockCoord::from(cell);
+
+        if hits(block_at(coord)?) {
+            return Some(RaycastHit {
+                coord,
+                normal,
+                distance,
+            });
+        }
+
+        let axis = (0..3).min_by(|a, b| next[*a].total_cmp(&next[*b])).unwrap();
+
+        distance = next[axis];
+
+        if distance > max_distance {
+            return None;
+        }
+
+        cell[axis] += step[axis];
+        next[axis] += delta[axis];
+        normal = [0; 3];
+        normal[axis] = -step[axis];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::block::BlockId;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn solid() -> VoxelBlock {
+        VoxelBlock {
+            id: BlockId(1),
+            ..default()
+        }
+    }
+
+    /// Empty world with solid blocks where `is_solid` says so
+    fn world(
+        is_solid: impl Fn([CoordSystemIntegerSize; 3]) -> bool,
+    ) -> impl Fn(GlobalVoxelBlockCoord) -> Option<VoxelBlock> {
+        move |coord| match is_solid([coord.x, coord.y, coord.z]) {
+            true => Some(solid()),
+            false => Some(VoxelBlock::default()),
+        }
+    }
+
+    fn is_solid(block: VoxelBlock) -> bool {
+        !block.is_empty()
+    }
+
+    #[test]
+    fn ray_stops_at_the_face_of_the_first_block() {
+        let hit = raycast_blocks(
+            Vec3::ZERO,
+            Vec3::X,
+            10.0,
+            world(|p| p == [3, 0, 0] || p == [5, 0, 0]),
+            is_solid,
+        )
+        .unwrap();
+
+        assert_eq!(hit.coord, GlobalVoxelBlockCoord::from([3, 0, 0]));
+        assert_eq!(hit.normal, [-1, 0, 0]);
+        assert_eq!(hit.distance, 2.5);
+    }
+
+    #[test]
+    fn ray_walks_through_negative_coordinates() {
+        let hit = raycast_blocks(
+            Vec3::new(-3.0, -5.2, -10.0),
+            Vec3::NEG_Z,
+            10.0,
+            world(|p| p == [-3, -5, -14]),
+            is_solid,
+        )
+        .unwrap();
+
+        assert_eq!(hit.coord, GlobalVoxelBlockCoord::from([-3, -5, -14]));
+        assert_eq!(hit.normal, [0, 0, 1]);
+        assert_eq!(hit.distance, 3.5);
+
+        // Down a slope toward a wall, behind the origin on x
+        let direction = Vec3::new(-1.0, -0.4, 0.3);
+        let hit = raycast_blocks(
+            Vec3::new(0.2, 0.0, 0.0),
+            direction,
+            20.0,
+            world(|p| p[0] == -6),
+            is_solid,
+        )
+        .unwrap();
+
+        assert_eq!(hit.coord.x, -6);
+        assert_eq!(hit.normal, [1, 0, 0]);
+        let expected = 5.7 / direction.normalize().x.abs();
+        assert!((hit.distance - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn ray_starting_inside_a_block_hits_it() {
+        let hit = raycast_blocks(
+            Vec3::new(1.4, 0.0, 0.0),
+            Vec3::Y,
+            10.0,
+            world(|p| p == [1, 0, 0]),
+            is_solid,
+        )
+        .unwrap();
+
+        assert_eq!(hit.coord, GlobalVoxelBlockCoord::from([1, 0, 0]));
+        assert_eq!(hit.normal, [0, 0, 0]);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn ray_stops_at_its_length_and_at_unloaded_chunks() {
+        let wall = world(|p| p[2] == 8);
+
+        assert!(raycast_blocks(Vec3::ZERO, Vec3::Z, 7.0, &wall, is_solid).is_none());
+        assert!(raycast_blocks(Vec3::ZERO, Vec3::Z, 7.5, &wall, is_solid).is_some());
+
+        // Nothing is known past z = 4
+        let unloaded = |coord: GlobalVoxelBlockCoord| match coord.z {
+            ..=4 => Some(VoxelBlock::default()),
+            _ => None,
+        };
+
+        assert!(raycast_blocks(Vec3::ZERO, Vec3::Z, 20.0, unloaded, is_solid).is_none());
+        assert!(raycast_blocks(Vec3::ZERO, Vec3::ZERO, 20.0, &wall, is_solid).is_none());
+    }
+
+    #[cfg(not(feature = "cubic_chunks"))]
+    #[test]
+    fn ray_from_above_the_columns_reaches_the_ground() {
+        let mut world = World::new();
+        let entity = world.spawn(VoxelChunk::filled(solid())).id();
+        let mut game_world = GameWorld::default();
+        game_world.insert(ChunkCoord::default(), entity);
+        world.insert_resource(game_world);
+
+        let hit = world
+            .run_system_once(|game_world: Res<GameWorld>, chunks: Query<&VoxelChunk>| {
+                let ray = Ray3d::new(Vec3::new(3.0, 100.0, 3.0), Dir3::NEG_Y);
+
+                game_world.raycast(&chunks, ray, 30.0, is_solid)
+            })
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(hit.coord, GlobalVoxelBlockCoord::new(3, 79, 3));
+        assert_eq!(hit.normal, [0, 1, 0]);
+        assert_eq!(hit.distance, 20.5);
+    }
+}
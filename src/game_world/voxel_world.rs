@@ -0,0 +1,324 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::light::{Light, LightChannel, MAX_LIGHT};
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::voxel_chunk::{sections_around_block, VoxelChunk};
+use crate::game_world::coord::{ChunkCoord, GlobalVoxelBlockCoord, LocalVoxelBlockCoord};
+use crate::game_world::GameWorld;
+use crate::settings::CUBIC_CHUNKS;
+use bevy::ecs::system::SystemParam;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy_rapier3d::na::Point3;
+use std::fmt;
+
+/// The block is in a chunk that is not loaded, or not generated yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkNotLoaded(pub ChunkCoord);
+
+impl fmt::Display for ChunkNotLoaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chunk {} is not loaded", *self.0)
+    }
+}
+
+impl std::error::Error for ChunkNotLoaded {}
+
+/// Why blocks could not be replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetBlockError {
+    NotLoaded(ChunkNotLoaded),
+    /// The block is above or below the chunk columns, where nothing can be
+    /// put
+    OutOfWorld(GlobalVoxelBlockCoord),
+}
+
+impl fmt::Display for SetBlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetBlockError::NotLoaded(error) => error.fmt(f),
+            SetBlockError::OutOfWorld(coord) => {
+                write!(f, "block {} is above or below the world", **coord)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SetBlockError {}
+
+impl From<ChunkNotLoaded> for SetBlockError {
+    fn from(error: ChunkNotLoaded) -> Self {
+        SetBlockError::NotLoaded(error)
+    }
+}
+
+/// Blocks of the loaded chunks, by global coordinates. Replacing a block marks
+/// the sections showing it to be meshed again, and sends a
+/// `BlockChangedEvent` for it at the next frame.
+#[derive(SystemParam)]
+pub struct VoxelWorld<'w, 's> {
+    game_world: Res<'w, GameWorld>,
+    chunks: Query<'w, 's, &'static mut VoxelChunk>,
+    block_registry: Res<'w, BlockRegistry>,
+}
+
+impl VoxelWorld<'_, '_> {
+    fn chunk(&self, chunk_coord: &ChunkCoord) -> Result<&VoxelChunk, ChunkNotLoaded> {
+        self.game_world
+            .get(chunk_coord)
+            .and_then(|entity| self.chunks.get(*entity).ok())
+            .ok_or(ChunkNotLoaded(*chunk_coord))
+    }
+
+    /// Fails with the first chunk that is not loaded in the box between the
+    /// two blocks, without going through the blocks of the box. The part of
+    /// the box above or below the chunk columns is only air.
+    pub fn check_loaded(
+        &self,
+        min: GlobalVoxelBlockCoord,
+        max: GlobalVoxelBlockCoord,
+    ) -> Result<(), ChunkNotLoaded> {
+        let (low, _): (ChunkCoord, LocalVoxelBlockCoord) = min.into();
+        let (high, _): (ChunkCoord, LocalVoxelBlockCoord) = max.into();
+        let heights = match CUBIC_CHUNKS {
+            true => low.y..=high.y,
+            false => low.y.max(0)..=high.y.min(0),
+        };
+
+        for y in heights {
+            for z in low.z..=high.z {
+                for x in low.x..=high.x {
+                    self.chunk(&ChunkCoord(Point3::new(x, y, z)))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The loaded chunks, for the systems going through all of their blocks
+    pub fn loaded_chunks(&self) -> impl Iterator<Item = (ChunkCoord, &VoxelChunk)> {
+        self.game_world.iter().filter_map(|(chunk_coord, entity)| {
+            self.chunks
+                .get(*entity)
+                .ok()
+                .map(|chunk| (*chunk_coord, chunk))
+        })
+    }
+
+    /// Above and below the chunk columns, blocks are air
+    pub fn get_block(&self, coord: GlobalVoxelBlockCoord) -> Result<VoxelBlock, ChunkNotLoaded> {
+        if coord.is_outside_column() {
+            return Ok(VoxelBlock::default());
+        }
+
+        let (chunk_coord, block_coord): (ChunkCoord, LocalVoxelBlockCoord) = coord.into();
+
+        self.chunk(&chunk_coord)
+            .map(|chunk| chunk.get_block(&block_coord).copied().unwrap_or_default())
+    }
+
+    /// The sky lights everything above the chunk columns, and nothing below
+    pub fn get_light(&self, coord: GlobalVoxelBlockCoord) -> Result<Light, ChunkNotLoaded> {
+        if coord.is_outside_column() {
+            return Ok(match coord.y < 0 {
+                true => Light::default(),
+                false => Light::default().with(LightChannel::Sky, MAX_LIGHT),
+            });
+        }
+
+        let (chunk_coord, block_coord): (ChunkCoord, LocalVoxelBlockCoord) = coord.into();
+
+        self.chunk(&chunk_coord)
+            .map(|chunk| chunk.light(&block_coord).unwrap_or_default())
+    }
+
+    /// Replaces a single block. Returns whether it was a different block.
+    pub fn set_block(
+        &mut self,
+        coord: GlobalVoxelBlockCoord,
+        block: VoxelBlock,
+    ) -> Result<bool, SetBlockError> {
+        self.set_blocks([(coord, block)])
+            .map(|replaced| replaced == 1)
+    }
+
+    /// Replaces many blocks at once. Every chunk they are in is looked up a
+    /// single time, and the sections around them are marked once. Nothing is
+    /// replaced when one of the chunks is not loaded, so that an edit is
+    /// never applied in part, nor when one of the blocks is out of the world.
+    /// A block with a state its type does not support is written with the
+    /// default state. Returns the number of blocks that changed.
+    pub fn set_blocks<I>(&mut self, edits: I) -> Result<usize, SetBlockError>
+    where
+        I: IntoIterator<Item = (GlobalVoxelBlockCoord, VoxelBlock)>,
+    {
+        let mut by_chunk: HashMap<ChunkCoord, Vec<(LocalVoxelBlockCoord, VoxelBlock)>> =
+            HashMap::new();
+
+        for (coord, block) in edits {
+            if coord.is_outside_column() {
+                return Err(SetBlockError::OutOfWorld(coord));
+            }
+
+            let (chunk_coord, block_coord): (ChunkCoord, LocalVoxelBlockCoord) = coord.into();
+
+            by_chunk
+                .entry(chunk_coord)
+                .or_default()
+                .push((block_coord, block));
+        }
+
+        if let Some(chunk_coord) = by_chunk
+            .keys()
+            .find(|chunk_coord| self.chunk(chunk_coord).is_err())
+        {
+            return Err(ChunkNotLoaded(*chunk_coord).into());
+        }
+
+        let mut replaced = 0;
+        let mut dirty_sections: HashSet<(ChunkCoord, usize)> = HashSet::new();
+
+        for (chunk_coord, blocks) in by_chunk {
+            let mut chunk = self
+                .chunks
+                .get_mut(self.game_world[&chunk_coord])
+                .map_err(|_| ChunkNotLoaded(chunk_coord))?;
+
+            for (block_coord, block) in blocks {
+                let block = match self.block_registry.is_valid(block) {
+                    true => block,
+                    false => self.block_registry.block(block.id),
+                };
+
+                if chunk.get_block(&block_coord) == Some(&block) {
+                    continue;
+                }
+
+                chunk.update_block(&block_coord, |current| *current = block);
+                chunk.queue_block_update(&block_coord);
+                dirty_sections.extend(sections_around_block(chunk_coord, &block_coord));
+                replaced += 1;
+            }
+        }
+
+        for (chunk_coord, section) in dirty_sections {
+            if let Some(mut chunk) = self
+                .game_world
+                .get(&chunk_coord)
+                .and_then(|entity| self.chunks.get_mut(*entity).ok())
+            {
+                chunk.mark_dirty(section);
+            }
+        }
+
+        Ok(replaced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::block::BlockId;
+    use crate::chunk::registry::tests::game_block_registry;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn rock() -> VoxelBlock {
+        VoxelBlock {
+            id: BlockId(1),
+            ..default()
+        }
+    }
+
+    /// World with the chunk at the origin loaded, and not the one next to it
+    fn world() -> World {
+        let mut world = World::new();
+        let entity = world.spawn(VoxelChunk::default()).id();
+        let mut game_world = GameWorld::default();
+        game_world.insert(ChunkCoord::default(), entity);
+        world.insert_resource(game_world);
+        world.insert_resource(game_block_registry());
+        world
+    }
+
+    #[test]
+    fn edits_touching_an_unloaded_chunk_are_not_applied() {
+        let mut world = world();
+        let loaded = GlobalVoxelBlockCoord::new(3, 2, 15);
+        let unloaded = GlobalVoxelBlockCoord::new(3, 2, 16);
+
+        let result = world
+            .run_system_once(move |mut voxel_world: VoxelWorld| {
+                let result = voxel_world.set_blocks([(loaded, rock()), (unloaded, rock())]);
+
+                (result, voxel_world.get_block(loaded))
+            })
+            .unwrap();
+
+        let not_loaded = ChunkNotLoaded(ChunkCoord(Point3::new(0, 0, 1)));
+
+        assert_eq!(result, (Err(not_loaded.into()), Ok(VoxelBlock::default())));
+
+        let result = world
+            .run_system_once(move |mut voxel_world: VoxelWorld| {
+                let result = voxel_world.set_blocks([(loaded, rock()), (loaded, rock())]);
+
+                (
+                    result,
+                    voxel_world.get_block(loaded),
+                    voxel_world.get_block(unloaded),
+                )
+            })
+            .unwrap();
+
+        assert_eq!(result, (Ok(1), Ok(rock()), Err(not_loaded)));
+    }
+
+    #[test]
+    fn unsupported_states_are_written_as_the_default_state() {
+        let mut world = world();
+        let coord = GlobalVoxelBlockCoord::new(3, 2, 5);
+        let open_rock = VoxelBlock {
+            state: rock().state.with_open(true),
+            ..rock()
+        };
+
+        let result = world
+            .run_system_once(move |mut voxel_world: VoxelWorld| {
+                let result = voxel_world.set_block(coord, open_rock);
+
+                (result, voxel_world.get_block(coord))
+            })
+            .unwrap();
+
+        assert_eq!(result, (Ok(true), Ok(rock())));
+    }
+
+    #[cfg(not(feature = "cubic_chunks"))]
+    #[test]
+    fn above_and_below_the_columns_is_air() {
+        let mut world = world();
+        let above = GlobalVoxelBlockCoord::new(3, 80, 5);
+        let below = GlobalVoxelBlockCoord::new(3, -1, 5);
+
+        let result = world
+            .run_system_once(move |mut voxel_world: VoxelWorld| {
+                (
+                    voxel_world.get_block(above),
+                    voxel_world.get_block(below),
+                    voxel_world.check_loaded(below, above.offset([0, 20, 0])),
+                    voxel_world.set_block(above, rock()),
+                )
+            })
+            .unwrap();
+
+        assert_eq!(
+            result,
+            (
+                Ok(VoxelBlock::default()),
+                Ok(VoxelBlock::default()),
+                Ok(()),
+                Err(SetBlockError::OutOfWorld(above))
+            )
+        );
+    }
+}
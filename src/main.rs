@@ -2,21 +2,25 @@ mod chunk;
 mod game_world;
 mod logging;
 mod player;
+mod region_edit;
 mod screen;
 mod settings;
 mod sun;
 mod toml_asset;
 mod utils;
 mod web;
+mod world_tick;
 
 use crate::chunk::ChunkPlugin;
 use crate::logging::LoggingPlugin;
 use crate::player::PlayerPlugin;
+use crate::region_edit::RegionEditPlugin;
 use crate::screen::ScreenPlugin;
 use crate::settings::NoiseConfigurationChangedEvent;
 use crate::sun::SunPlugin;
 use crate::toml_asset::TomlAssetPlugin;
 use crate::web::setup_pointer_lock;
+use crate::world_tick::WorldTickPlugin;
 use bevy::image::{ImageFilterMode, ImageSamplerDescriptor};
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
@@ -98,6 +102,8 @@ fn main() {
             ChunkPlugin,
             ScreenPlugin,
             SunPlugin,
+            WorldTickPlugin,
+            RegionEditPlugin,
         ))
         .add_event::<NoiseConfigurationChangedEvent>()
         // Debug plugins
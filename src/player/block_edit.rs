@@ -0,0 +1,157 @@
+use crate::chunk::block::{BlockId, VoxelBlock};
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::voxel_chunk::VoxelChunk;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::game_world::raycast::RaycastHit;
+use crate::game_world::{GameWorld, VoxelWorld};
+use crate::player::{ThePlayer, PLAYER_HALF_HEIGHT, PLAYER_RADIUS};
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+/// How far the player reaches blocks, in blocks
+const REACH: f32 = 6.0;
+
+/// Keys selecting the blocks with the ids 1 to 9
+const SELECT_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
+
+/// Block the player looks at, within reach
+#[derive(Resource, Debug, Default)]
+pub struct TargetedBlock(pub Option<RaycastHit>);
+
+/// Type of block the player places
+#[derive(Resource, Debug)]
+pub struct SelectedBlock(pub BlockId);
+
+impl Default for SelectedBlock {
+    fn default() -> Self {
+        Self(BlockId(1))
+    }
+}
+
+/// Whether a block would take some of the room of the player's cylinder.
+/// Touching it is fine.
+pub fn overlaps_player(player: Vec3, coord: GlobalVoxelBlockCoord) -> bool {
+    let center = Vec3::new(coord.x as f32, coord.y as f32, coord.z as f32);
+    let offset = (center - player).abs();
+
+    // Distance from the axis of the cylinder to the closest point of the block
+    let outside = (Vec2::new(offset.x, offset.z) - Vec2::splat(0.5)).max(Vec2::ZERO);
+
+    offset.y < 0.5 + PLAYER_HALF_HEIGHT && outside.length() < PLAYER_RADIUS
+}
+
+/// Looks for the block in front of the player. Fluids and empty blocks are
+/// looked through.
+pub fn target_block(
+    mut targeted_block: ResMut<TargetedBlock>,
+    player: Query<&Transform, With<ThePlayer>>,
+    chunks: Query<&VoxelChunk>,
+    game_world: Res<GameWorld>,
+    block_registry: Res<BlockRegistry>,
+) {
+    let Ok(transform) = player.single() else {
+        targeted_block.0 = None;
+        return;
+    };
+
+    let ray = Ray3d::new(transform.translation, transform.forward());
+
+    targeted_block.0 = game_world.raycast(&chunks, ray, REACH, |block| {
+        !block.is_empty() && block_registry.fluid_flow(block.id).is_none()
+    });
+}
+
+pub fn select_block(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut selected_block: ResMut<SelectedBlock>,
+    block_registry: Res<BlockRegistry>,
+) {
+    for (index, key) in SELECT_KEYS.iter().enumerate() {
+        let id = BlockId(index as u16 + 1);
+
+        if keys.just_pressed(*key) && block_registry.get(id).is_some() {
+            selected_block.0 = id;
+        }
+    }
+}
+
+/// Left click breaks the targeted block, right click places the selected
+/// block against the face the player looks at, unless the player stands
+/// there
+pub fn edit_blocks(
+    mouse: Res<ButtonInput<MouseButton>>,
+    primary_window: Query<&Window, With<PrimaryWindow>>,
+    targeted_block: Res<TargetedBlock>,
+    selected_block: Res<SelectedBlock>,
+    player: Query<&Transform, With<ThePlayer>>,
+    mut voxel_world: VoxelWorld,
+    block_registry: Res<BlockRegistry>,
+) {
+    let Ok(window) = primary_window.single() else {
+        return;
+    };
+
+    if window.cursor_options.grab_mode == CursorGrabMode::None {
+        return;
+    }
+
+    let Some(hit) = targeted_block.0 else {
+        return;
+    };
+
+    // The targeted block and the blocks next to it can be in a chunk that
+    // has just been unloaded, then nothing happens
+    if mouse.just_pressed(MouseButton::Left) {
+        let _ = voxel_world.set_block(hit.coord, VoxelBlock::default());
+    } else if mouse.just_pressed(MouseButton::Right) {
+        let Ok(transform) = player.single() else {
+            return;
+        };
+
+        if hit.normal == [0; 3] {
+            return;
+        }
+
+        let coord = hit.coord.offset(hit.normal);
+        let is_free = voxel_world
+            .get_block(coord)
+            .is_ok_and(|block| block.is_empty() || block_registry.fluid_flow(block.id).is_some());
+
+        if is_free && !overlaps_player(transform.translation, coord) {
+            let _ = voxel_world.set_block(coord, block_registry.block(selected_block.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_cannot_be_placed_inside_the_player() {
+        let player = Vec3::new(0.1, 10.0, 0.1);
+        let at = |x, y, z| GlobalVoxelBlockCoord::from([x, y, z]);
+
+        // Around the feet and the head
+        assert!(overlaps_player(player, at(0, 9, 0)));
+        assert!(overlaps_player(player, at(0, 11, 0)));
+        // Under the feet and over the head
+        assert!(!overlaps_player(player, at(0, 8, 0)));
+        assert!(!overlaps_player(player, at(0, 12, 0)));
+        // Next to the player on the side it leans to, but not on the other side
+        assert!(overlaps_player(player, at(1, 10, 0)));
+        assert!(!overlaps_player(player, at(-1, 10, 0)));
+        // Only the corner of the block is close, out of the cylinder
+        assert!(!overlaps_player(player, at(1, 10, 1)));
+    }
+}
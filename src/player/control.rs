@@ -31,6 +31,18 @@ pub struct KeyBindings {
     pub move_right: KeyCode,
     pub move_downward: KeyCode,
     pub move_upward: KeyCode,
+    pub select_first_corner: KeyCode,
+    pub select_second_corner: KeyCode,
+    pub cycle_selection_shape: KeyCode,
+    pub fill_selection: KeyCode,
+    pub replace_in_selection: KeyCode,
+    pub copy_selection: KeyCode,
+    pub paste: KeyCode,
+    pub turn_paste: KeyCode,
+    pub mirror_paste: KeyCode,
+    pub stamp_model: KeyCode,
+    pub undo_edit: KeyCode,
+    pub redo_edit: KeyCode,
 }
 
 impl Default for KeyBindings {
@@ -44,6 +56,18 @@ impl Default for KeyBindings {
             move_right: KeyCode::KeyD,
             move_downward: KeyCode::KeyC,
             move_upward: KeyCode::Space,
+            select_first_corner: KeyCode::KeyQ,
+            select_second_corner: KeyCode::KeyE,
+            cycle_selection_shape: KeyCode::KeyT,
+            fill_selection: KeyCode::KeyG,
+            replace_in_selection: KeyCode::KeyR,
+            copy_selection: KeyCode::KeyK,
+            paste: KeyCode::KeyV,
+            turn_paste: KeyCode::KeyN,
+            mirror_paste: KeyCode::KeyM,
+            stamp_model: KeyCode::KeyP,
+            undo_edit: KeyCode::KeyZ,
+            redo_edit: KeyCode::KeyY,
         }
     }
 }
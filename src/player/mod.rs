@@ -1,10 +1,21 @@
+mod block_edit;
 mod control;
 mod cursor;
+mod outline;
+mod region_tool;
 
+use crate::player::block_edit::{
+    edit_blocks, select_block, target_block, SelectedBlock, TargetedBlock,
+};
 use crate::player::control::{player_look, player_move, InputState, MovementSettings};
 use crate::player::cursor::{
     cursor_grab, initial_grab_cursor, initial_grab_cursor_delayed, DelayedSystemTimer,
 };
+use crate::player::outline::{spawn_block_outline, update_block_outline, OutlineMaterial};
+use crate::player::region_tool::{
+    cursor_is_grabbed, draw_region_selection, edit_region, paste_region, select_region,
+    RegionSelection,
+};
 use bevy::prelude::*;
 use bevy_rapier3d::control::{
     CharacterAutostep, CharacterLength, KinematicCharacterController,
@@ -15,6 +26,10 @@ use bevy_rapier3d::geometry::{Collider, Friction};
 
 pub use crate::player::control::KeyBindings;
 
+/// Half the height of the cylinder of the player, from its center
+pub const PLAYER_HALF_HEIGHT: f32 = 0.825;
+pub const PLAYER_RADIUS: f32 = 0.45;
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
@@ -28,7 +43,30 @@ impl Plugin for PlayerPlugin {
             .add_systems(Update, (initial_grab_cursor_delayed, cursor_grab))
             // control
             .init_resource::<InputState>()
-            .add_systems(Update, (player_move, player_look));
+            .add_systems(Update, (player_move, player_look))
+            // block edition
+            .add_plugins(MaterialPlugin::<OutlineMaterial>::default())
+            .init_resource::<TargetedBlock>()
+            .init_resource::<SelectedBlock>()
+            .add_systems(Startup, spawn_block_outline)
+            .add_systems(
+                Update,
+                (
+                    select_block,
+                    (target_block, edit_blocks, update_block_outline).chain(),
+                ),
+            )
+            // region edits
+            .init_resource::<RegionSelection>()
+            .add_systems(
+                Update,
+                (
+                    (select_region, edit_region, paste_region)
+                        .after(target_block)
+                        .run_if(cursor_is_grabbed),
+                    draw_region_selection,
+                ),
+            );
     }
 }
 
@@ -45,7 +83,7 @@ pub fn setup(mut commands: Commands) {
             combine_rule: CoefficientCombineRule::Min,
         },
         RigidBody::KinematicPositionBased,
-        Collider::cylinder(0.825, 0.45),
+        Collider::cylinder(PLAYER_HALF_HEIGHT, PLAYER_RADIUS),
         KinematicCharacterController {
             snap_to_ground: None,
             autostep: Some(CharacterAutostep {
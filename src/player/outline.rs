@@ -0,0 +1,82 @@
+use crate::player::block_edit::TargetedBlock;
+use bevy::asset::RenderAssetUsages;
+use bevy::pbr::NotShadowCaster;
+use bevy::prelude::*;
+use bevy::render::mesh::PrimitiveTopology;
+use bevy::render::render_resource::{AsBindGroup, ShaderRef};
+
+/// Half the size of the outline. A bit more than a block so that its edges
+/// are drawn over the faces of the block.
+const OUTLINE_HALF_SIZE: f32 = 0.502;
+
+/// Draws the lines of a mesh in a single colour
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone, Default)]
+pub struct OutlineMaterial {}
+
+impl Material for OutlineMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/outline.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/outline.wgsl".into()
+    }
+}
+
+/// Outline around the targeted block
+#[derive(Component, Debug)]
+pub struct BlockOutline;
+
+/// The 12 edges of a cube centered on the origin
+fn cube_edges_mesh(half_size: f32) -> Mesh {
+    let corners: Vec<[f32; 3]> = (0..8)
+        .map(|corner| {
+            [1, 2, 4].map(|bit| match corner & bit {
+                0 => -half_size,
+                _ => half_size,
+            })
+        })
+        .collect();
+
+    // Corners differing by a single bit share an edge
+    let edges: Vec<[f32; 3]> = (0..8)
+        .flat_map(|corner| [1, 2, 4].map(move |bit| (corner, corner | bit)))
+        .filter(|(from, to)| from != to)
+        .flat_map(|(from, to)| [corners[from], corners[to]])
+        .collect();
+
+    Mesh::new(PrimitiveTopology::LineList, RenderAssetUsages::RENDER_WORLD)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, edges)
+}
+
+pub fn spawn_block_outline(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<OutlineMaterial>>,
+) {
+    commands.spawn((
+        BlockOutline,
+        Mesh3d(meshes.add(cube_edges_mesh(OUTLINE_HALF_SIZE))),
+        MeshMaterial3d(materials.add(OutlineMaterial::default())),
+        Transform::default(),
+        Visibility::Hidden,
+        NotShadowCaster,
+    ));
+}
+
+/// Moves the outline onto the targeted block, and hides it when there is none
+pub fn update_block_outline(
+    targeted_block: Res<TargetedBlock>,
+    mut outline: Query<(&mut Transform, &mut Visibility), With<BlockOutline>>,
+) {
+    for (mut transform, mut visibility) in outline.iter_mut() {
+        match targeted_block.0 {
+            Some(hit) => {
+                transform.translation =
+                    Vec3::new(hit.coord.x as f32, hit.coord.y as f32, hit.coord.z as f32);
+                *visibility = Visibility::Visible;
+            }
+            None => *visibility = Visibility::Hidden,
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use crate::chunk::block::BlockId;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::structure::StructureModels;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::game_world::VoxelWorld;
+use crate::player::block_edit::{SelectedBlock, TargetedBlock};
+use crate::player::control::KeyBindings;
+use crate::region_edit::clipboard::{Mirror, Rotation};
+use crate::region_edit::shape::{Region, Shape};
+use crate::region_edit::RegionEdit;
+use bevy::prelude::*;
+use bevy::window::{CursorGrabMode, PrimaryWindow};
+
+const SELECTION_COLOR: Color = Color::srgb(1.0, 0.8, 0.2);
+
+/// Shapes the selection edits, from its two corners
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeKind {
+    /// The box between the corners
+    #[default]
+    Box,
+    HollowBox,
+    /// The sphere around the first corner reaching the second one
+    Sphere,
+    HollowSphere,
+}
+
+impl ShapeKind {
+    fn next(self) -> Self {
+        match self {
+            ShapeKind::Box => ShapeKind::HollowBox,
+            ShapeKind::HollowBox => ShapeKind::Sphere,
+            ShapeKind::Sphere => ShapeKind::HollowSphere,
+            ShapeKind::HollowSphere => ShapeKind::Box,
+        }
+    }
+}
+
+/// Blocks picked by the player for the region edits, and how the clipboard
+/// and the models are placed
+#[derive(Resource, Debug, Default)]
+pub struct RegionSelection {
+    corners: [Option<GlobalVoxelBlockCoord>; 2],
+    /// Type of the block at the first corner, the one replaced by the
+    /// `replace_in_selection` key
+    first_block: BlockId,
+    shape: ShapeKind,
+    rotation: Rotation,
+    mirror: Mirror,
+}
+
+impl RegionSelection {
+    fn region(&self) -> Option<Region> {
+        match self.corners {
+            [Some(first), Some(second)] => Some(Region::new(first, second)),
+            _ => None,
+        }
+    }
+
+    fn shape(&self) -> Option<Shape> {
+        let [Some(first), Some(second)] = self.corners else {
+            return None;
+        };
+
+        let radius = Vec3::new(
+            (second.x - first.x) as f32,
+            (second.y - first.y) as f32,
+            (second.z - first.z) as f32,
+        )
+        .length();
+
+        Some(match self.shape {
+            ShapeKind::Box => Shape::Box(Region::new(first, second)),
+            ShapeKind::HollowBox => Shape::HollowBox(Region::new(first, second)),
+            ShapeKind::Sphere => Shape::Sphere {
+                center: first,
+                radius,
+            },
+            ShapeKind::HollowSphere => Shape::HollowSphere {
+                center: first,
+                radius,
+            },
+        })
+    }
+}
+
+/// Region edits are made with the keys while the cursor is grabbed
+pub fn cursor_is_grabbed(primary_window: Query<&Window, With<PrimaryWindow>>) -> bool {
+    primary_window
+        .single()
+        .is_ok_and(|window| window.cursor_options.grab_mode != CursorGrabMode::None)
+}
+
+/// Marks the corners of the selection on the targeted block, and picks the
+/// shape and how the pasted blocks are turned and mirrored
+pub fn select_region(
+    keys: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    targeted_block: Res<TargetedBlock>,
+    voxel_world: VoxelWorld,
+    mut selection: ResMut<RegionSelection>,
+) {
+    if let Some(hit) = targeted_block.0 {
+        if keys.just_pressed(key_bindings.select_first_corner) {
+            selection.corners[0] = Some(hit.coord);
+            selection.first_block = voxel_world
+                .get_block(hit.coord)
+                .map(|block| block.id)
+                .unwrap_or_default();
+        }
+
+        if keys.just_pressed(key_bindings.select_second_corner) {
+            selection.corners[1] = Some(hit.coord);
+        }
+    }
+
+    if keys.just_pressed(key_bindings.cycle_selection_shape) {
+        selection.shape = selection.shape.next();
+        info!("Selection shape: {:?}", selection.shape);
+    }
+
+    if keys.just_pressed(key_bindings.turn_paste) {
+        let index = Rotation::ALL
+            .iter()
+            .position(|rotation| *rotation == selection.rotation)
+            .unwrap_or_default();
+
+        selection.rotation = Rotation::ALL[(index + 1) % Rotation::ALL.len()];
+        info!("Paste rotation: {:?}", selection.rotation);
+    }
+
+    if keys.just_pressed(key_bindings.mirror_paste) {
+        selection.mirror = match selection.mirror {
+            Mirror::None => Mirror::X,
+            Mirror::X => Mirror::Z,
+            Mirror::Z => Mirror::None,
+        };
+        info!("Paste mirror: {:?}", selection.mirror);
+    }
+}
+
+/// Fills the selection with the selected block, replaces the blocks of the
+/// type of its first corner, copies it, or goes through the edit history
+pub fn edit_region(
+    keys: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    selected_block: Res<SelectedBlock>,
+    selection: Res<RegionSelection>,
+    block_registry: Res<BlockRegistry>,
+    mut ev_region_edit: EventWriter<RegionEdit>,
+) {
+    let block = block_registry.block(selected_block.0);
+
+    if let Some(shape) = selection.shape() {
+        if keys.just_pressed(key_bindings.fill_selection) {
+            ev_region_edit.write(RegionEdit::Fill { shape, block });
+        }
+
+        if keys.just_pressed(key_bindings.replace_in_selection) {
+            ev_region_edit.write(RegionEdit::Replace {
+                shape,
+                from: selection.first_block,
+                to: block,
+            });
+        }
+    }
+
+    if let Some(region) = selection.region() {
+        if keys.just_pressed(key_bindings.copy_selection) {
+            ev_region_edit.write(RegionEdit::Copy(region));
+        }
+    }
+
+    if keys.just_pressed(key_bindings.undo_edit) {
+        ev_region_edit.write(RegionEdit::Undo);
+    }
+
+    if keys.just_pressed(key_bindings.redo_edit) {
+        ev_region_edit.write(RegionEdit::Redo);
+    }
+}
+
+/// Pastes the clipboard, or stamps the first model of the `structures`
+/// settings, against the face of the targeted block
+pub fn paste_region(
+    keys: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    targeted_block: Res<TargetedBlock>,
+    selection: Res<RegionSelection>,
+    structure_models: Res<StructureModels>,
+    mut ev_region_edit: EventWriter<RegionEdit>,
+) {
+    let Some(hit) = targeted_block.0 else {
+        return;
+    };
+
+    let at = hit.coord.offset(hit.normal);
+
+    if keys.just_pressed(key_bindings.paste) {
+        ev_region_edit.write(RegionEdit::Paste {
+            at,
+            rotation: selection.rotation,
+            mirror: selection.mirror,
+        });
+    }
+
+    if keys.just_pressed(key_bindings.stamp_model) {
+        match structure_models.handles().first() {
+            Some(model) => {
+                ev_region_edit.write(RegionEdit::Stamp {
+                    model: model.clone(),
+                    at,
+                    rotation: selection.rotation,
+                });
+            }
+            None => warn!("There is no structure model to stamp"),
+        }
+    }
+}
+
+/// Draws the box of the selection, or its sphere
+pub fn draw_region_selection(selection: Res<RegionSelection>, mut gizmos: Gizmos) {
+    let to_vec =
+        |coord: GlobalVoxelBlockCoord| Vec3::new(coord.x as f32, coord.y as f32, coord.z as f32);
+
+    match (selection.shape(), selection.region()) {
+        (Some(Shape::Sphere { center, radius } | Shape::HollowSphere { center, radius }), _) => {
+            gizmos.sphere(
+                Isometry3d::from_translation(to_vec(center)),
+                radius + 0.5,
+                SELECTION_COLOR,
+            );
+        }
+        (_, Some(region)) => {
+            let size = Vec3::from(region.size().map(|v| v as f32));
+            // Blocks are centered on their coordinates
+            let center = to_vec(region.min()) + (size - Vec3::ONE) / 2.0;
+
+            gizmos.cuboid(
+                Transform::from_translation(center).with_scale(size + 0.01),
+                SELECTION_COLOR,
+            );
+        }
+        _ => {
+            for corner in selection.corners.iter().flatten() {
+                gizmos.cuboid(
+                    Transform::from_translation(to_vec(*corner)).with_scale(Vec3::splat(1.01)),
+                    SELECTION_COLOR,
+                );
+            }
+        }
+    }
+}
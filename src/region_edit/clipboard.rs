@@ -0,0 +1,337 @@
+use crate::chunk::block::VoxelBlock;
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::state::Orientation;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::game_world::voxel_world::ChunkNotLoaded;
+use crate::region_edit::shape::Region;
+use crate::settings::CoordSystemIntegerSize;
+use bevy::prelude::*;
+
+/// Position of a block in a model, from its lowest corner
+type Position = [CoordSystemIntegerSize; 3];
+
+/// Quarter turns around the vertical axis, turning x toward z
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl Rotation {
+    pub const ALL: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Quarter,
+        Rotation::Half,
+        Rotation::ThreeQuarters,
+    ];
+
+    /// Where a block of a model of `size` goes once the model is turned. The
+    /// turned model keeps its lowest corner at the origin.
+    pub fn apply(self, position: Position, size: Position) -> Position {
+        let [x, y, z] = position;
+
+        match self {
+            Rotation::None => position,
+            Rotation::Quarter => [size[2] - 1 - z, y, x],
+            Rotation::Half => [size[0] - 1 - x, y, size[2] - 1 - z],
+            Rotation::ThreeQuarters => [z, y, size[0] - 1 - x],
+        }
+    }
+
+    /// Size of a model of `size` once it is turned
+    pub fn apply_to_size(self, size: Position) -> Position {
+        match self {
+            Rotation::None | Rotation::Half => size,
+            Rotation::Quarter | Rotation::ThreeQuarters => [size[2], size[1], size[0]],
+        }
+    }
+
+    /// Direction a block points to once it is turned along with its model
+    pub fn apply_to_orientation(self, orientation: Orientation) -> Orientation {
+        let [x, y, z] = orientation.normal();
+        let normal = match self {
+            Rotation::None => [x, y, z],
+            Rotation::Quarter => [-z, y, x],
+            Rotation::Half => [-x, y, -z],
+            Rotation::ThreeQuarters => [z, y, -x],
+        };
+
+        Orientation::from_normal(normal).unwrap_or(orientation)
+    }
+}
+
+/// Flips a model along an horizontal axis
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mirror {
+    #[default]
+    None,
+    X,
+    Z,
+}
+
+impl Mirror {
+    pub fn apply(self, position: Position, size: Position) -> Position {
+        let [x, y, z] = position;
+
+        match self {
+            Mirror::None => position,
+            Mirror::X => [size[0] - 1 - x, y, z],
+            Mirror::Z => [x, y, size[2] - 1 - z],
+        }
+    }
+
+    pub fn apply_to_orientation(self, orientation: Orientation) -> Orientation {
+        let [x, y, z] = orientation.normal();
+        let normal = match self {
+            Mirror::None => [x, y, z],
+            Mirror::X => [-x, y, z],
+            Mirror::Z => [x, y, -z],
+        };
+
+        Orientation::from_normal(normal).unwrap_or(orientation)
+    }
+}
+
+/// Block mirrored, then turned along with the model holding it, so that
+/// oriented blocks like stairs or logs keep pointing the same way in the
+/// model
+pub fn transform_block(
+    block: VoxelBlock,
+    rotation: Rotation,
+    mirror: Mirror,
+    registry: &BlockRegistry,
+) -> VoxelBlock {
+    let Some(kind) = registry
+        .get(block.id)
+        .and_then(|definition| definition.states.orientation)
+    else {
+        return block;
+    };
+
+    let orientation =
+        rotation.apply_to_orientation(mirror.apply_to_orientation(block.state.orientation()));
+
+    VoxelBlock {
+        state: block.state.with_orientation(kind.fit(orientation)),
+        ..block
+    }
+}
+
+/// Blocks of the last copied region, from its lowest corner
+#[derive(Resource, Debug, Default, Clone)]
+pub struct Clipboard {
+    size: Position,
+    /// x first, then z, then y
+    blocks: Vec<VoxelBlock>,
+}
+
+impl Clipboard {
+    pub fn copy<B>(region: Region, block_at: B) -> Result<Self, ChunkNotLoaded>
+    where
+        B: Fn(GlobalVoxelBlockCoord) -> Result<VoxelBlock, ChunkNotLoaded>,
+    {
+        Ok(Self {
+            size: region.size(),
+            blocks: region.iter().map(block_at).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Box the blocks of the clipboard take once turned, with its lowest
+    /// corner at `at`. `None` when nothing was copied.
+    pub fn bounds(&self, at: GlobalVoxelBlockCoord, rotation: Rotation) -> Option<Region> {
+        Region::with_size(at, rotation.apply_to_size(self.size))
+    }
+
+    /// Blocks of the clipboard mirrored, then turned, with the lowest corner
+    /// of the result at `at`. Empty blocks are pasted too.
+    pub fn paste(
+        &self,
+        at: GlobalVoxelBlockCoord,
+        rotation: Rotation,
+        mirror: Mirror,
+        registry: &BlockRegistry,
+    ) -> Vec<(GlobalVoxelBlockCoord, VoxelBlock)> {
+        let [size_x, _, size_z] = self.size;
+
+        self.blocks
+            .iter()
+            .enumerate()
+            .map(|(index, block)| {
+                let index = index as CoordSystemIntegerSize;
+                let position = [
+                    index % size_x,
+                    index / (size_x * size_z),
+                    index / size_x % size_z,
+                ];
+                let position = rotation.apply(mirror.apply(position, self.size), self.size);
+
+                (
+                    at.offset(position),
+                    transform_block(*block, rotation, mirror, registry),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::state::BlockState;
+    use crate::region_edit::tests::block;
+
+    /// An L of 3 blocks along x, then 2 along z, one block high
+    fn clipboard() -> Clipboard {
+        let region = Region::new(
+            GlobalVoxelBlockCoord::new(5, 5, 5),
+            GlobalVoxelBlockCoord::new(7, 5, 6),
+        );
+
+        Clipboard::copy(region, |coord| {
+            Ok(match [coord.x, coord.z] {
+                [5, 5] => block(1),
+                [6, 5] => block(2),
+                [7, 5] => block(3),
+                [7, 6] => block(4),
+                _ => VoxelBlock::default(),
+            })
+        })
+        .unwrap()
+    }
+
+    fn pasted(rotation: Rotation, mirror: Mirror) -> Vec<[CoordSystemIntegerSize; 3]> {
+        let at = GlobalVoxelBlockCoord::new(0, 10, 0);
+
+        (1..=4)
+            .map(|id| {
+                clipboard()
+                    .paste(at, rotation, mirror, &BlockRegistry::default())
+                    .into_iter()
+                    .find(|(_, pasted)| *pasted == block(id))
+                    .map(|(coord, _)| [coord.x, coord.y, coord.z])
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pasted_blocks_keep_their_layout() {
+        assert_eq!(
+            pasted(Rotation::None, Mirror::None),
+            vec![[0, 10, 0], [1, 10, 0], [2, 10, 0], [2, 10, 1]]
+        );
+        assert_eq!(
+            clipboard()
+                .paste(
+                    GlobalVoxelBlockCoord::default(),
+                    Rotation::None,
+                    Mirror::None,
+                    &BlockRegistry::default()
+                )
+                .len(),
+            6
+        );
+    }
+
+    #[test]
+    fn pasted_blocks_can_be_turned_and_mirrored() {
+        // x goes to z, z goes to -x
+        assert_eq!(
+            pasted(Rotation::Quarter, Mirror::None),
+            vec![[1, 10, 0], [1, 10, 1], [1, 10, 2], [0, 10, 2]]
+        );
+        assert_eq!(
+            pasted(Rotation::Half, Mirror::None),
+            vec![[2, 10, 1], [1, 10, 1], [0, 10, 1], [0, 10, 0]]
+        );
+        assert_eq!(
+            pasted(Rotation::ThreeQuarters, Mirror::None),
+            vec![[0, 10, 2], [0, 10, 1], [0, 10, 0], [1, 10, 0]]
+        );
+        assert_eq!(
+            pasted(Rotation::None, Mirror::X),
+            vec![[2, 10, 0], [1, 10, 0], [0, 10, 0], [0, 10, 1]]
+        );
+        // Mirrored first, then turned
+        assert_eq!(
+            pasted(Rotation::Quarter, Mirror::Z),
+            vec![[0, 10, 0], [0, 10, 1], [0, 10, 2], [1, 10, 2]]
+        );
+    }
+
+    #[test]
+    fn pasted_blocks_stay_in_the_turned_bounds() {
+        let at = GlobalVoxelBlockCoord::new(-4, 10, 7);
+
+        for rotation in Rotation::ALL {
+            let bounds = clipboard().bounds(at, rotation).unwrap();
+            let pasted = clipboard().paste(at, rotation, Mirror::X, &BlockRegistry::default());
+
+            assert_eq!(bounds.volume(), pasted.len());
+            assert!(pasted.iter().all(|(coord, _)| {
+                let [min, max] = [bounds.min(), bounds.max()];
+
+                (min.x..=max.x).contains(&coord.x)
+                    && (min.y..=max.y).contains(&coord.y)
+                    && (min.z..=max.z).contains(&coord.z)
+            }));
+        }
+
+        assert_eq!(Clipboard::default().bounds(at, Rotation::None), None);
+    }
+
+    #[test]
+    fn oriented_blocks_turn_with_the_pasted_blocks() {
+        let registry = BlockRegistry::from_definitions(
+            toml::from_str(
+                "[atlas]\ncolumns = 1\nrows = 1\ntile_size = 32\n\
+                [[blocks]]\nid = 1\nname = \"log\"\nsolid = true\n\
+                hardness = 1.0\ntextures = { all = 0 }\n\
+                states = { orientation = \"axis\" }\n\
+                [[blocks]]\nid = 2\nname = \"stairs\"\nsolid = true\n\
+                hardness = 1.0\ntextures = { all = 0 }\n\
+                states = { orientation = \"horizontal\" }\n",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let oriented = |id, orientation| VoxelBlock {
+            state: BlockState::default().with_orientation(orientation),
+            ..block(id)
+        };
+        let turned = |block, rotation, mirror| transform_block(block, rotation, mirror, &registry);
+
+        let stairs = oriented(2, Orientation::East);
+        assert_eq!(
+            turned(stairs, Rotation::Quarter, Mirror::None),
+            oriented(2, Orientation::South)
+        );
+        assert_eq!(
+            turned(stairs, Rotation::ThreeQuarters, Mirror::None),
+            oriented(2, Orientation::North)
+        );
+        // Mirrored first, then turned
+        assert_eq!(
+            turned(stairs, Rotation::Quarter, Mirror::X),
+            oriented(2, Orientation::North)
+        );
+
+        // Logs stay along an axis
+        let log = oriented(1, Orientation::South);
+        assert_eq!(
+            turned(log, Rotation::Quarter, Mirror::None),
+            oriented(1, Orientation::East)
+        );
+        assert_eq!(turned(log, Rotation::Half, Mirror::None), log);
+        assert_eq!(
+            turned(oriented(1, Orientation::Up), Rotation::Quarter, Mirror::Z),
+            oriented(1, Orientation::Up)
+        );
+
+        // Blocks without orientation are left as they are
+        assert_eq!(turned(block(3), Rotation::Quarter, Mirror::X), block(3));
+    }
+}
@@ -0,0 +1,560 @@
+pub mod clipboard;
+pub mod shape;
+
+use crate::chunk::block::{BlockId, VoxelBlock};
+use crate::chunk::registry::BlockRegistry;
+use crate::chunk::vox::VoxModel;
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::game_world::voxel_world::{ChunkNotLoaded, SetBlockError};
+use crate::game_world::VoxelWorld;
+use crate::region_edit::clipboard::{Clipboard, Mirror, Rotation};
+use crate::region_edit::shape::{Region, Shape};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Edits kept in the history, the oldest are forgotten
+const MAX_HISTORY: usize = 64;
+
+/// Blocks in the box around an edit beyond which it is not applied, so that
+/// a far corner or a large model does not stall the game while the blocks
+/// are listed
+const MAX_EDIT_BLOCKS: usize = 64 * 64 * 64;
+
+/// Blocks replaced by an edit, in order
+pub type BlockEdits = Vec<(GlobalVoxelBlockCoord, VoxelBlock)>;
+
+/// Edits of whole regions of the world, for building scenes. Each of them is
+/// applied at once through `VoxelWorld::set_blocks`, so every section it
+/// touches is meshed a single time, and nothing is applied when a block it
+/// touches is in a chunk that is not loaded.
+#[derive(Event, Debug, Clone)]
+pub enum RegionEdit {
+    /// Puts the block everywhere in the shape
+    Fill {
+        shape: Shape,
+        block: VoxelBlock,
+    },
+    /// Puts the block in the shape where there is a block of type `from`
+    Replace {
+        shape: Shape,
+        from: BlockId,
+        to: VoxelBlock,
+    },
+    /// Keeps the blocks of the region in the clipboard
+    Copy(Region),
+    /// Puts the blocks of the clipboard back with their lowest corner at
+    /// `at`. They are mirrored first, then turned.
+    Paste {
+        at: GlobalVoxelBlockCoord,
+        rotation: Rotation,
+        mirror: Mirror,
+    },
+    /// Puts the blocks of a model turned, with its lowest corner at `at`
+    Stamp {
+        model: Handle<VoxModel>,
+        at: GlobalVoxelBlockCoord,
+        rotation: Rotation,
+    },
+    Undo,
+    Redo,
+}
+
+/// Why a region edit was not applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionEditError {
+    NotLoaded(ChunkNotLoaded),
+    /// The edit puts a block above or below the chunk columns
+    OutOfWorld(GlobalVoxelBlockCoord),
+    /// The box around the edit holds this many blocks, more than
+    /// `MAX_EDIT_BLOCKS`
+    TooLarge(usize),
+}
+
+impl fmt::Display for RegionEditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionEditError::NotLoaded(error) => error.fmt(f),
+            RegionEditError::OutOfWorld(coord) => SetBlockError::OutOfWorld(*coord).fmt(f),
+            RegionEditError::TooLarge(volume) => write!(
+                f,
+                "it spans {} blocks, more than the {} allowed",
+                volume, MAX_EDIT_BLOCKS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegionEditError {}
+
+impl From<ChunkNotLoaded> for RegionEditError {
+    fn from(error: ChunkNotLoaded) -> Self {
+        RegionEditError::NotLoaded(error)
+    }
+}
+
+impl From<SetBlockError> for RegionEditError {
+    fn from(error: SetBlockError) -> Self {
+        match error {
+            SetBlockError::NotLoaded(error) => RegionEditError::NotLoaded(error),
+            SetBlockError::OutOfWorld(coord) => RegionEditError::OutOfWorld(coord),
+        }
+    }
+}
+
+/// Rejects the edits spanning too many blocks, or touching a chunk that is
+/// not loaded, before their blocks are listed
+fn check_bounds(bounds: Region, voxel_world: &VoxelWorld) -> Result<(), RegionEditError> {
+    let volume = bounds.volume();
+
+    if volume > MAX_EDIT_BLOCKS {
+        return Err(RegionEditError::TooLarge(volume));
+    }
+
+    voxel_world.check_loaded(bounds.min(), bounds.max())?;
+
+    Ok(())
+}
+
+/// Blocks of the world before and after an edit
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditRecord {
+    before: BlockEdits,
+    after: BlockEdits,
+}
+
+impl EditRecord {
+    /// Keeps the blocks `after` changes, along with what they were before.
+    /// The last of the blocks put at the same place wins. `None` when the
+    /// edit changes nothing.
+    pub fn new<B>(after: BlockEdits, block_at: B) -> Result<Option<Self>, ChunkNotLoaded>
+    where
+        B: Fn(GlobalVoxelBlockCoord) -> Result<VoxelBlock, ChunkNotLoaded>,
+    {
+        let mut last: HashMap<GlobalVoxelBlockCoord, VoxelBlock> = HashMap::new();
+        let mut order = vec![];
+
+        for (coord, block) in after {
+            if last.insert(coord, block).is_none() {
+                order.push(coord);
+            }
+        }
+
+        let mut record = Self {
+            before: vec![],
+            after: vec![],
+        };
+
+        for coord in order {
+            let (block, previous) = (last[&coord], block_at(coord)?);
+
+            if previous != block {
+                record.before.push((coord, previous));
+                record.after.push((coord, block));
+            }
+        }
+
+        Ok((!record.after.is_empty()).then_some(record))
+    }
+}
+
+/// Edits that can be undone, and the undone edits that can be redone until
+/// a new edit is made
+#[derive(Resource, Debug, Default)]
+pub struct EditHistory {
+    undo: VecDeque<EditRecord>,
+    redo: Vec<EditRecord>,
+}
+
+impl EditHistory {
+    pub fn record(&mut self, record: EditRecord) {
+        self.redo.clear();
+        self.undo.push_back(record);
+
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Puts back the blocks the last edit replaced with `apply`. Returns
+    /// false when there is nothing to undo. The edit stays in the history
+    /// when it cannot be undone.
+    pub fn undo<A, E>(&mut self, apply: A) -> Result<bool, E>
+    where
+        A: FnOnce(BlockEdits) -> Result<usize, E>,
+    {
+        let Some(record) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+
+        match apply(record.before.clone()) {
+            Ok(_) => {
+                self.redo.push(record);
+                Ok(true)
+            }
+            Err(error) => {
+                self.undo.push_back(record);
+                Err(error)
+            }
+        }
+    }
+
+    /// Applies the last undone edit again with `apply`. Returns false when
+    /// there is nothing to redo.
+    pub fn redo<A, E>(&mut self, apply: A) -> Result<bool, E>
+    where
+        A: FnOnce(BlockEdits) -> Result<usize, E>,
+    {
+        let Some(record) = self.redo.pop() else {
+            return Ok(false);
+        };
+
+        match apply(record.after.clone()) {
+            Ok(_) => {
+                self.undo.push_back(record);
+                Ok(true)
+            }
+            Err(error) => {
+                self.redo.push(record);
+                Err(error)
+            }
+        }
+    }
+}
+
+pub struct RegionEditPlugin;
+
+impl Plugin for RegionEditPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Clipboard>()
+            .init_resource::<EditHistory>()
+            .add_event::<RegionEdit>()
+            .add_systems(Update, apply_region_edits);
+    }
+}
+
+/// Blocks an edit puts in the world, in order
+fn planned_blocks<B>(
+    edit: &RegionEdit,
+    clipboard: &Clipboard,
+    block_registry: &BlockRegistry,
+    block_at: B,
+) -> Result<BlockEdits, ChunkNotLoaded>
+where
+    B: Fn(GlobalVoxelBlockCoord) -> Result<VoxelBlock, ChunkNotLoaded>,
+{
+    match edit {
+        RegionEdit::Fill { shape, block } => Ok(shape
+            .blocks()
+            .into_iter()
+            .map(|coord| (coord, *block))
+            .collect()),
+        RegionEdit::Replace { shape, from, to } => {
+            let mut blocks = vec![];
+
+            for coord in shape.blocks() {
+                if block_at(coord)?.id == *from {
+                    blocks.push((coord, *to));
+                }
+            }
+
+            Ok(blocks)
+        }
+        RegionEdit::Paste {
+            at,
+            rotation,
+            mirror,
+        } => Ok(clipboard.paste(*at, *rotation, *mirror, block_registry)),
+        RegionEdit::Copy(_) | RegionEdit::Stamp { .. } | RegionEdit::Undo | RegionEdit::Redo => {
+            Ok(vec![])
+        }
+    }
+}
+
+/// Puts the blocks in the world in a single batch, and keeps what they
+/// replace in the history
+fn apply_blocks(
+    after: BlockEdits,
+    voxel_world: &mut VoxelWorld,
+    history: &mut EditHistory,
+) -> Result<(), RegionEditError> {
+    if let Some(record) = EditRecord::new(after, |coord| voxel_world.get_block(coord))? {
+        voxel_world.set_blocks(record.after.clone())?;
+        history.record(record);
+    }
+
+    Ok(())
+}
+
+fn apply_region_edit(
+    edit: &RegionEdit,
+    voxel_world: &mut VoxelWorld,
+    clipboard: &mut Clipboard,
+    history: &mut EditHistory,
+    models: &Assets<VoxModel>,
+    block_registry: &BlockRegistry,
+) -> Result<(), RegionEditError> {
+    match edit {
+        RegionEdit::Stamp {
+            model,
+            at,
+            rotation,
+        } => match models.get(model) {
+            Some(model) => {
+                if let Some(bounds) = Region::with_size(*at, rotation.apply_to_size(model.size())) {
+                    check_bounds(bounds, voxel_world)?;
+                }
+
+                apply_blocks(
+                    model.stamp(block_registry, *at, *rotation),
+                    voxel_world,
+                    history,
+                )?;
+            }
+            None => warn!("The model to stamp is not loaded"),
+        },
+        RegionEdit::Copy(region) => {
+            check_bounds(*region, voxel_world)?;
+
+            *clipboard = Clipboard::copy(*region, |coord| voxel_world.get_block(coord))?;
+        }
+        RegionEdit::Undo => {
+            history.undo(|blocks| voxel_world.set_blocks(blocks))?;
+        }
+        RegionEdit::Redo => {
+            history.redo(|blocks| voxel_world.set_blocks(blocks))?;
+        }
+        RegionEdit::Fill { shape, .. } | RegionEdit::Replace { shape, .. } => {
+            check_bounds(shape.bounds(), voxel_world)?;
+
+            let after = planned_blocks(edit, clipboard, block_registry, |coord| {
+                voxel_world.get_block(coord)
+            })?;
+
+            apply_blocks(after, voxel_world, history)?;
+        }
+        RegionEdit::Paste { at, rotation, .. } => {
+            if let Some(bounds) = clipboard.bounds(*at, *rotation) {
+                check_bounds(bounds, voxel_world)?;
+            }
+
+            let after = planned_blocks(edit, clipboard, block_registry, |coord| {
+                voxel_world.get_block(coord)
+            })?;
+
+            apply_blocks(after, voxel_world, history)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn apply_region_edits(
+    mut ev_region_edit: EventReader<RegionEdit>,
+    mut voxel_world: VoxelWorld,
+    mut clipboard: ResMut<Clipboard>,
+    mut history: ResMut<EditHistory>,
+    models: Res<Assets<VoxModel>>,
+    block_registry: Res<BlockRegistry>,
+) {
+    for edit in ev_region_edit.read() {
+        if let Err(error) = apply_region_edit(
+            edit,
+            &mut voxel_world,
+            &mut clipboard,
+            &mut history,
+            &models,
+            &block_registry,
+        ) {
+            warn!("{:?} was not applied: {}", edit, error);
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chunk::registry::tests::game_block_registry;
+    use crate::chunk::voxel_chunk::VoxelChunk;
+    use crate::game_world::coord::{ChunkCoord, LocalVoxelBlockCoord};
+    use crate::game_world::GameWorld;
+
+    /// A block of the type with the id, in its default state
+    pub fn block(id: u16) -> VoxelBlock {
+        VoxelBlock {
+            id: BlockId(id),
+            ..default()
+        }
+    }
+
+    /// Blocks of a world where everything is loaded
+    #[derive(Default)]
+    struct Blocks(HashMap<GlobalVoxelBlockCoord, VoxelBlock>);
+
+    impl Blocks {
+        fn get(&self, coord: GlobalVoxelBlockCoord) -> Result<VoxelBlock, ChunkNotLoaded> {
+            Ok(self.0.get(&coord).copied().unwrap_or_default())
+        }
+
+        fn set(&mut self, blocks: BlockEdits) -> Result<usize, ChunkNotLoaded> {
+            let count = blocks.len();
+            self.0.extend(blocks);
+
+            Ok(count)
+        }
+
+        fn edit(&mut self, history: &mut EditHistory, after: BlockEdits) {
+            let record = EditRecord::new(after, |coord| self.get(coord))
+                .unwrap()
+                .unwrap();
+            self.set(record.after.clone()).unwrap();
+            history.record(record);
+        }
+    }
+
+    #[test]
+    fn edits_can_be_undone_and_redone() {
+        let mut blocks = Blocks::default();
+        let mut history = EditHistory::default();
+        let at = GlobalVoxelBlockCoord::new;
+
+        blocks.edit(
+            &mut history,
+            vec![(at(0, 0, 0), block(1)), (at(1, 0, 0), block(1))],
+        );
+        // The second edit writes over the first one, twice on the same block
+        blocks.edit(
+            &mut history,
+            vec![(at(1, 0, 0), block(2)), (at(1, 0, 0), block(3))],
+        );
+        // Ending with the block already there changes nothing
+        let unchanged = vec![(at(0, 0, 0), block(2)), (at(0, 0, 0), block(1))];
+        assert_eq!(
+            EditRecord::new(unchanged, |coord| blocks.get(coord)),
+            Ok(None)
+        );
+
+        let after_both = blocks.0.clone();
+
+        assert!(history.undo(|edits| blocks.set(edits)).unwrap());
+        assert_eq!(blocks.get(at(1, 0, 0)), Ok(block(1)));
+
+        assert!(history.undo(|edits| blocks.set(edits)).unwrap());
+        assert_eq!(blocks.get(at(0, 0, 0)), Ok(VoxelBlock::default()));
+        assert_eq!(blocks.get(at(1, 0, 0)), Ok(VoxelBlock::default()));
+        assert!(!history.undo(|edits| blocks.set(edits)).unwrap());
+
+        assert!(history.redo(|edits| blocks.set(edits)).unwrap());
+        assert!(history.redo(|edits| blocks.set(edits)).unwrap());
+        assert_eq!(blocks.0, after_both);
+        assert!(!history.redo(|edits| blocks.set(edits)).unwrap());
+
+        // A new edit forgets what was undone
+        assert!(history.undo(|edits| blocks.set(edits)).unwrap());
+        blocks.edit(&mut history, vec![(at(5, 0, 0), block(1))]);
+        assert!(!history.redo(|edits| blocks.set(edits)).unwrap());
+    }
+
+    #[test]
+    fn replace_only_touches_the_blocks_of_a_type() {
+        let mut blocks = Blocks::default();
+        let region = Region::new(
+            GlobalVoxelBlockCoord::new(0, 0, 0),
+            GlobalVoxelBlockCoord::new(3, 0, 0),
+        );
+        blocks
+            .0
+            .insert(GlobalVoxelBlockCoord::new(1, 0, 0), block(1));
+        blocks
+            .0
+            .insert(GlobalVoxelBlockCoord::new(2, 0, 0), block(2));
+
+        let edit = RegionEdit::Replace {
+            shape: Shape::Box(region),
+            from: BlockId(1),
+            to: block(3),
+        };
+
+        assert_eq!(
+            planned_blocks(
+                &edit,
+                &Clipboard::default(),
+                &BlockRegistry::default(),
+                |coord| blocks.get(coord)
+            ),
+            Ok(vec![(GlobalVoxelBlockCoord::new(1, 0, 0), block(3))])
+        );
+    }
+
+    #[test]
+    fn region_edits_change_the_loaded_chunks() {
+        let registry = game_block_registry();
+        let rock = registry.block(registry.id("rock").unwrap());
+        let mut world = World::new();
+        let entity = world.spawn(VoxelChunk::default()).id();
+        world.insert_resource(GameWorld(HashMap::from([(ChunkCoord::default(), entity)])));
+        world.insert_resource(registry);
+        world.init_resource::<Clipboard>();
+        world.init_resource::<EditHistory>();
+        world.init_resource::<Assets<VoxModel>>();
+        world.init_resource::<Events<RegionEdit>>();
+        let system = world.register_system(apply_region_edits);
+
+        let region = Region::new(
+            GlobalVoxelBlockCoord::new(1, 1, 1),
+            GlobalVoxelBlockCoord::new(3, 2, 3),
+        );
+        let rocks = |world: &World| {
+            let chunk = world.get::<VoxelChunk>(entity).unwrap();
+
+            (0..5)
+                .flat_map(|x| (0..5).flat_map(move |y| (0..5).map(move |z| [x, y, z])))
+                .filter(|position| {
+                    chunk.get_block(&LocalVoxelBlockCoord::from(*position)) == Some(&rock)
+                })
+                .count()
+        };
+
+        world.send_event(RegionEdit::Fill {
+            shape: Shape::Box(region),
+            block: rock,
+        });
+        world.run_system(system).unwrap();
+        assert_eq!(rocks(&world), 18);
+
+        // Copied, then pasted next to the first box
+        world.send_event(RegionEdit::Copy(region));
+        world.send_event(RegionEdit::Paste {
+            at: GlobalVoxelBlockCoord::new(1, 3, 1),
+            rotation: Rotation::None,
+            mirror: Mirror::None,
+        });
+        world.run_system(system).unwrap();
+        assert_eq!(rocks(&world), 36);
+
+        world.send_event(RegionEdit::Undo);
+        world.send_event(RegionEdit::Undo);
+        world.run_system(system).unwrap();
+        assert_eq!(rocks(&world), 0);
+
+        // Neither an edit spanning too many blocks nor one reaching past the
+        // loaded chunk is applied
+        world.send_event(RegionEdit::Fill {
+            shape: Shape::Sphere {
+                center: GlobalVoxelBlockCoord::new(1, 1, 1),
+                radius: 1000.0,
+            },
+            block: rock,
+        });
+        world.send_event(RegionEdit::Fill {
+            shape: Shape::Box(Region::new(
+                GlobalVoxelBlockCoord::new(1, 1, 1),
+                GlobalVoxelBlockCoord::new(1, 1, 20),
+            )),
+            block: rock,
+        });
+        world.run_system(system).unwrap();
+        assert_eq!(rocks(&world), 0);
+    }
+}
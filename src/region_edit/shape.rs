@@ -0,0 +1,236 @@
+use crate::game_world::coord::GlobalVoxelBlockCoord;
+use crate::settings::CoordSystemIntegerSize;
+
+/// Box of blocks between two corners, both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    min: GlobalVoxelBlockCoord,
+    max: GlobalVoxelBlockCoord,
+}
+
+impl Region {
+    /// The corners can be any two opposite corners of the box
+    pub fn new(corner: GlobalVoxelBlockCoord, opposite: GlobalVoxelBlockCoord) -> Self {
+        Self {
+            min: GlobalVoxelBlockCoord::new(
+                corner.x.min(opposite.x),
+                corner.y.min(opposite.y),
+                corner.z.min(opposite.z),
+            ),
+            max: GlobalVoxelBlockCoord::new(
+                corner.x.max(opposite.x),
+                corner.y.max(opposite.y),
+                corner.z.max(opposite.z),
+            ),
+        }
+    }
+
+    /// Box with its lowest corner at `min` and `size` blocks along each
+    /// axis. `None` when the box holds no block.
+    pub fn with_size(
+        min: GlobalVoxelBlockCoord,
+        size: [CoordSystemIntegerSize; 3],
+    ) -> Option<Self> {
+        size.iter().all(|length| *length > 0).then(|| Self {
+            min,
+            max: min.offset(size.map(|length| length - 1)),
+        })
+    }
+
+    pub fn min(&self) -> GlobalVoxelBlockCoord {
+        self.min
+    }
+
+    pub fn max(&self) -> GlobalVoxelBlockCoord {
+        self.max
+    }
+
+    /// Number of blocks along each axis
+    pub fn size(&self) -> [CoordSystemIntegerSize; 3] {
+        [
+            self.max.x - self.min.x + 1,
+            self.max.y - self.min.y + 1,
+            self.max.z - self.min.z + 1,
+        ]
+    }
+
+    /// Number of blocks in the box
+    pub fn volume(&self) -> usize {
+        self.size().iter().fold(1usize, |volume, length| {
+            volume.saturating_mul(*length as usize)
+        })
+    }
+
+    /// Blocks of the box, x first, then z, then y
+    pub fn iter(&self) -> impl Iterator<Item = GlobalVoxelBlockCoord> + use<> {
+        let (min, max) = (self.min, self.max);
+
+        (min.y..=max.y).flat_map(move |y| {
+            (min.z..=max.z).flat_map(move |z| {
+                (min.x..=max.x).map(move |x| GlobalVoxelBlockCoord::new(x, y, z))
+            })
+        })
+    }
+
+    /// Whether the block is on one of the six faces of the box
+    pub fn is_on_border(&self, coord: GlobalVoxelBlockCoord) -> bool {
+        coord.x == self.min.x
+            || coord.x == self.max.x
+            || coord.y == self.min.y
+            || coord.y == self.max.y
+            || coord.z == self.min.z
+            || coord.z == self.max.z
+    }
+}
+
+/// Blocks an edit applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    Box(Region),
+    /// Only the faces of the box, one block thick
+    HollowBox(Region),
+    /// Blocks whose center is no further than `radius` from `center`
+    Sphere {
+        center: GlobalVoxelBlockCoord,
+        radius: f32,
+    },
+    /// Blocks of the sphere next to a block out of it, so that the shell
+    /// is closed
+    HollowSphere {
+        center: GlobalVoxelBlockCoord,
+        radius: f32,
+    },
+}
+
+impl Shape {
+    /// Smallest box holding every block of the shape
+    pub fn bounds(&self) -> Region {
+        match *self {
+            Shape::Box(region) | Shape::HollowBox(region) => region,
+            Shape::Sphere { center, radius } | Shape::HollowSphere { center, radius } => {
+                sphere_bounds(center, radius)
+            }
+        }
+    }
+
+    pub fn blocks(&self) -> Vec<GlobalVoxelBlockCoord> {
+        match *self {
+            Shape::Box(region) => region.iter().collect(),
+            Shape::HollowBox(region) => region
+                .iter()
+                .filter(|coord| region.is_on_border(*coord))
+                .collect(),
+            Shape::Sphere { center, radius } => sphere_bounds(center, radius)
+                .iter()
+                .filter(|coord| is_in_sphere(center, radius, *coord))
+                .collect(),
+            Shape::HollowSphere { center, radius } => sphere_bounds(center, radius)
+                .iter()
+                .filter(|coord| {
+                    is_in_sphere(center, radius, *coord)
+                        && NEIGHBORS.iter().any(|direction| {
+                            !is_in_sphere(center, radius, coord.offset(*direction))
+                        })
+                })
+                .collect(),
+        }
+    }
+}
+
+const NEIGHBORS: [[CoordSystemIntegerSize; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+fn sphere_bounds(center: GlobalVoxelBlockCoord, radius: f32) -> Region {
+    let reach = radius.max(0.0).floor() as CoordSystemIntegerSize;
+
+    Region::new(center.offset([-reach; 3]), center.offset([reach; 3]))
+}
+
+fn is_in_sphere(center: GlobalVoxelBlockCoord, radius: f32, coord: GlobalVoxelBlockCoord) -> bool {
+    let [x, y, z] = [coord.x - center.x, coord.y - center.y, coord.z - center.z].map(|v| v as f32);
+
+    x * x + y * y + z * z <= radius * radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_are_the_same_from_any_pair_of_corners() {
+        let region = Region::new(
+            GlobalVoxelBlockCoord::new(2, -1, 6),
+            GlobalVoxelBlockCoord::new(-1, 3, 4),
+        );
+
+        assert_eq!(region.min(), GlobalVoxelBlockCoord::new(-1, -1, 4));
+        assert_eq!(region.size(), [4, 5, 3]);
+        assert_eq!(region.iter().count(), 60);
+        assert_eq!(region.volume(), 60);
+        assert_eq!(Region::with_size(region.min(), [4, 5, 3]), Some(region));
+        assert_eq!(Region::with_size(region.min(), [4, 0, 3]), None);
+        assert_eq!(Shape::Box(region).blocks().len(), 60);
+        // Only the blocks at x = 0 or 1, y = 0 to 2 and z = 5 are inside
+        assert_eq!(Shape::HollowBox(region).blocks().len(), 60 - 2 * 3);
+    }
+
+    #[test]
+    fn spheres_are_round_and_hollow_spheres_are_closed() {
+        let center = GlobalVoxelBlockCoord::new(10, 20, -30);
+
+        assert_eq!(
+            Shape::Sphere {
+                center,
+                radius: 0.5
+            }
+            .blocks(),
+            vec![center]
+        );
+        // The center and its 6 neighbours, not the diagonals
+        assert_eq!(
+            Shape::Sphere {
+                center,
+                radius: 1.0
+            }
+            .blocks()
+            .len(),
+            7
+        );
+
+        let sphere = Shape::Sphere {
+            center,
+            radius: 4.5,
+        }
+        .blocks();
+        let shell = Shape::HollowSphere {
+            center,
+            radius: 4.5,
+        }
+        .blocks();
+
+        assert_eq!(
+            Shape::Sphere {
+                center,
+                radius: 4.5
+            }
+            .bounds()
+            .volume(),
+            9 * 9 * 9
+        );
+        assert!(shell.iter().all(|coord| sphere.contains(coord)));
+        assert!(!shell.contains(&center));
+        // Walking straight out of the center always crosses the shell
+        for direction in NEIGHBORS {
+            let crossed =
+                (1..=5).any(|step| shell.contains(&center.offset(direction.map(|v| v * step))));
+
+            assert!(crossed);
+        }
+    }
+}
@@ -1,10 +1,26 @@
 pub type CoordSystemIntegerSize = i32;
 
 pub const CHUNK_SIZE: CoordSystemIntegerSize = 16;
+
+/// With the `cubic_chunks` feature, chunks are cubes stacked vertically
+/// without limit. Otherwise, they are columns holding the whole height of the
+/// world and `ChunkCoord::y` is always 0.
+pub const CUBIC_CHUNKS: bool = cfg!(feature = "cubic_chunks");
+
+#[cfg(not(feature = "cubic_chunks"))]
 pub const CHUNK_HEIGHT: CoordSystemIntegerSize = 80;
+#[cfg(feature = "cubic_chunks")]
+pub const CHUNK_HEIGHT: CoordSystemIntegerSize = CHUNK_SIZE;
 
 pub const MAX_OFFSET: CoordSystemIntegerSize = CHUNK_SIZE * CHUNK_HEIGHT * CHUNK_SIZE;
 
+/// Chunks are split vertically into sections of this height, each stored and
+/// meshed on its own
+pub const SECTION_HEIGHT: CoordSystemIntegerSize = 16;
+pub const SECTION_COUNT: usize = (CHUNK_HEIGHT / SECTION_HEIGHT) as usize;
+pub const SECTION_VOLUME: CoordSystemIntegerSize = CHUNK_SIZE * SECTION_HEIGHT * CHUNK_SIZE;
+
+use crate::chunk::mesher::Mesher;
 use crate::chunk::noise::Noise;
 use bevy::prelude::*;
 use serde_derive::Deserialize;
@@ -18,6 +34,18 @@ pub struct GameSettings {
     pub world: World,
     pub logs: Logs,
     pub procedural: Procedural,
+    pub render: Render,
+    pub physics: Physics,
+    pub horizon: Horizon,
+}
+
+impl GameSettings {
+    /// Brings the values that would break the game back in range, with a
+    /// warning
+    pub fn validate(&mut self) {
+        self.world.validate();
+        self.horizon.validate();
+    }
 }
 
 #[derive(Resource, Deref, DerefMut)]
@@ -34,6 +62,54 @@ pub struct GameSettingResource {
 pub struct World {
     pub world_dimension: i32,
     pub preload_extra_distance: i32,
+    /// Number of chunks above and below the player, only used with cubic chunks
+    pub vertical_dimension: i32,
+    /// Chunks further than this from the player are hidden, then despawned
+    /// once they have been out of range for `unload_grace_period` seconds
+    pub unload_distance: i32,
+    pub unload_grace_period: f32,
+    /// Number of chunks generated at the same time
+    pub max_generation_tasks: usize,
+    /// Rate of the `WorldTick` schedule, which simulates the world
+    pub ticks_per_second: f64,
+    /// Blocks picked at random in each section at every world tick
+    pub random_ticks_per_section: usize,
+}
+
+impl World {
+    /// Number of chunks generated above and below the player
+    pub fn vertical_generation_radius(&self) -> i32 {
+        match CUBIC_CHUNKS {
+            true => self.vertical_dimension + self.preload_extra_distance,
+            false => 0,
+        }
+    }
+
+    /// Number of chunks generated around the player
+    pub fn generation_radius(&self) -> i32 {
+        self.world_dimension + self.preload_extra_distance
+    }
+
+    /// Number of chunks kept above and below the player
+    pub fn vertical_unload_radius(&self) -> i32 {
+        match CUBIC_CHUNKS {
+            true => self.vertical_dimension + self.unload_distance - self.world_dimension,
+            false => 0,
+        }
+    }
+
+    /// Chunks unloaded closer than the generation radius would be generated
+    /// again right away
+    fn validate(&mut self) {
+        if self.unload_distance < self.generation_radius() {
+            warn!(
+                "The unload distance {} is below the generation radius, {} is used instead",
+                self.unload_distance,
+                self.generation_radius()
+            );
+            self.unload_distance = self.generation_radius();
+        }
+    }
 }
 
 impl Default for World {
@@ -41,6 +117,12 @@ impl Default for World {
         Self {
             world_dimension: 4,
             preload_extra_distance: 1,
+            vertical_dimension: 2,
+            unload_distance: 7,
+            unload_grace_period: 10.0,
+            max_generation_tasks: 8,
+            ticks_per_second: 20.0,
+            random_ticks_per_section: 3,
         }
     }
 }
@@ -55,4 +137,109 @@ pub struct Logs {
 pub struct Procedural {
     pub base_noise: Noise,
     pub block_noise: Noise,
+    /// Empty blocks at or below this height are filled with water. A
+    /// negative value leaves the basins dry.
+    pub sea_level: i32,
+    /// Models placed on the terrain, none by default
+    #[serde(default)]
+    pub structures: Vec<Structure>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Structure {
+    /// `.vox` model, relative to the assets folder
+    pub model: String,
+    /// Chance for a chunk column to get one, from 0 to 1
+    pub chance: f64,
+}
+
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Render {
+    pub mesher: Mesher,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Physics {
+    /// Chunks this many chunks away from a moving body get a collider
+    pub collider_distance: i32,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            collider_distance: 1,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Horizon {
+    /// Chunks this many chunks away from the player are drawn as coarse
+    /// heightmap tiles until they are generated. 0 disables the horizon.
+    pub distance: i32,
+    /// Blocks between two height samples of a tile, a divisor of `CHUNK_SIZE`
+    pub step: i32,
+    /// Number of tiles built at the same time
+    pub max_tile_tasks: usize,
+}
+
+impl Horizon {
+    /// The tiles only join when `step` divides `CHUNK_SIZE`, the largest
+    /// divisor below it is used otherwise
+    fn validate(&mut self) {
+        let step = (1..=self.step.clamp(1, CHUNK_SIZE))
+            .rev()
+            .find(|step| CHUNK_SIZE % step == 0)
+            .unwrap_or(1);
+
+        if step != self.step {
+            warn!(
+                "The horizon step {} does not divide the chunk size, {} is used instead",
+                self.step, step
+            );
+            self.step = step;
+        }
+    }
+}
+
+impl Default for Horizon {
+    fn default() -> Self {
+        Self {
+            distance: 24,
+            step: 4,
+            max_tile_tasks: 8,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// The settings of the game, as written in the assets
+    pub fn game_settings() -> GameSettings {
+        toml::from_str(include_str!("../assets/game.toml")).unwrap()
+    }
+
+    #[test]
+    fn settings_are_brought_back_in_range() {
+        let mut settings = game_settings();
+        settings.validate();
+        assert_eq!(settings.horizon.step, 4);
+        assert_eq!(settings.world.unload_distance, 7);
+
+        settings.world.unload_distance = 2;
+        settings.validate();
+        assert_eq!(
+            settings.world.unload_distance,
+            settings.world.generation_radius()
+        );
+
+        for (step, valid) in [(0, 1), (-3, 1), (3, 2), (6, 4), (16, 16), (40, 16)] {
+            settings.horizon.step = step;
+            settings.validate();
+
+            assert_eq!(settings.horizon.step, valid);
+        }
+    }
 }
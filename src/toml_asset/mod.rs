@@ -131,7 +131,8 @@ impl AssetLoader for TomlAssetLoader {
 
         let text = std::str::from_utf8(&bytes)?;
 
-        let config: GameSettings = toml::from_str(text)?;
+        let mut config: GameSettings = toml::from_str(text)?;
+        config.validate();
 
         Ok(config)
     }
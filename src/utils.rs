@@ -5,7 +5,10 @@ use bevy::render::mesh::{Indices, PrimitiveTopology};
 pub type Vertex = [f32; 3];
 type Normal = [f32; 3];
 pub type UV = [f32; 2];
-pub type VertexBuffer = Vec<(Vertex, Normal, UV)>;
+pub type Color = [f32; 4];
+/// Position, normal, UV inside the block tile, UV of the tile in the atlas
+/// and vertex colour
+pub type VertexBuffer = Vec<(Vertex, Normal, UV, UV, Color)>;
 
 pub fn render_mesh(indices: &Indices, vertices: &VertexBuffer) -> Mesh {
     let mut mesh = Mesh::new(
@@ -13,13 +16,17 @@ pub fn render_mesh(indices: &Indices, vertices: &VertexBuffer) -> Mesh {
         RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
     );
 
-    let positions: Vec<_> = vertices.iter().map(|(p, _, _)| *p).collect();
-    let normals: Vec<_> = vertices.iter().map(|(_, n, _)| *n).collect();
-    let uvs: Vec<_> = vertices.iter().map(|(_, _, uv)| *uv).collect();
+    let positions: Vec<_> = vertices.iter().map(|(p, _, _, _, _)| *p).collect();
+    let normals: Vec<_> = vertices.iter().map(|(_, n, _, _, _)| *n).collect();
+    let uvs: Vec<_> = vertices.iter().map(|(_, _, uv, _, _)| *uv).collect();
+    let tiles: Vec<_> = vertices.iter().map(|(_, _, _, tile, _)| *tile).collect();
+    let colors: Vec<_> = vertices.iter().map(|(_, _, _, _, c)| *c).collect();
 
     mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
     mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
     mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_1, tiles);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
 
     mesh.insert_indices(indices.clone());
 
@@ -0,0 +1,62 @@
+use crate::settings::GameSettingResource;
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::prelude::*;
+
+/// Schedule simulating the world at a fixed rate, `ticks_per_second` in the
+/// `[world]` settings, whatever the frame rate. It runs from `FixedUpdate`,
+/// before `Update`, so the blocks it edits are lit and meshed in the same
+/// frame. After a long frame, `Time<Virtual>` caps the time to catch up so
+/// that the game does not freeze.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorldTick;
+
+/// Number of world ticks run since the game started
+#[derive(Resource, Debug, Default)]
+pub struct WorldTicks {
+    pub count: u64,
+}
+
+pub struct WorldTickPlugin;
+
+impl Plugin for WorldTickPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_schedule(WorldTick)
+            .init_resource::<WorldTicks>()
+            .add_systems(
+                PreUpdate,
+                set_tick_rate.run_if(resource_exists_and_changed::<GameSettingResource>),
+            )
+            .add_systems(FixedUpdate, run_world_tick);
+    }
+}
+
+/// Makes the fixed timestep follow `ticks_per_second`. A rate of 0 or less
+/// pauses the world instead.
+fn set_tick_rate(game_setting_resource: Res<GameSettingResource>, mut time: ResMut<Time<Fixed>>) {
+    let ticks_per_second = game_setting_resource.settings.world.ticks_per_second;
+
+    if ticks_per_second > 0.0 {
+        time.set_timestep_hz(ticks_per_second);
+    }
+}
+
+/// Runs a world tick at every fixed timestep
+fn run_world_tick(world: &mut World) {
+    let ticks_per_second = world
+        .resource::<GameSettingResource>()
+        .settings
+        .world
+        .ticks_per_second;
+
+    if ticks_per_second <= 0.0 {
+        return;
+    }
+
+    world.run_schedule(WorldTick);
+    world.resource_mut::<WorldTicks>().count += 1;
+}
+
+/// Run condition for the systems of the world tick that run every `ticks` ticks
+pub fn every_ticks(ticks: u64) -> impl FnMut(Res<WorldTicks>) -> bool + Clone {
+    move |world_ticks: Res<WorldTicks>| world_ticks.count.is_multiple_of(ticks)
+}